@@ -14,6 +14,19 @@ create table users (
   UNIQUE(username)
 );
 
+ALTER TABLE users ADD COLUMN auth_source varchar(32) NOT NULL DEFAULT 'local';
+ALTER TABLE users ADD COLUMN totp_secret varchar(255);
+ALTER TABLE users ADD COLUMN totp_enabled boolean NOT NULL DEFAULT false;
+ALTER TABLE users ADD COLUMN totp_last_step bigint;
+ALTER TABLE users ADD COLUMN role varchar(16) NOT NULL DEFAULT 'member';
+UPDATE users SET role = 'admin' WHERE id = (SELECT min(id) FROM users);
+ALTER TABLE users ADD COLUMN deactivated boolean NOT NULL DEFAULT false;
+ALTER TABLE users ADD COLUMN display_name varchar(255) NOT NULL DEFAULT '';
+ALTER TABLE users ADD COLUMN bio text NOT NULL DEFAULT '';
+ALTER TABLE users ADD COLUMN title varchar(255) NOT NULL DEFAULT '';
+ALTER TABLE users ADD COLUMN team varchar(255) NOT NULL DEFAULT '';
+ALTER TABLE users ADD COLUMN links text NOT NULL DEFAULT '';
+
 create table posts (
   id            serial primary key,
   kind          varchar(255) NOT NULL,
@@ -26,6 +39,8 @@ create table posts (
 );
 
 ALTER TABLE posts ADD COLUMN shared boolean DEFAULT false;
+ALTER TABLE posts ADD COLUMN visibility varchar(16) NOT NULL DEFAULT 'public';
+ALTER TABLE posts ADD COLUMN share_token varchar(64);
 
 create table post_comments (
   id            serial primary key,
@@ -68,6 +83,10 @@ create table gists (
   created       timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP,
   updated       timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP
 );
+ALTER TABLE gists ADD COLUMN forked_from integer REFERENCES gists (id) ON DELETE SET NULL;
+ALTER TABLE gists ADD COLUMN visibility varchar(16) NOT NULL DEFAULT 'public';
+ALTER TABLE gists ADD COLUMN share_token varchar(64);
+CREATE INDEX gists_forked_from_idx ON gists (forked_from);
 
 create table gist_comments (
   id            serial primary key,
@@ -85,6 +104,7 @@ create table tweets (
   created       timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP,
   updated       timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP
 );
+ALTER TABLE tweets ADD COLUMN edited boolean NOT NULL DEFAULT false;
 
 create table tweet_comments (
   id            serial primary key,
@@ -94,6 +114,8 @@ create table tweet_comments (
   created       timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP,
   updated       timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP
 );
+ALTER TABLE tweet_comments ADD COLUMN edited boolean NOT NULL DEFAULT false;
+ALTER TABLE tweet_comments ADD COLUMN parent_id integer REFERENCES tweet_comments (id) ON DELETE SET NULL;
 
 create table notifications (
   id            serial primary key,
@@ -105,6 +127,7 @@ create table notifications (
   created       timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP,
   updated       timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP
 );
+ALTER TABLE notifications ADD COLUMN kind varchar(32) NOT NULL DEFAULT 'comment';
 
 create table preferences (
   id            serial primary key,
@@ -123,3 +146,200 @@ create table pinneds (
   created       timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP,
   updated       timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP
 );
+
+create table recovery_codes (
+  id            serial primary key,
+  user_id       serial REFERENCES users (id) ON DELETE CASCADE NOT NULL,
+  code_hash     varchar(255) NOT NULL,
+  used          boolean DEFAULT false,
+  created       timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  updated       timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+create table team_settings (
+  id            serial primary key,
+  name          varchar(255) NOT NULL,
+  value         text,
+  created       timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  updated       timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE(name)
+);
+
+create table sessions (
+  id            serial primary key,
+  session_hash  varchar(255) NOT NULL,
+  user_id       integer REFERENCES users (id) ON DELETE CASCADE,
+  data          text NOT NULL DEFAULT '{}',
+  user_agent    varchar(1024) NOT NULL DEFAULT '',
+  ip            varchar(64) NOT NULL DEFAULT '',
+  created       timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  last_seen     timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE(session_hash)
+);
+CREATE INDEX sessions_user_id_idx ON sessions (user_id);
+
+create table invites (
+  id            serial primary key,
+  user_id       serial REFERENCES users (id) ON DELETE CASCADE NOT NULL,
+  token_hash    varchar(255) NOT NULL,
+  max_uses      integer NOT NULL DEFAULT 1,
+  uses          integer NOT NULL DEFAULT 0,
+  expires       timestamp NOT NULL,
+  created       timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  updated       timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE(token_hash)
+);
+
+create table password_resets (
+  id            serial primary key,
+  user_id       serial REFERENCES users (id) ON DELETE CASCADE NOT NULL,
+  token_hash    varchar(255) NOT NULL,
+  ip            varchar(64) NOT NULL DEFAULT '',
+  used          boolean NOT NULL DEFAULT false,
+  expires       timestamp NOT NULL,
+  created       timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  updated       timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE(token_hash)
+);
+
+create table login_failures (
+  id            serial primary key,
+  username      varchar(255) NOT NULL,
+  ip            varchar(64) NOT NULL DEFAULT '',
+  created       timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX login_failures_username_idx ON login_failures (username, created);
+
+create table follows (
+  id            serial primary key,
+  user_id       serial REFERENCES users (id) ON DELETE CASCADE NOT NULL,
+  followee_id   serial REFERENCES users (id) ON DELETE CASCADE NOT NULL,
+  created       timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE(user_id, followee_id)
+);
+CREATE INDEX follows_followee_id_idx ON follows (followee_id);
+
+create table tag_follows (
+  id            serial primary key,
+  user_id       serial REFERENCES users (id) ON DELETE CASCADE NOT NULL,
+  tag_id        serial REFERENCES tags (id) ON DELETE CASCADE NOT NULL,
+  created       timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE(user_id, tag_id)
+);
+
+create table audit_events (
+  id            serial primary key,
+  user_id       integer REFERENCES users (id),
+  action        varchar(64) NOT NULL,
+  target        text NOT NULL DEFAULT '',
+  detail        text NOT NULL DEFAULT '',
+  ip            varchar(64) NOT NULL DEFAULT '',
+  created       timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX audit_events_action_idx ON audit_events (action, created);
+CREATE INDEX audit_events_user_id_idx ON audit_events (user_id, created);
+
+create table gist_files (
+  id            serial primary key,
+  gist_id       serial REFERENCES gists (id) ON DELETE CASCADE NOT NULL,
+  filename      varchar(255) NOT NULL,
+  code          text NOT NULL,
+  position      integer NOT NULL DEFAULT 0,
+  created       timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  updated       timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX gist_files_gist_id_idx ON gist_files (gist_id, position);
+-- Single-file gists become gists with one file.
+INSERT INTO gist_files (gist_id, filename, code) SELECT id, coalesce(filename, ''), code FROM gists;
+ALTER TABLE gists DROP COLUMN filename;
+ALTER TABLE gists DROP COLUMN code;
+ALTER TABLE gist_files ADD COLUMN language varchar(64) NOT NULL DEFAULT '';
+
+create table gist_revisions (
+  id            serial primary key,
+  gist_id       serial REFERENCES gists (id) ON DELETE CASCADE NOT NULL,
+  user_id       serial REFERENCES users (id) ON DELETE CASCADE NOT NULL,
+  description   varchar(255) NOT NULL DEFAULT '',
+  created       timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX gist_revisions_gist_id_idx ON gist_revisions (gist_id);
+
+create table gist_revision_files (
+  id            serial primary key,
+  revision_id   serial REFERENCES gist_revisions (id) ON DELETE CASCADE NOT NULL,
+  filename      varchar(255) NOT NULL,
+  code          text NOT NULL,
+  position      integer NOT NULL DEFAULT 0
+);
+CREATE INDEX gist_revision_files_revision_id_idx ON gist_revision_files (revision_id);
+-- Existing gists start with their current state as the first revision.
+INSERT INTO gist_revisions (gist_id, user_id, description, created)
+  SELECT id, user_id, coalesce(description, ''), updated FROM gists;
+INSERT INTO gist_revision_files (revision_id, filename, code, position)
+  SELECT r.id, f.filename, f.code, f.position FROM gist_revisions as r JOIN gist_files as f ON f.gist_id = r.gist_id;
+
+create table gist_stars (
+  id            serial primary key,
+  user_id       serial REFERENCES users (id) ON DELETE CASCADE NOT NULL,
+  gist_id       serial REFERENCES gists (id) ON DELETE CASCADE NOT NULL,
+  created       timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE (user_id, gist_id)
+);
+
+create table gist_taggings (
+  id            serial primary key,
+  tag_id        serial REFERENCES tags (id) ON DELETE CASCADE NOT NULL,
+  gist_id       serial REFERENCES gists (id) ON DELETE CASCADE NOT NULL,
+  created       timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX gist_taggings_gist_id_idx ON gist_taggings (gist_id);
+
+create table api_tokens (
+  id            serial primary key,
+  user_id       serial REFERENCES users (id) ON DELETE CASCADE NOT NULL,
+  name          varchar(255) NOT NULL DEFAULT '',
+  token_hash    varchar(255) NOT NULL,
+  created       timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  last_used     timestamp,
+  UNIQUE(token_hash)
+);
+
+create table tweet_taggings (
+  id            serial primary key,
+  tag_id        serial REFERENCES tags (id) ON DELETE CASCADE NOT NULL,
+  tweet_id      serial REFERENCES tweets (id) ON DELETE CASCADE NOT NULL,
+  created       timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX tweet_taggings_tweet_id_idx ON tweet_taggings (tweet_id);
+
+create table tweet_attachments (
+  id            serial primary key,
+  tweet_id      serial REFERENCES tweets (id) ON DELETE CASCADE NOT NULL,
+  path          varchar(255) NOT NULL,
+  created       timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE(path)
+);
+CREATE INDEX tweet_attachments_tweet_id_idx ON tweet_attachments (tweet_id);
+
+create table signup_confirmations (
+  id            serial primary key,
+  username      varchar(255) NOT NULL,
+  password      varchar(255) NOT NULL,
+  email         varchar(255) NOT NULL,
+  invite_id     integer REFERENCES invites (id) ON DELETE SET NULL,
+  token_hash    varchar(255) NOT NULL,
+  ip            varchar(64) NOT NULL DEFAULT '',
+  used          boolean NOT NULL DEFAULT false,
+  expires       timestamp NOT NULL,
+  created       timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  updated       timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE(token_hash)
+);
+
+create table uploads (
+  id            serial primary key,
+  user_id       serial REFERENCES users (id) ON DELETE CASCADE NOT NULL,
+  path          varchar(255) NOT NULL,
+  created       timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE(path)
+);
@@ -1,5 +1,9 @@
 $(document).ready(function(){
 
+  $.ajaxSetup({
+    headers: { 'X-CSRF-Token': $('meta[name="csrf-token"]').attr('content') }
+  });
+
   $('textarea', '.CodeMirror').textcomplete([
     {
       match: /\B:([\-+\w]*)$/,
@@ -93,11 +97,48 @@ $(document).ready(function(){
     $(element).show();
   });
 
+  // Hashtags follow the same rules as `models::tag::hashtags` on the server.
+  var hashtag = /(^|[\s(\[「（])#([^\s#.,!?;:()\[\]{}<>"'`\/\\*~|&+=%、。，．！？：；「」『』（）【】]+)/g;
+  function linkHashtags(element) {
+    $(element).contents().each(function (i, node) {
+      if (node.nodeType === 1 && !$(node).is('a, code, pre')) {
+        linkHashtags(node);
+        return;
+      }
+      if (node.nodeType !== 3) {
+        return;
+      }
+      var text = node.nodeValue;
+      var fragment = document.createDocumentFragment();
+      var last = 0;
+      var m;
+      hashtag.lastIndex = 0;
+      while ((m = hashtag.exec(text)) !== null) {
+        if (/^[0-9]+$/.test(m[2])) {
+          continue;
+        }
+        var start = m.index + m[1].length;
+        fragment.appendChild(document.createTextNode(text.slice(last, start)));
+        var link = document.createElement('a');
+        link.href = '/tweet/list?tag=' + encodeURIComponent(m[2]);
+        link.textContent = '#' + m[2];
+        link.addEventListener('click', function (e) { e.stopPropagation(); });
+        fragment.appendChild(link);
+        last = start + 1 + m[2].length;
+      }
+      if (last > 0) {
+        fragment.appendChild(document.createTextNode(text.slice(last)));
+        node.parentNode.replaceChild(fragment, node);
+      }
+    });
+  }
+
   $(".tweet-body").each(function (index, element) {
     // var htmlText = $(element).html();
     // htmlText = htmlText.replace(/\n+$/g, '');
     // htmlText = htmlText.replace(/\r?\n/g, '<br>');
     // $(element).html(htmlText);
+    linkHashtags(element);
     $(element).show();
   });
 
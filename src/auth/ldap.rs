@@ -0,0 +1,146 @@
+use ldap3::{LdapConn, Scope, SearchEntry};
+
+use db;
+use env::CONFIG;
+use models;
+
+use auth::Backend;
+
+pub struct Ldap;
+
+// RFC 4515 filter value escaping
+fn escape(value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\5c"),
+            '*' => escaped.push_str("\\2a"),
+            '(' => escaped.push_str("\\28"),
+            ')' => escaped.push_str("\\29"),
+            '\0' => escaped.push_str("\\00"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn build_filter(user_filter: &str, group_filter: &str, username: &str) -> String {
+    let filter = user_filter.replace("{username}", &escape(username));
+    if group_filter == "" {
+        filter
+    } else {
+        format!("(&{}{})", filter, group_filter)
+    }
+}
+
+fn user_filter(username: &str) -> String {
+    build_filter(&CONFIG.team_ldap_user_filter, &CONFIG.team_ldap_group_filter, username)
+}
+
+struct Entry {
+    dn: String,
+    email: String,
+}
+
+fn find_user(username: &str) -> Result<Option<Entry>, String> {
+    let ldap = try!(LdapConn::new(&CONFIG.team_ldap_url).map_err(|e| e.to_string()));
+    if CONFIG.team_ldap_bind_dn != "" {
+        try!(ldap.simple_bind(&CONFIG.team_ldap_bind_dn, &CONFIG.team_ldap_bind_password)
+            .and_then(|r| r.success())
+            .map_err(|e| format!("service bind: {}", e)));
+    }
+    let email_attr = CONFIG.team_ldap_email_attr.as_str();
+    let (entries, _) = try!(ldap.search(&CONFIG.team_ldap_base_dn, Scope::Subtree, &user_filter(username), vec![email_attr])
+        .and_then(|r| r.success())
+        .map_err(|e| format!("search: {}", e)));
+    let _ = ldap.unbind();
+    if entries.len() != 1 {
+        return Ok(None);
+    }
+    let entry = SearchEntry::construct(entries.into_iter().next().unwrap());
+    let email = entry.attrs.get(email_attr)
+        .and_then(|v| v.get(0))
+        .map(|v| v.to_string())
+        .unwrap_or("".to_string());
+    Ok(Some(Entry { dn: entry.dn, email: email }))
+}
+
+fn bind_as(dn: &str, password: &str) -> Result<bool, String> {
+    let ldap = try!(LdapConn::new(&CONFIG.team_ldap_url).map_err(|e| e.to_string()));
+    let result = try!(ldap.simple_bind(dn, password).map_err(|e| e.to_string()));
+    let _ = ldap.unbind();
+    Ok(result.rc == 0)
+}
+
+impl Backend for Ldap {
+    fn name(&self) -> &'static str {
+        "ldap"
+    }
+
+    fn authenticate(&self, conn: &db::PostgresConnection, username: &str, password: &str) -> Result<Option<models::user::User>, String> {
+        if CONFIG.team_ldap_url == "" {
+            return Err("TEAM_LDAP_URL is not set".to_string());
+        }
+        let entry = match try!(find_user(username)) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        if !try!(bind_as(&entry.dn, password)) {
+            return Ok(None);
+        }
+
+        // First successful LDAP login provisions the account.
+        let user = try!(models::user::get_by_username(conn, username).map_err(|e| format!("{}", e)));
+        if user.username != "" {
+            let source = try!(models::user::get_auth_source(conn, &user.id).map_err(|e| format!("{}", e)));
+            if source != "ldap" {
+                return Err(format!("{} is a {} account, refusing LDAP login", username, source));
+            }
+            return Ok(Some(user));
+        }
+        let user_id = try!(models::user::create_with_auth_source(conn, &username.to_string(), &entry.email, "ldap")
+            .map_err(|e| format!("{}", e)));
+        models::user::get_by_id(conn, &user_id).map(|u| Some(u)).map_err(|e| format!("{}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_filter, escape};
+
+    #[test]
+    fn plain_values_are_unchanged() {
+        assert_eq!(escape("alice.smith-01"), "alice.smith-01");
+    }
+
+    #[test]
+    fn filter_specials_are_escaped() {
+        assert_eq!(escape("*"), "\\2a");
+        assert_eq!(escape("("), "\\28");
+        assert_eq!(escape(")"), "\\29");
+        assert_eq!(escape("\\"), "\\5c");
+        assert_eq!(escape("\0"), "\\00");
+    }
+
+    #[test]
+    fn backslash_is_escaped() {
+        assert_eq!(escape("a\\2a"), "a\\5c2a");
+    }
+
+    #[test]
+    fn injection_stays_inside_the_value() {
+        assert_eq!(build_filter("(uid={username})", "", "*)(uid=*"), "(uid=\\2a\\29\\28uid=\\2a)");
+        assert_eq!(build_filter("(uid={username})", "", "admin)(|(a=*"), "(uid=admin\\29\\28|\\28a=\\2a)");
+    }
+
+    #[test]
+    fn group_filter_is_anded() {
+        assert_eq!(build_filter("(uid={username})", "(memberOf=cn=team,dc=example,dc=org)", "bob"),
+                   "(&(uid=bob)(memberOf=cn=team,dc=example,dc=org))");
+    }
+
+    #[test]
+    fn every_placeholder_is_replaced() {
+        assert_eq!(build_filter("(|(uid={username})(mail={username}))", "", "a*"), "(|(uid=a\\2a)(mail=a\\2a))");
+    }
+}
@@ -0,0 +1,27 @@
+use db;
+use helper;
+use models;
+
+use auth::Backend;
+
+pub struct Local;
+
+impl Backend for Local {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    fn authenticate(&self, conn: &db::PostgresConnection, username: &str, password: &str) -> Result<Option<models::user::User>, String> {
+        let password = helper::encrypt_password(password.to_string());
+        match models::user::get_by_username_password(conn, &username.to_string(), &password) {
+            Ok(user) => {
+                if user.username == "" {
+                    Ok(None)
+                } else {
+                    Ok(Some(user))
+                }
+            }
+            Err(e) => Err(format!("{}", e)),
+        }
+    }
+}
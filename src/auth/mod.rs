@@ -0,0 +1,45 @@
+use db;
+use env::CONFIG;
+use models;
+
+pub mod local;
+pub mod ldap;
+
+/// A source of truth for username/password sign-in.
+///
+/// `authenticate` returns `Ok(None)` when the credentials are unknown to the
+/// backend, so the next configured backend gets a chance to check them.
+pub trait Backend {
+    fn name(&self) -> &'static str;
+    fn authenticate(&self, conn: &db::PostgresConnection, username: &str, password: &str) -> Result<Option<models::user::User>, String>;
+}
+
+fn backends() -> Vec<Box<Backend>> {
+    let mut backends: Vec<Box<Backend>> = Vec::new();
+    for name in CONFIG.team_auth_backends.split(",") {
+        match name.trim() {
+            "local" => backends.push(Box::new(local::Local)),
+            "ldap" => backends.push(Box::new(ldap::Ldap)),
+            "" => {}
+            other => error!("unknown auth backend: {}", other),
+        }
+    }
+    backends
+}
+
+pub fn authenticate(conn: &db::PostgresConnection, username: &str, password: &str) -> Result<Option<models::user::User>, String> {
+    if username == "" || password == "" {
+        return Ok(None);
+    }
+    for backend in backends() {
+        match backend.authenticate(conn, username, password) {
+            Ok(Some(user)) => {
+                debug!("authenticated {} with {}", username, backend.name());
+                return Ok(Some(user));
+            }
+            Ok(None) => {}
+            Err(e) => error!("{} backend: {}", backend.name(), e),
+        }
+    }
+    Ok(None)
+}
@@ -0,0 +1,279 @@
+//! `team-gist`: create, list, show and update gists from the command line.
+//!
+//! Talks to the JSON API under `/api/gists` with a token from Settings > API tokens.
+//! `TEAM_GIST_URL` is the site, e.g. `https://team.example.com`, and
+//! `TEAM_GIST_TOKEN` is the token.
+
+extern crate reqwest;
+extern crate serde;
+extern crate serde_json;
+#[macro_use]
+extern crate serde_derive;
+
+use std::env;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::process;
+
+use reqwest::header::{Authorization, Bearer};
+
+const USAGE: &str = "usage:
+  team-gist create [-d DESCRIPTION] [-t TAG,TAG] [--unlisted | --private] [-f NAME] [FILE...]
+  team-gist list [--page N]
+  team-gist show ID [FILENAME]
+  team-gist update ID [-d DESCRIPTION] [-f NAME] [FILE...]
+
+Without FILE, create and update read one file from standard input, named by -f.
+update replaces the files with the same name and adds the others.
+
+environment:
+  TEAM_GIST_URL    the site, e.g. https://team.example.com
+  TEAM_GIST_TOKEN  an API token from Settings > API tokens";
+
+#[derive(Deserialize)]
+struct Gist {
+    id: i32,
+    description: String,
+    visibility: String,
+    tags: Vec<String>,
+    files: Vec<GistFile>,
+    html_url: String,
+}
+
+#[derive(Deserialize)]
+struct GistFile {
+    filename: String,
+    language_override: String,
+    content: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ApiError {
+    error: String,
+}
+
+#[derive(Serialize)]
+struct FileParam {
+    filename: String,
+    content: String,
+    language: String,
+}
+
+#[derive(Serialize)]
+struct GistParam {
+    description: Option<String>,
+    visibility: Option<String>,
+    tags: Option<Vec<String>>,
+    files: Vec<FileParam>,
+}
+
+struct Api {
+    url: String,
+    token: String,
+    client: reqwest::Client,
+}
+
+impl Api {
+    fn from_env() -> Result<Api, String> {
+        let url = try!(env::var("TEAM_GIST_URL").map_err(|_| "TEAM_GIST_URL is not set".to_string()));
+        let token = try!(env::var("TEAM_GIST_TOKEN").map_err(|_| "TEAM_GIST_TOKEN is not set".to_string()));
+        let client = try!(reqwest::Client::new().map_err(|e| e.to_string()));
+        Ok(Api {
+            url: url.trim_right_matches('/').to_string(),
+            token: token,
+            client: client,
+        })
+    }
+
+    fn send(&self, builder: reqwest::RequestBuilder) -> Result<reqwest::Response, String> {
+        let mut res = try!(builder
+            .header(Authorization(Bearer { token: self.token.clone() }))
+            .send()
+            .map_err(|e| e.to_string()));
+        if res.status().is_success() {
+            return Ok(res);
+        }
+        match res.json::<ApiError>() {
+            Ok(e) => Err(e.error),
+            Err(_) => Err(format!("the server returned {}", res.status())),
+        }
+    }
+
+    fn get_gist(&self, id: &str) -> Result<Gist, String> {
+        let mut res = try!(self.send(self.client.get(&format!("{}/api/gists/{}", self.url, id))));
+        res.json::<Gist>().map_err(|e| e.to_string())
+    }
+}
+
+// Options shared by the subcommands, plus whatever is left over as arguments.
+#[derive(Default)]
+struct Options {
+    description: Option<String>,
+    visibility: Option<String>,
+    tags: Option<Vec<String>>,
+    filename: Option<String>,
+    page: Option<String>,
+    args: Vec<String>,
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let mut value = |name: &str| iter.next().cloned().ok_or(format!("{} needs a value", name));
+        match arg.as_str() {
+            "-d" | "--description" => options.description = Some(try!(value(arg))),
+            "-t" | "--tags" => {
+                let tags = try!(value(arg));
+                options.tags = Some(tags.split(",").map(|t| t.trim().to_string()).filter(|t| t != "").collect());
+            }
+            "-f" | "--filename" => options.filename = Some(try!(value(arg))),
+            "--page" => options.page = Some(try!(value(arg))),
+            "--public" => options.visibility = Some("public".to_string()),
+            "--unlisted" => options.visibility = Some("unlisted".to_string()),
+            "--private" => options.visibility = Some("private".to_string()),
+            _ if arg.starts_with("-") => return Err(format!("unknown option {}", arg)),
+            _ => options.args.push(arg.to_string()),
+        }
+    }
+    Ok(options)
+}
+
+// Reads the named files, or standard input when there are none.
+fn read_files(paths: &[String], stdin_name: &Option<String>) -> Result<Vec<FileParam>, String> {
+    let mut files: Vec<FileParam> = Vec::new();
+    if paths.is_empty() {
+        let mut content = String::new();
+        try!(io::stdin().read_to_string(&mut content).map_err(|e| e.to_string()));
+        files.push(FileParam {
+            filename: stdin_name.clone().unwrap_or("".to_string()),
+            content: content,
+            language: "".to_string(),
+        });
+        return Ok(files);
+    }
+    for path in paths {
+        let mut content = String::new();
+        let mut file = try!(File::open(path).map_err(|e| format!("{}: {}", path, e)));
+        try!(file.read_to_string(&mut content).map_err(|e| format!("{}: {}", path, e)));
+        let filename = match Path::new(path).file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => return Err(format!("{}: not a file", path)),
+        };
+        files.push(FileParam {
+            filename: filename,
+            content: content,
+            language: "".to_string(),
+        });
+    }
+    Ok(files)
+}
+
+fn create(api: &Api, options: Options) -> Result<(), String> {
+    let params = GistParam {
+        files: try!(read_files(&options.args, &options.filename)),
+        description: options.description,
+        visibility: options.visibility,
+        tags: options.tags,
+    };
+    let mut res = try!(api.send(api.client.post(&format!("{}/api/gists", api.url)).json(&params)));
+    let gist = try!(res.json::<Gist>().map_err(|e| e.to_string()));
+    println!("{}", gist.html_url);
+    Ok(())
+}
+
+fn list(api: &Api, options: Options) -> Result<(), String> {
+    let page = options.page.unwrap_or("1".to_string());
+    let mut res = try!(api.send(api.client.get(&format!("{}/api/gists?page={}", api.url, page))));
+    let gists = try!(res.json::<Vec<Gist>>().map_err(|e| e.to_string()));
+    for gist in gists {
+        let filenames: Vec<&str> = gist.files.iter().map(|f| f.filename.as_str()).collect();
+        let tags = if gist.tags.is_empty() { "".to_string() } else { format!(" [{}]", gist.tags.join(", ")) };
+        println!("{}\t{}\t{}\t{}{}", gist.id, gist.visibility, filenames.join(", "), gist.description, tags);
+    }
+    Ok(())
+}
+
+fn show(api: &Api, options: Options) -> Result<(), String> {
+    let id = try!(options.args.get(0).ok_or("show needs a gist id".to_string()));
+    if let Some(filename) = options.args.get(1) {
+        let mut url = try!(reqwest::Url::parse(&format!("{}/api/gists/{}/raw", api.url, id)).map_err(|e| e.to_string()));
+        try!(url.path_segments_mut().map_err(|_| "TEAM_GIST_URL is not a web address".to_string())).push(filename);
+        let mut res = try!(api.send(api.client.get(url)));
+        try!(io::copy(&mut res, &mut io::stdout()).map_err(|e| e.to_string()));
+        return Ok(());
+    }
+    let gist = try!(api.get_gist(id));
+    let many = gist.files.len() > 1;
+    for file in gist.files {
+        if many {
+            println!("==> {} <==", file.filename);
+        }
+        print!("{}", file.content.unwrap_or("".to_string()));
+    }
+    Ok(())
+}
+
+fn update(api: &Api, options: Options) -> Result<(), String> {
+    let id = try!(options.args.get(0).cloned().ok_or("update needs a gist id".to_string()));
+    let gist = try!(api.get_gist(&id));
+    let mut stdin_name = options.filename.clone();
+    if stdin_name.is_none() && gist.files.len() == 1 {
+        stdin_name = Some(gist.files[0].filename.clone());
+    }
+    if options.args.len() == 1 && stdin_name.is_none() {
+        return Err("name the file to replace with -f".to_string());
+    }
+    let changed = try!(read_files(&options.args[1..], &stdin_name));
+
+    let mut files: Vec<FileParam> = gist.files.into_iter().map(|f| FileParam {
+        filename: f.filename,
+        content: f.content.unwrap_or("".to_string()),
+        language: f.language_override,
+    }).collect();
+    for file in changed {
+        match files.iter().position(|f| f.filename == file.filename) {
+            Some(i) => files[i].content = file.content,
+            None => files.push(file),
+        }
+    }
+
+    let params = GistParam {
+        description: options.description,
+        visibility: options.visibility,
+        tags: options.tags,
+        files: files,
+    };
+    let mut res = try!(api.send(api.client.put(&format!("{}/api/gists/{}", api.url, gist.id)).json(&params)));
+    let gist = try!(res.json::<Gist>().map_err(|e| e.to_string()));
+    println!("{}", gist.html_url);
+    Ok(())
+}
+
+fn run(args: Vec<String>) -> Result<(), String> {
+    let command = match args.get(1) {
+        Some(command) => command.to_string(),
+        None => return Err(USAGE.to_string()),
+    };
+    if command == "-h" || command == "--help" || command == "help" {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    let options = try!(parse_options(&args[2..]));
+    let api = try!(Api::from_env());
+    match command.as_str() {
+        "create" => create(&api, options),
+        "list" => list(&api, options),
+        "show" => show(&api, options),
+        "update" => update(&api, options),
+        _ => Err(format!("unknown command {}\n\n{}", command, USAGE)),
+    }
+}
+
+fn main() {
+    if let Err(e) = run(env::args().collect()) {
+        eprintln!("team-gist: {}", e);
+        process::exit(1);
+    }
+}
@@ -26,6 +26,98 @@ fn default_theme() -> String {
     String::from("light")
 }
 
+fn default_oidc_scopes() -> String {
+    String::from("openid email profile")
+}
+
+fn default_oidc_name() -> String {
+    String::from("SSO")
+}
+
+fn default_oidc_username_claim() -> String {
+    String::from("preferred_username")
+}
+
+fn default_oidc_email_claim() -> String {
+    String::from("email")
+}
+
+fn default_oidc_avatar_claim() -> String {
+    String::from("picture")
+}
+
+fn default_auth_backends() -> String {
+    String::from("local")
+}
+
+fn default_ldap_user_filter() -> String {
+    String::from("(uid={username})")
+}
+
+fn default_ldap_email_attr() -> String {
+    String::from("mail")
+}
+
+fn default_smtp_port() -> u16 {
+    587
+}
+
+fn default_smtp_security() -> String {
+    String::from("starttls")
+}
+
+fn default_password_min_length() -> usize {
+    10
+}
+
+fn default_false() -> bool {
+    false
+}
+
+fn default_session_idle_timeout() -> i32 {
+    60 * 60 * 24 * 7
+}
+
+fn default_session_absolute_timeout() -> i32 {
+    60 * 60 * 24 * 30
+}
+
+fn default_rate_limit_window() -> u64 {
+    60
+}
+
+fn default_rate_limit_login_window() -> u64 {
+    60 * 5
+}
+
+fn default_rate_limit_login_per_ip() -> u32 {
+    30
+}
+
+fn default_rate_limit_login_per_account() -> u32 {
+    10
+}
+
+fn default_rate_limit_uploads() -> u32 {
+    20
+}
+
+fn default_rate_limit_comments() -> u32 {
+    20
+}
+
+fn default_lockout_threshold() -> i32 {
+    5
+}
+
+fn default_lockout_base() -> i32 {
+    60
+}
+
+fn default_lockout_max() -> i32 {
+    60 * 60
+}
+
 fn default_secret_cookie() -> String {
     String::from("FLEo9NZJDhZbBaT")
 }
@@ -49,11 +141,87 @@ pub struct Config {
     #[serde(default="default_empty_string")]
     pub team_google_redirect_url: String, // TEAM_GOOGLE_REDIRECT_URL
     #[serde(default="default_empty_string")]
-    pub team_google_allow_domain: String, // TEAM_GOOGLE_ALLOW_DOMAIN
+    pub team_google_allow_domain: String, // TEAM_GOOGLE_ALLOW_DOMAIN (deprecated, use TEAM_GOOGLE_ALLOW_DOMAINS)
+    #[serde(default="default_empty_string")]
+    pub team_google_allow_domains: String, // TEAM_GOOGLE_ALLOW_DOMAINS
+    #[serde(default="default_empty_string")]
+    pub team_oidc_discovery_url: String, // TEAM_OIDC_DISCOVERY_URL
+    #[serde(default="default_empty_string")]
+    pub team_oidc_client_id: String, // TEAM_OIDC_CLIENT_ID
+    #[serde(default="default_empty_string")]
+    pub team_oidc_client_secret: String, // TEAM_OIDC_CLIENT_SECRET
+    #[serde(default="default_empty_string")]
+    pub team_oidc_redirect_url: String, // TEAM_OIDC_REDIRECT_URL
+    #[serde(default="default_oidc_scopes")]
+    pub team_oidc_scopes: String, // TEAM_OIDC_SCOPES
+    #[serde(default="default_oidc_name")]
+    pub team_oidc_name: String, // TEAM_OIDC_NAME
+    #[serde(default="default_oidc_username_claim")]
+    pub team_oidc_username_claim: String, // TEAM_OIDC_USERNAME_CLAIM
+    #[serde(default="default_oidc_email_claim")]
+    pub team_oidc_email_claim: String, // TEAM_OIDC_EMAIL_CLAIM
+    #[serde(default="default_oidc_avatar_claim")]
+    pub team_oidc_avatar_claim: String, // TEAM_OIDC_AVATAR_CLAIM
+    #[serde(default="default_empty_string")]
+    pub team_oidc_email_verified_claim: String, // TEAM_OIDC_EMAIL_VERIFIED_CLAIM
+    #[serde(default="default_auth_backends")]
+    pub team_auth_backends: String, // TEAM_AUTH_BACKENDS
+    #[serde(default="default_empty_string")]
+    pub team_ldap_url: String, // TEAM_LDAP_URL
+    #[serde(default="default_empty_string")]
+    pub team_ldap_bind_dn: String, // TEAM_LDAP_BIND_DN
+    #[serde(default="default_empty_string")]
+    pub team_ldap_bind_password: String, // TEAM_LDAP_BIND_PASSWORD
+    #[serde(default="default_empty_string")]
+    pub team_ldap_base_dn: String, // TEAM_LDAP_BASE_DN
+    #[serde(default="default_ldap_user_filter")]
+    pub team_ldap_user_filter: String, // TEAM_LDAP_USER_FILTER
+    #[serde(default="default_empty_string")]
+    pub team_ldap_group_filter: String, // TEAM_LDAP_GROUP_FILTER
+    #[serde(default="default_ldap_email_attr")]
+    pub team_ldap_email_attr: String, // TEAM_LDAP_EMAIL_ATTR
     #[serde(default="default_menu")]
     pub team_menu: String, // TEAM_MENU
     #[serde(default="default_theme")]
     pub team_theme: String, // TEAM_THEME
+    #[serde(default="default_empty_string")]
+    pub team_smtp_host: String, // TEAM_SMTP_HOST
+    #[serde(default="default_smtp_port")]
+    pub team_smtp_port: u16, // TEAM_SMTP_PORT
+    #[serde(default="default_smtp_security")]
+    pub team_smtp_security: String, // TEAM_SMTP_SECURITY (starttls, tls or none)
+    #[serde(default="default_empty_string")]
+    pub team_smtp_username: String, // TEAM_SMTP_USERNAME
+    #[serde(default="default_empty_string")]
+    pub team_smtp_password: String, // TEAM_SMTP_PASSWORD
+    #[serde(default="default_empty_string")]
+    pub team_smtp_from: String, // TEAM_SMTP_FROM
+    #[serde(default="default_password_min_length")]
+    pub team_password_min_length: usize, // TEAM_PASSWORD_MIN_LENGTH
+    #[serde(default="default_session_idle_timeout")]
+    pub team_session_idle_timeout: i32, // TEAM_SESSION_IDLE_TIMEOUT (seconds)
+    #[serde(default="default_session_absolute_timeout")]
+    pub team_session_absolute_timeout: i32, // TEAM_SESSION_ABSOLUTE_TIMEOUT (seconds)
+    #[serde(default="default_false")]
+    pub team_trust_proxy: bool, // TEAM_TRUST_PROXY
+    #[serde(default="default_rate_limit_window")]
+    pub team_rate_limit_window: u64, // TEAM_RATE_LIMIT_WINDOW (seconds)
+    #[serde(default="default_rate_limit_login_window")]
+    pub team_rate_limit_login_window: u64, // TEAM_RATE_LIMIT_LOGIN_WINDOW (seconds)
+    #[serde(default="default_rate_limit_login_per_ip")]
+    pub team_rate_limit_login_per_ip: u32, // TEAM_RATE_LIMIT_LOGIN_PER_IP (0 disables)
+    #[serde(default="default_rate_limit_login_per_account")]
+    pub team_rate_limit_login_per_account: u32, // TEAM_RATE_LIMIT_LOGIN_PER_ACCOUNT (0 disables)
+    #[serde(default="default_rate_limit_uploads")]
+    pub team_rate_limit_uploads: u32, // TEAM_RATE_LIMIT_UPLOADS (0 disables)
+    #[serde(default="default_rate_limit_comments")]
+    pub team_rate_limit_comments: u32, // TEAM_RATE_LIMIT_COMMENTS (0 disables)
+    #[serde(default="default_lockout_threshold")]
+    pub team_lockout_threshold: i32, // TEAM_LOCKOUT_THRESHOLD (0 disables)
+    #[serde(default="default_lockout_base")]
+    pub team_lockout_base: i32, // TEAM_LOCKOUT_BASE (seconds)
+    #[serde(default="default_lockout_max")]
+    pub team_lockout_max: i32, // TEAM_LOCKOUT_MAX (seconds)
     #[serde(default="default_secret_cookie")]
     pub team_secret_cookie: String // TEAM_SECRET_COOKIE
 }
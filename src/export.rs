@@ -0,0 +1,218 @@
+use std::fs::File;
+use std::io::{Cursor, Read, Write};
+use std::path::Path;
+
+use chrono::NaiveDateTime;
+use serde_json;
+use zip::ZipWriter;
+use zip::write::FileOptions;
+
+use db;
+use models;
+
+#[derive(Serialize, Debug)]
+struct Manifest {
+    username: String,
+    email: Option<String>,
+    display_name: String,
+    title: String,
+    team: String,
+    bio: String,
+    links: Vec<String>,
+    exported: String,
+    posts: Vec<Entry>,
+    gists: Vec<Entry>,
+    tweets: Vec<models::export::Tweet>,
+    comments: Vec<models::export::Comment>,
+    stocks: Vec<models::export::Stock>,
+    stars: Vec<models::export::Star>,
+    images: Vec<String>,
+}
+
+// A manifest line pointing at a file in the archive.
+#[derive(Serialize, Debug)]
+struct Entry {
+    id: i32,
+    kind: String,
+    title: String,
+    status: String,
+    tags: Vec<String>,
+    created: NaiveDateTime,
+    updated: NaiveDateTime,
+    path: String,
+}
+
+// Keeps archive paths to plain ASCII words joined by dashes.
+fn slug(title: &str) -> String {
+    let slug: String = title.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+        .collect();
+    let slug: Vec<&str> = slug.split("-").filter(|s| *s != "").collect();
+    slug.join("-").chars().take(50).collect()
+}
+
+fn front_matter(post: &models::export::Post) -> String {
+    format!("---\ntitle: {}\nkind: {}\nstatus: {}\ntags: [{}]\ncreated: {}\nupdated: {}\n---\n\n",
+            serde_json::to_string(&post.title).unwrap_or("\"\"".to_string()),
+            post.kind, post.status, post.tags.join(", "), post.created, post.updated)
+}
+
+/// Collects the uploaded images (`img/posts/<name>.png`) a text links to.
+fn image_paths(text: &str) -> Vec<String> {
+    let mut paths: Vec<String> = Vec::new();
+    let marker = "img/posts/";
+    let mut rest = text;
+    while let Some(start) = rest.find(marker) {
+        rest = &rest[start + marker.len()..];
+        let name: String = rest.chars().take_while(|c| c.is_ascii_alphanumeric()).collect();
+        if name != "" && rest[name.len()..].starts_with(".png") {
+            paths.push(format!("{}{}.png", marker, name));
+        }
+    }
+    paths
+}
+
+// Paths come from user-editable text, so anything that could leave public/ is refused.
+fn read_public(path: &str) -> Option<Vec<u8>> {
+    if path.contains("..") || !path.chars().all(|c| c.is_ascii_alphanumeric() || "/._-".contains(c)) {
+        return None;
+    }
+    let mut buf = Vec::new();
+    match File::open(Path::new("public").join(path)).and_then(|mut f| f.read_to_end(&mut buf)) {
+        Ok(_) => Some(buf),
+        Err(_) => None,
+    }
+}
+
+// Gist filenames are user input; keep them to a single path segment.
+fn archive_filename(file: &models::gist::File) -> String {
+    if file.filename.trim_matches('.') == "" { "gist.txt".to_string() } else { file.filename.replace("/", "_").replace("\\", "_") }
+}
+
+fn add(zip: &mut ZipWriter<Cursor<Vec<u8>>>, path: &str, bytes: &[u8]) -> Result<(), String> {
+    try!(zip.start_file(path, FileOptions::default()).map_err(|e| e.to_string()));
+    zip.write_all(bytes).map_err(|e| e.to_string())
+}
+
+/// Builds a zip of everything the user has written: posts, nippo and drafts as
+/// Markdown, gists as their original files, and a `manifest.json` with the rest.
+pub fn archive(conn: &db::PostgresConnection, user_id: &i32, exported: &str) -> Result<Vec<u8>, String> {
+    let user = try!(models::user::get_with_email_by_id(conn, user_id).map_err(|e| e.to_string()));
+    let profile = try!(models::member::get(conn, user_id).map_err(|e| e.to_string()));
+    let posts = try!(models::export::posts(conn, user_id).map_err(|e| e.to_string()));
+    let gists = try!(models::export::gists(conn, user_id).map_err(|e| e.to_string()));
+    let tweets = try!(models::export::tweets(conn, user_id).map_err(|e| e.to_string()));
+    let comments = try!(models::export::comments(conn, user_id).map_err(|e| e.to_string()));
+    let stocks = try!(models::export::stocks(conn, user_id).map_err(|e| e.to_string()));
+    let stars = try!(models::export::stars(conn, user_id).map_err(|e| e.to_string()));
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let mut texts: Vec<String> = Vec::new();
+
+    let mut post_entries: Vec<Entry> = Vec::new();
+    for post in &posts {
+        let dir = if post.status == "draft" { "drafts".to_string() } else { post.kind.to_string() };
+        let path = format!("{}/{}-{}.md", dir, post.id, slug(&post.title));
+        try!(add(&mut zip, &path, format!("{}{}\n", front_matter(post), post.body).as_bytes()));
+        texts.push(post.body.to_string());
+        post_entries.push(Entry {
+            id: post.id,
+            kind: post.kind.to_string(),
+            title: post.title.to_string(),
+            status: post.status.to_string(),
+            tags: post.tags.clone(),
+            created: post.created,
+            updated: post.updated,
+            path: path,
+        });
+    }
+
+    let mut gist_entries: Vec<Entry> = Vec::new();
+    for gist in &gists {
+        let path = format!("gists/{}/", gist.id);
+        for file in &gist.files {
+            try!(add(&mut zip, &format!("{}{}", path, archive_filename(file)), file.code.as_bytes()));
+        }
+        gist_entries.push(Entry {
+            id: gist.id,
+            kind: "gist".to_string(),
+            title: gist.description.to_string(),
+            status: "".to_string(),
+            tags: gist.tags.clone(),
+            created: gist.created,
+            updated: gist.updated,
+            path: path,
+        });
+    }
+
+    let mut tweets_md = String::new();
+    for tweet in &tweets {
+        tweets_md.push_str(&format!("## {}\n\n{}\n\n", tweet.created, tweet.body));
+        for path in &tweet.attachments {
+            tweets_md.push_str(&format!("![]({})\n\n", path.replace("img/", "images/")));
+            texts.push(path.to_string());
+        }
+        texts.push(tweet.body.to_string());
+    }
+    try!(add(&mut zip, "tweets.md", tweets_md.as_bytes()));
+
+    let mut comments_md = String::new();
+    for comment in &comments {
+        comments_md.push_str(&format!("## {} on {} {}\n\n{}\n\n", comment.created, comment.target, comment.target_id, comment.body));
+        texts.push(comment.body.to_string());
+    }
+    try!(add(&mut zip, "comments.md", comments_md.as_bytes()));
+
+    let mut images: Vec<String> = Vec::new();
+    if let Some(ref icon_url) = user.icon_url {
+        if let Some(start) = icon_url.find("img/profile/icons/") {
+            images.push(icon_url[start..].to_string());
+        }
+    }
+    for text in &texts {
+        images.extend(image_paths(text));
+    }
+    images.sort();
+    images.dedup();
+    let mut exported_images: Vec<String> = Vec::new();
+    for image in &images {
+        if let Some(bytes) = read_public(image) {
+            let path = format!("images/{}", image.replace("img/", ""));
+            try!(add(&mut zip, &path, &bytes));
+            exported_images.push(path);
+        }
+    }
+
+    let manifest = Manifest {
+        username: user.username,
+        email: user.email,
+        display_name: profile.display_name,
+        title: profile.title,
+        team: profile.team,
+        bio: profile.bio,
+        links: profile.links,
+        exported: exported.to_string(),
+        posts: post_entries,
+        gists: gist_entries,
+        tweets: tweets,
+        comments: comments,
+        stocks: stocks,
+        stars: stars,
+        images: exported_images,
+    };
+    let json = try!(serde_json::to_string_pretty(&manifest).map_err(|e| e.to_string()));
+    try!(add(&mut zip, "manifest.json", json.as_bytes()));
+
+    let cursor = try!(zip.finish().map_err(|e| e.to_string()));
+    Ok(cursor.into_inner())
+}
+
+/// Zips a gist's files under a `gist-<id>/` folder for download.
+pub fn gist_archive(gist: &models::gist::Gist) -> Result<Vec<u8>, String> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    for file in &gist.files {
+        try!(add(&mut zip, &format!("gist-{}/{}", gist.id, archive_filename(file)), file.code.as_bytes()));
+    }
+    let cursor = try!(zip.finish().map_err(|e| e.to_string()));
+    Ok(cursor.into_inner())
+}
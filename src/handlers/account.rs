@@ -9,18 +9,23 @@ use hbs::handlebars::to_json;
 
 use iron_sessionstorage;
 use iron_sessionstorage::traits::*;
+use postgres::error::Error;
 
+use auth;
 use db;
+use totp;
 use models;
 use helper;
 use handlers;
+use middlewares;
+use mail;
 use env;
 
 const PAGINATES_PER: i32 = 10;
 
 #[derive(Serialize, Debug, Default)]
 pub struct Login {
-    id: String,
+    pub id: String,
 }
 
 impl iron_sessionstorage::Value for Login {
@@ -60,18 +65,233 @@ impl iron_sessionstorage::Value for RefUrl {
     }
 }
 
-pub fn get_signup_handler(_: &mut Request) -> IronResult<Response> {
+// A user who passed the password step but still owes a second factor.
+#[derive(Serialize, Debug, Default)]
+pub struct PendingLogin {
+    pub id: String,
+}
+
+impl iron_sessionstorage::Value for PendingLogin {
+    fn get_key() -> &'static str {
+        "pending_login"
+    }
+    fn into_raw(self) -> String {
+        self.id
+    }
+    fn from_raw(value: String) -> Option<Self> {
+        if value.is_empty() {
+            None
+        } else {
+            Some(PendingLogin { id: value })
+        }
+    }
+}
+
+// The invitation a visitor arrived with, kept until an account is created.
+#[derive(Serialize, Debug, Default)]
+pub struct Invitation {
+    token: String,
+}
+
+impl iron_sessionstorage::Value for Invitation {
+    fn get_key() -> &'static str {
+        "invitation"
+    }
+    fn into_raw(self) -> String {
+        self.token
+    }
+    fn from_raw(value: String) -> Option<Self> {
+        if value.is_empty() {
+            None
+        } else {
+            Some(Invitation { token: value })
+        }
+    }
+}
+
+pub fn ref_url(req: &mut Request) -> Url {
+    let ref_url = req.session().get::<RefUrl>().ok().and_then(|x| x)
+        .unwrap_or(RefUrl { url: "".to_string() });
+    return Url::parse(&format!("{}", ref_url.url)
+        .to_string())
+        .unwrap_or(helper::redirect_url("/"));
+}
+
+// Finishes a successful first-factor sign-in, refusing deactivated accounts
+// and detouring through the TOTP step when the user has enrolled. The
+// session is replaced so an id issued before sign-in never becomes
+// authenticated.
+pub fn complete_sign_in(req: &mut Request, conn: &db::PostgresConnection, user_id: &i32) -> IronResult<Response> {
+    match models::user::is_deactivated(conn, user_id) {
+        Ok(false) => {}
+        Ok(true) => {
+            return render_signin(req, status::Forbidden, "This account has been deactivated.");
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+    let url = ref_url(req);
+    try!(req.session().clear());
+    match models::totp::get_by_user_id(conn, user_id) {
+        Ok(totp) => {
+            if totp.enabled {
+                try!(req.session().set(RefUrl { url: url.to_string() }));
+                try!(req.session().set(PendingLogin { id: user_id.to_string() }));
+                return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin/totp")))));
+            }
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+    if let Err(e) = models::login_failure::delete_by_user_id(conn, user_id) {
+        error!("Errored: {:?}", e);
+    }
+    helper::audit(req, conn, user_id, "signin", "", "");
+    try!(req.session().set(Login { id: user_id.to_string() }));
+    return Ok(Response::with((status::Found, Redirect(url))));
+}
+
+#[derive(Serialize, Debug, Default)]
+struct AuthFormData {
+    csrf_token: String,
+    oidc_enabled: bool,
+    oidc_name: String,
+    message: String,
+    notice: String,
+    invited: bool,
+    invite_only: bool,
+    email_required: bool,
+    signup_domains: String,
+}
+
+// Confirmation mails one address may ask for in an hour.
+const MAX_SIGNUP_CONFIRMATIONS_PER_IP: i32 = 10;
+
+fn invitation_token(req: &mut Request) -> Option<String> {
+    req.session().get::<Invitation>().ok().and_then(|x| x).map(|i| i.token)
+}
+
+fn invite_id(req: &mut Request, conn: &db::PostgresConnection) -> Result<Option<i32>, Error> {
+    match invitation_token(req) {
+        Some(token) => models::invite::get_valid_id(conn, &token),
+        None => Ok(None),
+    }
+}
+
+// Decides whether a new account may be created for `email`, which must already be
+// confirmed when the domain list applies. An invite lets anyone in.
+fn check_signup(conn: &db::PostgresConnection, invite_id: &Option<i32>, email: &str) -> Result<(), String> {
+    if invite_id.is_some() {
+        return Ok(());
+    }
+
+    let mode = models::setting::signup_mode(conn);
+    let domains = models::setting::signup_domains(conn);
+    match (mode, domains) {
+        (Ok(mode), Ok(domains)) => {
+            if mode == models::setting::SIGNUP_INVITE {
+                return Err("Signup is by invitation only.".to_string());
+            }
+            if mode == models::setting::SIGNUP_DOMAIN {
+                let allowed = match helper::email_domain(email) {
+                    Some(domain) => domains.contains(&domain),
+                    None => false,
+                };
+                if !allowed {
+                    return Err(format!("Signup is limited to {} addresses.", domains.join(", ")));
+                }
+            }
+            Ok(())
+        }
+        (Err(e), _) | (_, Err(e)) => {
+            error!("Errored: {:?}", e);
+            Err("Signup is temporarily unavailable.".to_string())
+        }
+    }
+}
+
+// Returns why the username or email can't be used, if either is already taken.
+fn signup_taken(conn: &db::PostgresConnection, username: &String, email: &String) -> Result<Option<String>, Error> {
+    if try!(models::user::get_by_username(conn, username)).id != 0 {
+        return Ok(Some("That username is already taken.".to_string()));
+    }
+    if email != "" && try!(models::user::get_by_email(conn, email)).id != 0 {
+        return Ok(Some("That email address is already registered.".to_string()));
+    }
+    Ok(None)
+}
+
+fn render_signup(req: &mut Request, conn: &db::PostgresConnection, st: status::Status, message: &str, notice: &str) -> IronResult<Response> {
+    let invited = match invitation_token(req) {
+        Some(token) => models::invite::is_valid(conn, &token).unwrap_or(false),
+        None => false,
+    };
+    let mode = models::setting::signup_mode(conn).unwrap_or(models::setting::SIGNUP_OPEN.to_string());
+    let domains = models::setting::signup_domains(conn).unwrap_or(Vec::new());
+
+    let data = AuthFormData {
+        csrf_token: middlewares::csrf::token(req),
+        oidc_enabled: oidc::enabled(),
+        oidc_name: env::CONFIG.team_oidc_name.to_string(),
+        message: message.to_string(),
+        notice: notice.to_string(),
+        invited: invited,
+        invite_only: !invited && mode == models::setting::SIGNUP_INVITE,
+        email_required: !invited && mode == models::setting::SIGNUP_DOMAIN,
+        signup_domains: domains.join(", "),
+    };
     let mut resp = Response::new();
-    resp.set_mut(Template::new("account/signup", {}))
-        .set_mut(status::Ok);
+    resp.set_mut(Template::new("account/signup", to_json(&data)))
+        .set_mut(st);
     return Ok(resp);
 }
 
+// Creates the account and signs it in; the invite, if any, is used up in the same transaction.
+fn create_account(req: &mut Request, conn: &db::PostgresConnection, username: &String, password: &String, email: &String, invite_id: &Option<i32>) -> IronResult<Response> {
+    match models::user::create(conn, username, password, email, invite_id) {
+        Ok(Some(user_id)) => {
+            if invite_id.is_some() {
+                let _ = req.session().set(Invitation { token: "".to_string() });
+            }
+            return complete_sign_in(req, conn, &user_id);
+        }
+        Ok(None) => return render_signup(req, conn, status::Forbidden, "That invitation is no longer valid.", ""),
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+}
+
+pub fn get_signup_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+
+    let invite: Option<String>;
+    {
+        use params::{Params, Value};
+        let map = req.get_ref::<Params>().unwrap();
+        match map.get("invite") {
+            Some(&Value::String(ref token)) if token != "" => invite = Some(token.to_string()),
+            _ => invite = None,
+        }
+    }
+    if let Some(token) = invite {
+        try!(req.session().set(Invitation { token: token }));
+    }
+
+    render_signup(req, &conn, status::Ok, "", "")
+}
+
 pub fn post_signup_handler(req: &mut Request) -> IronResult<Response> {
     let conn = get_pg_connection!(req);
 
     let username: String;
     let mut password: String;
+    let email: String;
 
     {
         use params::{Params, Value};
@@ -95,37 +315,164 @@ pub fn post_signup_handler(req: &mut Request) -> IronResult<Response> {
             }
             _ => return Ok(Response::with(status::BadRequest)),
         }
+        match map.get("email") {
+            Some(&Value::String(ref name)) => {
+                email = name.trim().to_string();
+            }
+            _ => email = "".to_string(),
+        }
+    }
+
+    match signup_taken(&conn, &username, &email) {
+        Ok(Some(message)) => return render_signup(req, &conn, status::BadRequest, &message, ""),
+        Ok(None) => {}
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+
+    if let Err(message) = helper::check_password_policy(&password, &username) {
+        return render_signup(req, &conn, status::BadRequest, &message, "");
+    }
+
+    let invite_id = match invite_id(req, &conn) {
+        Ok(invite_id) => invite_id,
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    };
+
+    // Checked again once the address is confirmed; this only saves a pointless mail.
+    if let Err(message) = check_signup(&conn, &invite_id, &email) {
+        return render_signup(req, &conn, status::Forbidden, &message, "");
     }
 
     password = helper::encrypt_password(password);
-    match models::user::create(&conn, &username, &password) {
-        Ok(user_id) => {
-            try!(req.session().set(Login { id: user_id.to_string() }));
-            return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/")))));
+    if email == "" {
+        return create_account(req, &conn, &username, &password, &email, &invite_id);
+    }
+
+    // An email address decides domain signups and links Google and OpenID Connect
+    // sign-ins to the account, so the account waits until it is confirmed.
+    if !mail::enabled() {
+        return render_signup(req, &conn, status::BadRequest,
+                             "Email addresses can't be confirmed on this site. Sign up without one, or with single sign-on.", "");
+    }
+    let ip = helper::client_ip(req);
+    match models::signup_confirmation::recent_count_by_ip(&conn, &ip) {
+        Ok(count) => {
+            if count >= MAX_SIGNUP_CONFIRMATIONS_PER_IP {
+                return render_signup(req, &conn, status::TooManyRequests, "Too many signups. Try again later.", "");
+            }
         }
         Err(e) => {
-            info!("Errored: {:?}", e);
+            error!("Errored: {:?}", e);
             return Ok(Response::with(status::InternalServerError));
         }
     }
+
+    let token = helper::random_token();
+    if let Err(e) = models::signup_confirmation::create(&conn, &username, &password, &email, &invite_id, &token, &ip) {
+        error!("Errored: {:?}", e);
+        return Ok(Response::with(status::InternalServerError));
+    }
+    let url = helper::redirect_url(&format!("/signup/confirm?token={}", token));
+    let body = format!("Hi @{},\n\n\
+                        Open this link within a day to confirm your email address\n\
+                        and finish signing up for Team:\n\n\
+                        {}\n\n\
+                        If it wasn't you, you can ignore this email.\n",
+                       username, url);
+    if let Err(e) = mail::send(&email, "Confirm your Team signup", &body) {
+        error!("Errored: {:?}", e);
+        return render_signup(req, &conn, status::InternalServerError, "The confirmation email could not be sent. Try again later.", "");
+    }
+
+    render_signup(req, &conn, status::Ok, "", &format!("We sent a confirmation link to {}. Open it to finish signing up.", email))
 }
 
-pub fn get_signin_handler(req: &mut Request) -> IronResult<Response> {
+pub fn get_signup_confirm_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+
+    let token: String;
+    {
+        use params::Params;
+        let map = &req.get_ref::<Params>().unwrap();
+        token = helper::get_param(map, "token").unwrap_or("".to_string());
+    }
+
+    let confirmation = match models::signup_confirmation::consume(&conn, &token) {
+        Ok(Some(confirmation)) => confirmation,
+        Ok(None) => return render_signup(req, &conn, status::BadRequest, "That confirmation link is invalid or has expired.", ""),
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    };
+
+    match signup_taken(&conn, &confirmation.username, &confirmation.email) {
+        Ok(Some(message)) => return render_signup(req, &conn, status::BadRequest, &message, ""),
+        Ok(None) => {}
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+
+    if let Err(message) = check_signup(&conn, &confirmation.invite_id, &confirmation.email) {
+        return render_signup(req, &conn, status::Forbidden, &message, "");
+    }
+
+    create_account(req, &conn, &confirmation.username, &confirmation.password, &confirmation.email, &confirmation.invite_id)
+}
+
+fn render_signin(req: &mut Request, st: status::Status, message: &str) -> IronResult<Response> {
+    let data = AuthFormData {
+        csrf_token: middlewares::csrf::token(req),
+        oidc_enabled: oidc::enabled(),
+        oidc_name: env::CONFIG.team_oidc_name.to_string(),
+        message: message.to_string(),
+        ..Default::default()
+    };
     let mut resp = Response::new();
+    resp.set_mut(Template::new("account/signin", to_json(&data)))
+        .set_mut(st);
+    return Ok(resp);
+}
+
+pub fn lockout_message(seconds: i32) -> String {
+    let minutes = (seconds + 59) / 60;
+    format!("Too many failed sign-in attempts. Try again in {} minute{}.",
+            minutes, if minutes == 1 { "" } else { "s" })
+}
+
+/// Records a failed sign-in for the account and logs it for auditing.
+pub fn record_login_failure(req: &mut Request, conn: &db::PostgresConnection, username: &str, reason: &str) {
+    let ip = helper::client_ip(req);
+    warn!("Failed sign-in for {:?} from {}: {}", username, ip, reason);
+    if let Err(e) = models::login_failure::create(conn, username, &ip) {
+        error!("Errored: {:?}", e);
+    }
+    // Attributed to the account when it exists, so the admin user filter finds it.
+    let user_id = models::user::get_by_username(conn, username).map(|user| user.id).unwrap_or(0);
+    helper::audit(req, conn, &user_id, "signin.failed", username, reason);
+}
+
+pub fn get_signin_handler(req: &mut Request) -> IronResult<Response> {
     if try!(req.session().get::<Login>()).is_some() {
         // Already logged in
         return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/")))));
     }
-    resp.set_mut(Template::new("account/signin", {}))
-        .set_mut(status::Ok);
-    return Ok(resp);
+    render_signin(req, status::Ok, "")
 }
 
 pub fn post_signin_handler(req: &mut Request) -> IronResult<Response> {
     let conn = get_pg_connection!(req);
 
     let username: String;
-    let mut password: String;
+    let password: String;
 
     {
         use params::{Params, Value};
@@ -133,7 +480,7 @@ pub fn post_signin_handler(req: &mut Request) -> IronResult<Response> {
 
         match map.get("username") {
             Some(&Value::String(ref name)) => {
-                username = name.to_string();
+                username = name.trim().to_string();
             }
             _ => return Ok(Response::with(status::BadRequest)),
         }
@@ -145,22 +492,27 @@ pub fn post_signin_handler(req: &mut Request) -> IronResult<Response> {
         }
     }
 
-    password = helper::encrypt_password(password);
-    match models::user::get_by_username_password(&conn, &username, &password) {
-        Ok(user) => {
-            if user.username != "" {
-                try!(req.session().set(Login { id: user.id.to_string() }));
-                let ref_url = req.session().get::<RefUrl>().ok().and_then(|x| x)
-                    .unwrap_or(RefUrl { url: "".to_string() });
-                let url = Url::parse(&format!("{}", ref_url.url)
-                    .to_string())
-                    .unwrap_or(helper::redirect_url("/"));
-                println!("==== Referer: {:?} =====", url.to_string());
-                return Ok(Response::with((status::Found, Redirect(url))));
-            } else {
-                return Ok(Response::with((status::Found,
-                                          Redirect(helper::redirect_url("/signin")))));
+    // A locked account is refused before its password is even checked.
+    match models::login_failure::locked_for(&conn, &username) {
+        Ok(0) => {}
+        Ok(seconds) => {
+            return render_signin(req, status::TooManyRequests, &lockout_message(seconds));
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+
+    match auth::authenticate(&conn, &username, &password) {
+        Ok(Some(user)) => {
+            return complete_sign_in(req, &conn, &user.id);
+        }
+        Ok(None) => {
+            if username != "" {
+                record_login_failure(req, &conn, &username, "bad password");
             }
+            return render_signin(req, status::Unauthorized, "Invalid username or password.");
         }
         Err(e) => {
             error!("Errored: {:?}", e);
@@ -169,7 +521,12 @@ pub fn post_signin_handler(req: &mut Request) -> IronResult<Response> {
     }
 }
 
-pub fn get_signout_handler(req: &mut Request) -> IronResult<Response> {
+pub fn post_signout_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let login = req.session().get::<Login>().ok().and_then(|x| x);
+    if let Some(login_id) = login.and_then(|l| l.id.parse::<i32>().ok()) {
+        helper::audit(req, &conn, &login_id, "signout", "", "");
+    }
     try!(req.session().clear());
     return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
 }
@@ -202,9 +559,22 @@ pub fn get_settings_handler(req: &mut Request) -> IronResult<Response> {
         menu: Vec<Menu>,
         theme_light: String,
         theme_black: String,
+        totp_enabled: bool,
+        totp_pending: bool,
+        totp_secret: String,
+        totp_qr_svg: String,
+        recovery_code_count: i32,
+        require_2fa: bool,
+        has_password: bool,
+        profile: models::member::Member,
+        profile_links: String,
     }
 
     let user: models::user::User;
+    let totp: models::totp::Totp;
+    let recovery_code_count: i32;
+    let require_2fa: bool;
+    let has_password: bool;
 
     match models::user::get_by_id(&conn, &login_id) {
         Ok(user_obj) => {
@@ -216,6 +586,62 @@ pub fn get_settings_handler(req: &mut Request) -> IronResult<Response> {
         }
     }
 
+    match models::totp::get_by_user_id(&conn, &login_id) {
+        Ok(totp_obj) => {
+            totp = totp_obj;
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+
+    match models::totp::unused_recovery_code_count(&conn, &login_id) {
+        Ok(count) => {
+            recovery_code_count = count;
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+
+    match models::setting::get_bool(&conn, models::setting::REQUIRE_2FA) {
+        Ok(required) => {
+            require_2fa = required;
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+
+    match models::user::get_password(&conn, &login_id) {
+        Ok(password) => {
+            has_password = password.is_some();
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+
+    let profile = match models::member::get(&conn, &login_id) {
+        Ok(profile) => profile,
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    };
+
+    // Two-factor authentication
+    let totp_secret = totp.secret.unwrap_or("".to_string());
+    let totp_pending = !totp.enabled && totp_secret != "";
+    let mut totp_qr_svg = String::from("");
+    if totp_pending {
+        totp_qr_svg = totp::qr_svg(&totp::provisioning_uri(&user.username, &totp_secret));
+    }
+
     // Menu
     let default_menu = &env::CONFIG.team_menu;
     let mut menu: Vec<Menu> = Vec::new();
@@ -248,6 +674,15 @@ pub fn get_settings_handler(req: &mut Request) -> IronResult<Response> {
         menu: menu,
         theme_light: theme_light_checked,
         theme_black: theme_black_checked,
+        totp_enabled: totp.enabled,
+        totp_pending: totp_pending,
+        totp_secret: if totp_pending { totp_secret } else { "".to_string() },
+        totp_qr_svg: totp_qr_svg,
+        recovery_code_count: recovery_code_count,
+        require_2fa: require_2fa,
+        has_password: has_password,
+        profile_links: profile.links.join("\n"),
+        profile: profile,
     };
 
     resp.set_mut(Template::new("account/settings", to_json(&data)))
@@ -331,6 +766,9 @@ pub fn post_password_update(req: &mut Request) -> IronResult<Response> {
     if new_password != confirm_password {
         return Ok(Response::with(status::BadRequest));
     }
+    if let Err(message) = helper::check_password_policy(&new_password, &login_user.username) {
+        return Ok(Response::with((status::BadRequest, message)));
+    }
 
     let current_password = helper::encrypt_password(current_password);
     let user: models::user::UserWithPassword;
@@ -346,6 +784,7 @@ pub fn post_password_update(req: &mut Request) -> IronResult<Response> {
 
     match models::user::update_password(&conn, &login_id, &helper::encrypt_password(new_password)) {
         Ok(_) => {
+            helper::audit(req, &conn, &login_id, "password.change", &format!("user:{}", login_id), "");
             return Ok(Response::with((status::Found,
                                       Redirect(helper::redirect_url("/account/settings")))));
         }
@@ -356,6 +795,137 @@ pub fn post_password_update(req: &mut Request) -> IronResult<Response> {
     }
 }
 
+pub fn post_profile_update(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+
+    let mut login_user: models::user::UserWithPreference = models::user::UserWithPreference{..Default::default()};
+    match handlers::account::current_user(req, &conn) {
+        Ok(user) => { login_user = user; }
+        Err(e) => { error!("Errored: {:?}", e); }
+    }
+    let login_id = login_user.id;
+    if login_id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+
+    let display_name: String;
+    let bio: String;
+    let title: String;
+    let team: String;
+    let links: String;
+    {
+        use params::{Params, Value};
+        let map = req.get_ref::<Params>().unwrap();
+        match map.get("display_name") {
+            Some(&Value::String(ref value)) => display_name = value.trim().to_string(),
+            _ => display_name = "".to_string(),
+        }
+        match map.get("bio") {
+            Some(&Value::String(ref value)) => bio = value.trim().to_string(),
+            _ => bio = "".to_string(),
+        }
+        match map.get("title") {
+            Some(&Value::String(ref value)) => title = value.trim().to_string(),
+            _ => title = "".to_string(),
+        }
+        match map.get("team") {
+            Some(&Value::String(ref value)) => team = value.trim().to_string(),
+            _ => team = "".to_string(),
+        }
+        match map.get("links") {
+            Some(&Value::String(ref value)) => links = helper::split_links(value.trim()).join("\n"),
+            _ => links = "".to_string(),
+        }
+    }
+    if display_name.chars().count() > 255 || title.chars().count() > 255 || team.chars().count() > 255
+        || bio.chars().count() > 1000 {
+        return Ok(Response::with(status::BadRequest));
+    }
+
+    match models::member::update_profile(&conn, &login_id, &display_name, &bio, &title, &team, &links) {
+        Ok(_) => {
+            return Ok(Response::with((status::Found,
+                                      Redirect(helper::redirect_url("/account/settings")))));
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+}
+
+pub fn post_delete_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+
+    let mut login_user: models::user::UserWithPreference = models::user::UserWithPreference{..Default::default()};
+    match handlers::account::current_user(req, &conn) {
+        Ok(user) => { login_user = user; }
+        Err(e) => { error!("Errored: {:?}", e); }
+    }
+    let login_id = login_user.id;
+    if login_id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+
+    let confirm_username: String;
+    let password: String;
+    {
+        use params::{Params, Value};
+        let map = req.get_ref::<Params>().unwrap();
+        match map.get("confirm_username") {
+            Some(&Value::String(ref value)) => confirm_username = value.trim().to_string(),
+            _ => return Ok(Response::with(status::BadRequest)),
+        }
+        match map.get("password") {
+            Some(&Value::String(ref value)) => password = value.to_string(),
+            _ => password = "".to_string(),
+        }
+    }
+
+    if confirm_username != login_user.username {
+        return Ok(Response::with((status::BadRequest, "username does not match")));
+    }
+    // Accounts with a password must confirm it; SSO-only accounts cannot.
+    match models::user::get_password(&conn, &login_id) {
+        Ok(Some(current)) => {
+            if helper::encrypt_password(password) != current {
+                return Ok(Response::with((status::BadRequest, "password does not match")));
+            }
+        }
+        Ok(None) => {}
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+    if login_user.is_admin {
+        match models::user::admin_count(&conn) {
+            Ok(count) => {
+                if count <= 1 {
+                    return Ok(Response::with((status::BadRequest, "make someone else an admin first")));
+                }
+            }
+            Err(e) => {
+                error!("Errored: {:?}", e);
+                return Ok(Response::with(status::InternalServerError));
+            }
+        }
+    }
+
+    match models::user::anonymize(&conn, &login_id) {
+        Ok(_) => {
+            info!("User {} deleted their account", login_id);
+            helper::audit(req, &conn, &login_id, "account.delete", &format!("user:{}", login_id), "");
+            try!(req.session().clear());
+            return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+}
+
 pub fn post_username_update(req: &mut Request) -> IronResult<Response> {
     let conn = get_pg_connection!(req);
 
@@ -381,6 +951,8 @@ pub fn post_username_update(req: &mut Request) -> IronResult<Response> {
 
     match models::user::update_username(&conn, &login_id, &username) {
         Ok(_) => {
+            helper::audit(req, &conn, &login_id, "account.username", &format!("user:{}", login_id),
+                          &format!("{} -> {}", login_user.username, username));
             return Ok(Response::with((status::Found,
                                       Redirect(helper::redirect_url("/account/settings")))));
         }
@@ -405,12 +977,14 @@ pub fn current_user(req: &mut Request, conn: &db::PostgresConnection) -> Result<
         .unwrap_or(Login { id: "".to_string() });
 
     if login.id == "" {
+        user.csrf_token = middlewares::csrf::token(req);
         return Ok(user);
     } else {
         let login_id = login.id.parse::<i32>().unwrap();
         match models::user::get_current_user(&conn, &login_id) {
             Ok(user_obj) => {
                 user = user_obj;
+                user.csrf_token = middlewares::csrf::token(req);
                 Ok(user)
             }
             Err(e) => {
@@ -467,6 +1041,10 @@ pub fn profile_post_handler(req: &mut Request) -> IronResult<Response> {
         kind: String,
         kind_post_active: String,
         kind_nippo_active: String,
+        profile: models::member::Member,
+        is_self: bool,
+        following: bool,
+        follower_count: i32,
     }
 
     let mut page = page_param.parse::<i32>().unwrap();
@@ -487,7 +1065,31 @@ pub fn profile_post_handler(req: &mut Request) -> IronResult<Response> {
         }
     }
 
-    match models::post::user_posts(&conn, &username, &offset, &limit, "post") {
+    let profile = match models::member::get(&conn, &user.id) {
+        Ok(profile) => profile,
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    };
+
+    let following = match models::follow::is_following_user(&conn, &login_id, &user.id) {
+        Ok(following) => following,
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    };
+
+    let follower_count = match models::follow::follower_count(&conn, &user.id) {
+        Ok(count) => count,
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    };
+
+    match models::post::user_posts(&conn, &username, &login_id, &offset, &limit, "post") {
         Ok(posts_db) => {
             posts = posts_db;
         }
@@ -497,7 +1099,7 @@ pub fn profile_post_handler(req: &mut Request) -> IronResult<Response> {
         }
     }
 
-    match models::post::user_posts_count(&conn, username, "post") {
+    match models::post::user_posts_count(&conn, username, &login_id, "post") {
         Ok(count_db) => {
             count = count_db;
         }
@@ -522,6 +1124,10 @@ pub fn profile_post_handler(req: &mut Request) -> IronResult<Response> {
         kind: String::from("post"),
         kind_post_active: String::from("is-active"),
         kind_nippo_active: String::from(""),
+        is_self: user.id == login_id,
+        profile: profile,
+        following: following,
+        follower_count: follower_count,
     };
 
     resp.set_mut(Template::new("account/profile", to_json(&data)))
@@ -575,6 +1181,10 @@ pub fn profile_nippo_handler(req: &mut Request) -> IronResult<Response> {
         kind: String,
         kind_post_active: String,
         kind_nippo_active: String,
+        profile: models::member::Member,
+        is_self: bool,
+        following: bool,
+        follower_count: i32,
     }
 
     let mut page = page_param.parse::<i32>().unwrap();
@@ -595,7 +1205,31 @@ pub fn profile_nippo_handler(req: &mut Request) -> IronResult<Response> {
         }
     }
 
-    match models::post::user_posts(&conn, &username, &offset, &limit, "nippo") {
+    let profile = match models::member::get(&conn, &user.id) {
+        Ok(profile) => profile,
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    };
+
+    let following = match models::follow::is_following_user(&conn, &login_id, &user.id) {
+        Ok(following) => following,
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    };
+
+    let follower_count = match models::follow::follower_count(&conn, &user.id) {
+        Ok(count) => count,
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    };
+
+    match models::post::user_posts(&conn, &username, &login_id, &offset, &limit, "nippo") {
         Ok(posts_db) => {
             posts = posts_db;
         }
@@ -605,7 +1239,7 @@ pub fn profile_nippo_handler(req: &mut Request) -> IronResult<Response> {
         }
     }
 
-    match models::post::user_posts_count(&conn, username, "nippo") {
+    match models::post::user_posts_count(&conn, username, &login_id, "nippo") {
         Ok(count_db) => {
             count = count_db;
         }
@@ -630,6 +1264,10 @@ pub fn profile_nippo_handler(req: &mut Request) -> IronResult<Response> {
         kind: String::from("nippo"),
         kind_post_active: String::from(""),
         kind_nippo_active: String::from("is-active"),
+        is_self: user.id == login_id,
+        profile: profile,
+        following: following,
+        follower_count: follower_count,
     };
 
     resp.set_mut(Template::new("account/profile", to_json(&data)))
@@ -639,21 +1277,59 @@ pub fn profile_nippo_handler(req: &mut Request) -> IronResult<Response> {
 
 use oauth2::Config;
 use iron::Url;
+use crypto::util::fixed_time_eq;
 
-pub fn get_auth_google_handler(req: &mut Request) -> IronResult<Response> {
+use oidc;
+
+#[derive(Serialize, Debug, Default)]
+pub struct AuthRequest {
+    state: String,
+    code_verifier: String,
+    nonce: String,
+}
+
+impl iron_sessionstorage::Value for AuthRequest {
+    fn get_key() -> &'static str {
+        "auth_request"
+    }
+    fn into_raw(self) -> String {
+        if self.state.is_empty() {
+            return "".to_string();
+        }
+        format!("{}:{}:{}", self.state, self.code_verifier, self.nonce)
+    }
+    fn from_raw(value: String) -> Option<Self> {
+        let v: Vec<&str> = value.split(":").collect();
+        if v.len() != 3 || v[0].is_empty() {
+            None
+        } else {
+            Some(AuthRequest {
+                state: v[0].to_string(),
+                code_verifier: v[1].to_string(),
+                nonce: v[2].to_string(),
+            })
+        }
+    }
+}
 
-    let google_client_id = env::CONFIG.team_google_client_id.as_str();
-    let google_client_secret = env::CONFIG.team_google_client_secret.as_str();
-    let auth_url = "https://accounts.google.com/o/oauth2/v2/auth";
-    let token_url = "https://www.googleapis.com/oauth2/v3/token";
-    let mut config = Config::new(google_client_id, google_client_secret, auth_url, token_url);
-    // config = config.add_scope("https://www.googleapis.com/auth/plus.me");
-    config = config.add_scope("https://www.googleapis.com/auth/userinfo.email");
-    config = config.set_redirect_url(env::CONFIG.team_google_redirect_url.as_str());
-    config = config.set_state("S5nHXBzfeJBWmE9CmzLKLaFxfjwxqdvAyHPnFnS9");
-    let authorize_url = config.authorize_url();
+fn auth_error_page(st: status::Status, message: &str) -> IronResult<Response> {
+    #[derive(Serialize, Debug)]
+    struct Data {
+        message: String,
+    }
+    let data = Data {
+        message: message.to_string(),
+    };
+    let mut resp = Response::new();
+    resp.set_mut(Template::new("account/auth_error", to_json(&data)))
+        .set_mut(st);
+    return Ok(resp);
+}
 
+pub fn get_auth_google_handler(req: &mut Request) -> IronResult<Response> {
     let code: String;
+    let state: String;
+    let error: String;
     {
         use params::{Params, Value};
         let map = req.get_ref::<Params>().unwrap();
@@ -663,65 +1339,229 @@ pub fn get_auth_google_handler(req: &mut Request) -> IronResult<Response> {
             }
             _ => code = "".to_string(),
         }
+        match map.get("state") {
+            Some(&Value::String(ref name)) => {
+                state = name.to_string();
+            }
+            _ => state = "".to_string(),
+        }
+        match map.get("error") {
+            Some(&Value::String(ref name)) => {
+                error = name.to_string();
+            }
+            _ => error = "".to_string(),
+        }
+    }
+
+    if error != "" {
+        let _ = req.session().set(AuthRequest{..Default::default()});
+        if error == "access_denied" {
+            return auth_error_page(status::Forbidden, "Google sign-in was cancelled.");
+        }
+        error!("google auth error: {}", error);
+        return auth_error_page(status::BadRequest, "Google sign-in failed.");
     }
 
     if code == "" {
-        let url = Url::parse(&format!("{}",authorize_url).to_string()).unwrap();
+        let auth = AuthRequest {
+            state: helper::random_token(),
+            code_verifier: helper::random_token(),
+            nonce: helper::random_token(),
+        };
+        let google_client_id = env::CONFIG.team_google_client_id.as_str();
+        let google_client_secret = env::CONFIG.team_google_client_secret.as_str();
+        let mut config = Config::new(google_client_id, google_client_secret, helper::GOOGLE_AUTH_URL, helper::GOOGLE_TOKEN_URL);
+        config = config.add_scope("openid");
+        config = config.add_scope("email");
+        config = config.set_redirect_url(env::CONFIG.team_google_redirect_url.as_str());
+        config = config.set_state(auth.state.as_str());
+        let mut authorize_url = config.authorize_url();
+        authorize_url.query_pairs_mut()
+            .append_pair("code_challenge", &helper::pkce_challenge(&auth.code_verifier))
+            .append_pair("code_challenge_method", "S256")
+            .append_pair("nonce", &auth.nonce);
+        try!(req.session().set(auth));
+        let url = Url::parse(&format!("{}", authorize_url).to_string()).unwrap();
         return Ok(Response::with((status::Found, Redirect(url))));
-    } else {
-        let result = config.exchange_code(code);
-        match result {
-            Ok(token) => {
-                let allow_domain = env::CONFIG.team_google_allow_domain.as_str();
-                let email = helper::get_google_email(token.access_token.to_string());
-                let v: Vec<&str> = email.as_str().split("@").collect();
-                let username = v[0].to_string();
-                let domain = v[1];
-                if allow_domain != domain && allow_domain != "" {
-                    return Ok(Response::with((status::InternalServerError, "domain error")));
-                }
+    }
+
+    // The state is single use, whatever the outcome of this callback.
+    let auth = match req.session().get::<AuthRequest>().ok().and_then(|x| x) {
+        Some(auth) => auth,
+        None => return auth_error_page(status::BadRequest, "Your sign-in session has expired. Please try again."),
+    };
+    try!(req.session().set(AuthRequest{..Default::default()}));
+    if state.len() != auth.state.len() || !fixed_time_eq(state.as_bytes(), auth.state.as_bytes()) {
+        return auth_error_page(status::BadRequest, "Invalid sign-in request. Please try again.");
+    }
+
+    let token = match helper::exchange_google_code(&code, &auth.code_verifier) {
+        Ok(token) => token,
+        Err(e) => {
+            error!("error: {}", e);
+            return auth_error_page(status::BadRequest, "Google sign-in failed.");
+        }
+    };
+    let info = match helper::get_google_id_token_info(&token.id_token) {
+        Ok(info) => info,
+        Err(e) => {
+            error!("error: {}", e);
+            return auth_error_page(status::BadRequest, "Google sign-in failed.");
+        }
+    };
+    if info.aud != env::CONFIG.team_google_client_id || info.nonce != auth.nonce {
+        return auth_error_page(status::BadRequest, "Invalid sign-in response. Please try again.");
+    }
+    if info.email_verified != "true" {
+        return auth_error_page(status::Forbidden, "Your Google email address is not verified.");
+    }
+
+    let email = info.email;
+    let (username, domain) = match email.rfind("@") {
+        Some(i) if i > 0 => (email[..i].to_string(), email[i + 1..].to_string()),
+        _ => return auth_error_page(status::BadRequest, "Google did not return a valid email address."),
+    };
+    if !helper::is_allowed_google_domain(&domain) {
+        return auth_error_page(status::Forbidden, &format!("Accounts from {} are not allowed to sign in.", domain));
+    }
+
+    return sign_in_with_email(req, &email, &username, "");
+}
 
-                let ref_url = req.session().get::<RefUrl>().ok().and_then(|x| x)
-                    .unwrap_or(RefUrl { url: "".to_string() });
-                let url = Url::parse(&format!("{}", ref_url.url)
-                    .to_string())
-                    .unwrap_or(helper::redirect_url("/"));
-
-                let conn = get_pg_connection!(req);
-                let user: models::user::UserWithEmail;
-                match models::user::get_by_email(&conn, &email) {
-                    Ok(user_db) => {
-                        user = user_db;
-                        println!("{:?}", user);
-                    }
-                    Err(e) => {
-                        error!("Errored: {:?}", e);
-                        return Ok(Response::with(status::InternalServerError));
-                    }
+// Signs in the user owning `email`, creating the account on first login.
+fn sign_in_with_email(req: &mut Request, email: &str, username: &str, icon_url: &str) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let user: models::user::UserWithEmail;
+    match models::user::get_by_email(&conn, email) {
+        Ok(user_db) => {
+            user = user_db;
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+    if user.username == "" {
+        let invite_id = match invite_id(req, &conn) {
+            Ok(id) => id,
+            Err(e) => {
+                error!("Errored: {:?}", e);
+                return Ok(Response::with(status::InternalServerError));
+            }
+        };
+        if let Err(message) = check_signup(&conn, &invite_id, email) {
+            return auth_error_page(status::Forbidden, &message);
+        }
+        let username = match models::user::available_username(&conn, username) {
+            Ok(name) => name,
+            Err(e) => {
+                error!("Errored: {:?}", e);
+                return Ok(Response::with(status::InternalServerError));
+            }
+        };
+        match models::user::create_with_email(&conn, &username, &email.to_string(), &invite_id) {
+            Ok(Some(user_id)) => {
+                if invite_id.is_some() {
+                    let _ = req.session().set(Invitation { token: "".to_string() });
                 }
-                if user.username == "" {
-                    match models::user::create_with_email(&conn, &username, &email) {
-                        Ok(user_id) => {
-                            try!(req.session().set(Login { id: user_id.to_string() }));
-                            return Ok(Response::with((status::Found, Redirect(url))));
-                        }
-                        Err(e) => {
-                            info!("Errored: {:?}", e);
-                            return Ok(Response::with(status::InternalServerError));
-                        }
-                    }
-                } else {
-                    try!(req.session().set(Login { id: user.id.to_string() }));
-                    return Ok(Response::with((status::Found, Redirect(url))));
+                if icon_url != "" {
+                    let _ = models::user::update_icon_url(&conn, &user_id, &icon_url.to_string());
                 }
+                return complete_sign_in(req, &conn, &user_id);
             }
-            Err(err) => {
-                error!("error: {}", err);
+            Ok(None) => return auth_error_page(status::Forbidden, "That invitation is no longer valid."),
+            Err(e) => {
+                info!("Errored: {:?}", e);
+                return Ok(Response::with(status::InternalServerError));
             }
         }
+    } else {
+        if icon_url != "" && user.icon_url.is_none() {
+            let _ = models::user::update_icon_url(&conn, &user.id, &icon_url.to_string());
+        }
+        return complete_sign_in(req, &conn, &user.id);
+    }
+}
+
+pub fn get_auth_oidc_handler(req: &mut Request) -> IronResult<Response> {
+    if !oidc::enabled() {
+        return Ok(Response::with(status::NotFound));
+    }
+
+    let code: String;
+    let state: String;
+    let error: String;
+    {
+        use params::{Params, Value};
+        let map = req.get_ref::<Params>().unwrap();
+        match map.get("code") {
+            Some(&Value::String(ref name)) => {
+                code = name.to_string();
+            }
+            _ => code = "".to_string(),
+        }
+        match map.get("state") {
+            Some(&Value::String(ref name)) => {
+                state = name.to_string();
+            }
+            _ => state = "".to_string(),
+        }
+        match map.get("error") {
+            Some(&Value::String(ref name)) => {
+                error = name.to_string();
+            }
+            _ => error = "".to_string(),
+        }
+    }
+
+    let provider = &env::CONFIG.team_oidc_name;
+
+    if error != "" {
+        let _ = req.session().set(AuthRequest{..Default::default()});
+        if error == "access_denied" {
+            return auth_error_page(status::Forbidden, &format!("{} sign-in was cancelled.", provider));
+        }
+        error!("oidc auth error: {}", error);
+        return auth_error_page(status::BadRequest, &format!("{} sign-in failed.", provider));
+    }
+
+    if code == "" {
+        let auth = AuthRequest {
+            state: helper::random_token(),
+            code_verifier: helper::random_token(),
+            nonce: helper::random_token(),
+        };
+        let authorize_url = match oidc::authorize_url(&auth.state, &auth.code_verifier, &auth.nonce) {
+            Ok(url) => url,
+            Err(e) => {
+                error!("error: {}", e);
+                return auth_error_page(status::InternalServerError, &format!("{} is not reachable.", provider));
+            }
+        };
+        try!(req.session().set(auth));
+        let url = Url::parse(&format!("{}", authorize_url).to_string()).unwrap();
+        return Ok(Response::with((status::Found, Redirect(url))));
+    }
+
+    let auth = match req.session().get::<AuthRequest>().ok().and_then(|x| x) {
+        Some(auth) => auth,
+        None => return auth_error_page(status::BadRequest, "Your sign-in session has expired. Please try again."),
     };
+    try!(req.session().set(AuthRequest{..Default::default()}));
+    if state.len() != auth.state.len() || !fixed_time_eq(state.as_bytes(), auth.state.as_bytes()) {
+        return auth_error_page(status::BadRequest, "Invalid sign-in request. Please try again.");
+    }
 
-    return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    let identity = match oidc::exchange_code(&code, &auth.code_verifier)
+        .and_then(|token| oidc::identity(&token, &auth.nonce)) {
+        Ok(identity) => identity,
+        Err(e) => {
+            error!("error: {}", e);
+            return auth_error_page(status::BadRequest, &format!("{} sign-in failed.", provider));
+        }
+    };
+
+    return sign_in_with_email(req, &identity.email, &identity.username, &identity.avatar_url);
 }
 
 pub fn post_preference_menu(req: &mut Request) -> IronResult<Response> {
@@ -0,0 +1,773 @@
+use iron::{Request, status};
+use iron::modifiers::Redirect;
+use iron::prelude::IronResult;
+use iron::prelude::*;
+use iron::mime::Mime;
+use router::Router;
+use hbs::Template;
+use persistent;
+use hbs::handlebars::to_json;
+use chrono::NaiveDate;
+use url::form_urlencoded;
+
+use db;
+use models;
+use helper;
+use handlers;
+
+pub const PAGINATES_PER: i32 = 20;
+
+fn current_user(req: &mut Request, conn: &db::PostgresConnection) -> models::user::UserWithPreference {
+    let mut login_user: models::user::UserWithPreference = models::user::UserWithPreference{..Default::default()};
+    match handlers::account::current_user(req, conn) {
+        Ok(user) => { login_user = user; }
+        Err(e) => { error!("Errored: {:?}", e); }
+    }
+    login_user
+}
+
+// Audit targets name users as `user:<id>` so they survive renames.
+fn user_target(id: &i32) -> String {
+    format!("user:{}", id)
+}
+
+fn id_param(req: &mut Request) -> Option<i32> {
+    req.extensions
+        .get::<Router>()
+        .unwrap()
+        .find("id")
+        .unwrap_or("/")
+        .parse::<i32>()
+        .ok()
+}
+
+pub fn users_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let login_user = current_user(req, &conn);
+    if login_user.id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+    if !login_user.is_admin {
+        return Ok(Response::with(status::Forbidden));
+    }
+
+    #[derive(Serialize)]
+    struct Data {
+        logged_in: bool,
+        login_user: models::user::UserWithPreference,
+        users: Vec<models::user::UserWithRole>,
+        roles: Vec<String>,
+    }
+
+    match models::user::list_with_role(&conn) {
+        Ok(users) => {
+            let data = Data {
+                logged_in: true,
+                login_user: login_user,
+                users: users,
+                roles: models::user::ROLES.iter().map(|r| r.to_string()).collect(),
+            };
+            let mut resp = Response::new();
+            resp.set_mut(Template::new("admin/users", to_json(&data)))
+                .set_mut(status::Ok);
+            return Ok(resp);
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+}
+
+pub fn role_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let login_user = current_user(req, &conn);
+    if login_user.id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+    if !login_user.is_admin {
+        return Ok(Response::with(status::Forbidden));
+    }
+
+    let id = match id_param(req) {
+        Some(id) => id,
+        None => return Ok(Response::with(status::BadRequest)),
+    };
+
+    let role: String;
+    {
+        use params::Params;
+        let map = &req.get_ref::<Params>().unwrap();
+        match helper::get_param(map, "role") {
+            Ok(value) => role = value,
+            Err(st) => return Ok(Response::with(st)),
+        }
+    }
+    if !models::user::ROLES.contains(&role.as_str()) {
+        return Ok(Response::with(status::BadRequest));
+    }
+
+    // Never leave the team without an admin.
+    if role != models::user::ROLE_ADMIN {
+        match (models::user::get_role(&conn, &id), models::user::admin_count(&conn)) {
+            (Ok(current), Ok(count)) => {
+                if current == models::user::ROLE_ADMIN && count <= 1 {
+                    return Ok(Response::with((status::BadRequest, "cannot demote the last admin")));
+                }
+            }
+            (Err(e), _) | (_, Err(e)) => {
+                error!("Errored: {:?}", e);
+                return Ok(Response::with(status::InternalServerError));
+            }
+        }
+    }
+
+    match models::user::update_role(&conn, &id, &role) {
+        Ok(_) => {
+            helper::audit(req, &conn, &login_user.id, "admin.role", &user_target(&id), &role);
+            return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/admin")))));
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+}
+
+pub fn logout_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let login_user = current_user(req, &conn);
+    if login_user.id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+    if !login_user.is_admin {
+        return Ok(Response::with(status::Forbidden));
+    }
+
+    let id = match id_param(req) {
+        Some(id) => id,
+        None => return Ok(Response::with(status::BadRequest)),
+    };
+
+    match models::session::delete_by_user_id(&conn, &id) {
+        Ok(_) => {
+            helper::audit(req, &conn, &login_user.id, "admin.logout", &user_target(&id), "");
+            return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/admin")))));
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+}
+
+fn set_deactivated(req: &mut Request, deactivated: bool) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let login_user = current_user(req, &conn);
+    if login_user.id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+    if !login_user.is_admin {
+        return Ok(Response::with(status::Forbidden));
+    }
+
+    let id = match id_param(req) {
+        Some(id) => id,
+        None => return Ok(Response::with(status::BadRequest)),
+    };
+
+    if deactivated {
+        if id == login_user.id {
+            return Ok(Response::with((status::BadRequest, "cannot deactivate yourself")));
+        }
+        match (models::user::get_role(&conn, &id), models::user::admin_count(&conn)) {
+            (Ok(current), Ok(count)) => {
+                if current == models::user::ROLE_ADMIN && count <= 1 {
+                    return Ok(Response::with((status::BadRequest, "cannot deactivate the last admin")));
+                }
+            }
+            (Err(e), _) | (_, Err(e)) => {
+                error!("Errored: {:?}", e);
+                return Ok(Response::with(status::InternalServerError));
+            }
+        }
+    }
+
+    // Deactivation also ends every session the user still has open.
+    let mut result = models::user::set_deactivated(&conn, &id, &deactivated);
+    if deactivated {
+        result = result.and_then(|_| models::session::delete_by_user_id(&conn, &id));
+    }
+    match result {
+        Ok(_) => {
+            let action = if deactivated { "admin.deactivate" } else { "admin.reactivate" };
+            helper::audit(req, &conn, &login_user.id, action, &user_target(&id), "");
+            return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/admin")))));
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+}
+
+pub fn deactivate_handler(req: &mut Request) -> IronResult<Response> {
+    set_deactivated(req, true)
+}
+
+pub fn reactivate_handler(req: &mut Request) -> IronResult<Response> {
+    set_deactivated(req, false)
+}
+
+pub fn transfer_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let login_user = current_user(req, &conn);
+    if login_user.id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+    if !login_user.is_admin {
+        return Ok(Response::with(status::Forbidden));
+    }
+
+    let id = match id_param(req) {
+        Some(id) => id,
+        None => return Ok(Response::with(status::BadRequest)),
+    };
+
+    let to_user_id: i32;
+    {
+        use params::Params;
+        let map = &req.get_ref::<Params>().unwrap();
+        match helper::get_param(map, "to_user_id") {
+            Ok(value) => {
+                match value.parse::<i32>() {
+                    Ok(value) => to_user_id = value,
+                    Err(_) => return Ok(Response::with(status::BadRequest)),
+                }
+            }
+            Err(st) => return Ok(Response::with(st)),
+        }
+    }
+    if to_user_id == id {
+        return Ok(Response::with(status::BadRequest));
+    }
+    // Content only moves away from someone who has left.
+    match models::user::is_deactivated(&conn, &id) {
+        Ok(true) => {}
+        Ok(false) => return Ok(Response::with((status::BadRequest, "only a deactivated user's content can be transferred"))),
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+    match models::user::get_by_id(&conn, &to_user_id) {
+        Ok(user) => {
+            if user.id == 0 || user.deactivated {
+                return Ok(Response::with((status::BadRequest, "cannot transfer to that user")));
+            }
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+
+    match models::user::transfer_content(&conn, &id, &to_user_id) {
+        Ok((posts, gists)) => {
+            info!("Transferred {} posts and {} gists from user {} to user {}", posts, gists, id, to_user_id);
+            helper::audit(req, &conn, &login_user.id, "admin.transfer", &user_target(&id),
+                          &format!("{} posts and {} gists to {}", posts, gists, user_target(&to_user_id)));
+            return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/admin")))));
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+}
+
+pub fn pins_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let login_user = current_user(req, &conn);
+    if login_user.id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+    if !login_user.is_admin {
+        return Ok(Response::with(status::Forbidden));
+    }
+
+    let page_param: String;
+    {
+        use params::{Params, Value};
+        let map = req.get_ref::<Params>().unwrap();
+        match map.get("page") {
+            Some(&Value::String(ref name)) => {
+                page_param = name.to_string();
+            }
+            _ => page_param = "1".to_string(),
+        }
+    }
+
+    #[derive(Serialize)]
+    struct Data {
+        logged_in: bool,
+        login_user: models::user::UserWithPreference,
+        pinneds: Vec<models::post::Post>,
+        current_page: i32,
+        total_page: i32,
+        next_page: i32,
+        prev_page: i32,
+    }
+
+    let mut page = page_param.parse::<i32>().unwrap_or(1);
+    if page <= 0 {
+        page = 1;
+    }
+    let offset = (page - 1) * PAGINATES_PER;
+    let limit = PAGINATES_PER;
+
+    let pinneds: Vec<models::post::Post>;
+    let count: i32;
+
+    match models::post::pinned_list(&conn, &offset, &limit) {
+        Ok(pinneds_db) => {
+            pinneds = pinneds_db;
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+
+    match models::post::pinned_count(&conn) {
+        Ok(count_db) => {
+            count = count_db;
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+
+    let data = Data {
+        logged_in: true,
+        login_user: login_user,
+        pinneds: pinneds,
+        current_page: page,
+        total_page: count / PAGINATES_PER + 1,
+        next_page: page + 1,
+        prev_page: page - 1,
+    };
+    let mut resp = Response::new();
+    resp.set_mut(Template::new("admin/pins", to_json(&data)))
+        .set_mut(status::Ok);
+    return Ok(resp);
+}
+
+pub fn unpin_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let login_user = current_user(req, &conn);
+    if login_user.id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+    if !login_user.is_admin {
+        return Ok(Response::with(status::Forbidden));
+    }
+
+    let id = match id_param(req) {
+        Some(id) => id,
+        None => return Ok(Response::with(status::BadRequest)),
+    };
+
+    match models::post::unpin_post(&conn, &id) {
+        Ok(_) => {
+            helper::audit(req, &conn, &login_user.id, "admin.unpin", &format!("/post/show/{}", id), "");
+            return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/admin/pins")))));
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+}
+
+pub fn get_settings_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let login_user = current_user(req, &conn);
+    if login_user.id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+    if !login_user.is_admin {
+        return Ok(Response::with(status::Forbidden));
+    }
+
+    #[derive(Serialize)]
+    struct Data {
+        logged_in: bool,
+        login_user: models::user::UserWithPreference,
+        require_2fa: bool,
+        signup_mode: String,
+        signup_modes: Vec<String>,
+        signup_domains: String,
+    }
+
+    let require_2fa: bool;
+    let signup_mode: String;
+    let signup_domains: Vec<String>;
+
+    match models::setting::get_bool(&conn, models::setting::REQUIRE_2FA) {
+        Ok(value) => require_2fa = value,
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+    match models::setting::signup_mode(&conn) {
+        Ok(value) => signup_mode = value,
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+    match models::setting::signup_domains(&conn) {
+        Ok(value) => signup_domains = value,
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+
+    let data = Data {
+        logged_in: true,
+        login_user: login_user,
+        require_2fa: require_2fa,
+        signup_mode: signup_mode,
+        signup_modes: models::setting::SIGNUP_MODES.iter().map(|m| m.to_string()).collect(),
+        signup_domains: signup_domains.join(","),
+    };
+    let mut resp = Response::new();
+    resp.set_mut(Template::new("admin/settings", to_json(&data)))
+        .set_mut(status::Ok);
+    return Ok(resp);
+}
+
+pub fn post_settings_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let login_user = current_user(req, &conn);
+    if login_user.id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+    if !login_user.is_admin {
+        return Ok(Response::with(status::Forbidden));
+    }
+
+    let require_2fa: bool;
+    let signup_mode: String;
+    let signup_domains: String;
+    {
+        use params::Params;
+        let map = &req.get_ref::<Params>().unwrap();
+        // Unchecked checkboxes are not submitted at all.
+        require_2fa = helper::get_param(map, "require_2fa").is_ok();
+        match helper::get_param(map, "signup_mode") {
+            Ok(value) => signup_mode = value,
+            Err(st) => return Ok(Response::with(st)),
+        }
+        signup_domains = helper::get_param(map, "signup_domains").unwrap_or("".to_string());
+    }
+    if !models::setting::SIGNUP_MODES.contains(&signup_mode.as_str()) {
+        return Ok(Response::with(status::BadRequest));
+    }
+
+    let result = models::setting::set(&conn, models::setting::REQUIRE_2FA, &require_2fa.to_string())
+        .and_then(|_| models::setting::set(&conn, models::setting::SIGNUP_MODE, &signup_mode))
+        .and_then(|_| models::setting::set(&conn, models::setting::SIGNUP_DOMAINS, &signup_domains));
+    match result {
+        Ok(_) => {
+            helper::audit(req, &conn, &login_user.id, "admin.settings", "settings",
+                          &format!("require_2fa={} signup_mode={} signup_domains={}", require_2fa, signup_mode, signup_domains));
+            return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/admin/settings")))));
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+}
+
+pub fn invites_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let login_user = current_user(req, &conn);
+    if login_user.id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+    if !login_user.is_admin {
+        return Ok(Response::with(status::Forbidden));
+    }
+    render_invites(&conn, login_user, "")
+}
+
+fn render_invites(conn: &db::PostgresConnection, login_user: models::user::UserWithPreference, invite_url: &str) -> IronResult<Response> {
+    #[derive(Serialize)]
+    struct Data {
+        logged_in: bool,
+        login_user: models::user::UserWithPreference,
+        invites: Vec<models::invite::Invite>,
+        invite_url: String,
+    }
+
+    match models::invite::list(conn) {
+        Ok(invites) => {
+            let data = Data {
+                logged_in: true,
+                login_user: login_user,
+                invites: invites,
+                invite_url: invite_url.to_string(),
+            };
+            let mut resp = Response::new();
+            resp.set_mut(Template::new("admin/invites", to_json(&data)))
+                .set_mut(status::Ok);
+            return Ok(resp);
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+}
+
+pub fn create_invite_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let login_user = current_user(req, &conn);
+    if login_user.id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+    if !login_user.is_admin {
+        return Ok(Response::with(status::Forbidden));
+    }
+
+    let days: i32;
+    let max_uses: i32;
+    {
+        use params::Params;
+        let map = &req.get_ref::<Params>().unwrap();
+        days = helper::get_param(map, "days").ok().and_then(|v| v.parse::<i32>().ok()).unwrap_or(7);
+        max_uses = helper::get_param(map, "max_uses").ok().and_then(|v| v.parse::<i32>().ok()).unwrap_or(1);
+    }
+    if days <= 0 || max_uses <= 0 {
+        return Ok(Response::with(status::BadRequest));
+    }
+
+    // The token is only shown once; the database keeps its hash.
+    let token = helper::random_token();
+    match models::invite::create(&conn, &login_user.id, &token, &days, &max_uses) {
+        Ok(invite_id) => {
+            helper::audit(req, &conn, &login_user.id, "admin.invite_create", &format!("invite:{}", invite_id),
+                          &format!("{} days, {} uses", days, max_uses));
+            let url = helper::redirect_url(&format!("/signup?invite={}", token));
+            render_invites(&conn, login_user, &url.to_string())
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+}
+
+pub fn delete_invite_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let login_user = current_user(req, &conn);
+    if login_user.id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+    if !login_user.is_admin {
+        return Ok(Response::with(status::Forbidden));
+    }
+
+    let id = match id_param(req) {
+        Some(id) => id,
+        None => return Ok(Response::with(status::BadRequest)),
+    };
+
+    match models::invite::delete(&conn, &id) {
+        Ok(_) => {
+            helper::audit(req, &conn, &login_user.id, "admin.invite_delete", &format!("invite:{}", id), "");
+            return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/admin/invites")))));
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+}
+
+// Dates that do not parse are dropped rather than rejected, like an empty field.
+fn audit_filter(req: &mut Request) -> (models::audit::Filter, String) {
+    use params::{Params, Value};
+    let map = req.get_ref::<Params>().unwrap();
+    let param = |name: &str| match map.get(name) {
+        Some(&Value::String(ref value)) => value.trim().to_string(),
+        _ => "".to_string(),
+    };
+    let date = |name: &str| {
+        let value = param(name);
+        if NaiveDate::parse_from_str(&value, "%Y-%m-%d").is_ok() { value } else { "".to_string() }
+    };
+    let filter = models::audit::Filter {
+        action: param("action"),
+        username: param("username"),
+        from: date("from"),
+        to: date("to"),
+    };
+    let query = form_urlencoded::Serializer::new(String::new())
+        .append_pair("action", &filter.action)
+        .append_pair("username", &filter.username)
+        .append_pair("from", &filter.from)
+        .append_pair("to", &filter.to)
+        .finish();
+    (filter, query)
+}
+
+pub fn audit_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let login_user = current_user(req, &conn);
+    if login_user.id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+    if !login_user.is_admin {
+        return Ok(Response::with(status::Forbidden));
+    }
+
+    let page_param: String;
+    {
+        use params::{Params, Value};
+        let map = req.get_ref::<Params>().unwrap();
+        match map.get("page") {
+            Some(&Value::String(ref name)) => {
+                page_param = name.to_string();
+            }
+            _ => page_param = "1".to_string(),
+        }
+    }
+    let (filter, query) = audit_filter(req);
+
+    #[derive(Serialize)]
+    struct Action {
+        name: String,
+        selected: bool,
+    }
+
+    #[derive(Serialize)]
+    struct Data {
+        logged_in: bool,
+        login_user: models::user::UserWithPreference,
+        events: Vec<models::audit::Event>,
+        actions: Vec<Action>,
+        username: String,
+        from: String,
+        to: String,
+        query: String,
+        current_page: i32,
+        total_page: i32,
+        next_page: i32,
+        prev_page: i32,
+    }
+
+    let mut page = page_param.parse::<i32>().unwrap_or(1);
+    if page <= 0 {
+        page = 1;
+    }
+    let offset = (page - 1) * PAGINATES_PER;
+    let limit = PAGINATES_PER;
+
+    let events = match models::audit::list(&conn, &filter, &offset, &limit) {
+        Ok(events) => events,
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    };
+    let count = match models::audit::count(&conn, &filter) {
+        Ok(count) => count,
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    };
+
+    let data = Data {
+        logged_in: true,
+        login_user: login_user,
+        events: events,
+        actions: models::audit::ACTIONS.iter()
+            .map(|a| Action { name: a.to_string(), selected: *a == filter.action })
+            .collect(),
+        username: filter.username,
+        from: filter.from,
+        to: filter.to,
+        query: query,
+        current_page: page,
+        total_page: count / PAGINATES_PER + 1,
+        next_page: page + 1,
+        prev_page: page - 1,
+    };
+    let mut resp = Response::new();
+    resp.set_mut(Template::new("admin/audit", to_json(&data)))
+        .set_mut(status::Ok);
+    return Ok(resp);
+}
+
+const AUDIT_EXPORT_LIMIT: i32 = 100000;
+
+// Quotes a CSV field, and defuses values a spreadsheet would read as a formula.
+fn csv_field(value: &str) -> String {
+    let mut value = value.to_string();
+    if value.starts_with("=") || value.starts_with("+") || value.starts_with("-") || value.starts_with("@") {
+        value = format!("'{}", value);
+    }
+    if value.contains(",") || value.contains("\"") || value.contains("\n") || value.contains("\r") {
+        value = format!("\"{}\"", value.replace("\"", "\"\""));
+    }
+    value
+}
+
+pub fn audit_export_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let login_user = current_user(req, &conn);
+    if login_user.id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+    if !login_user.is_admin {
+        return Ok(Response::with(status::Forbidden));
+    }
+
+    let (filter, _) = audit_filter(req);
+    match models::audit::list(&conn, &filter, &0, &AUDIT_EXPORT_LIMIT) {
+        Ok(events) => {
+            let mut csv = String::from("id,created,username,action,target,detail,ip\n");
+            for event in &events {
+                let fields = vec![
+                    event.id.to_string(),
+                    event.created.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+                    event.user.username.to_string(),
+                    event.action.to_string(),
+                    event.target.to_string(),
+                    event.detail.to_string(),
+                    event.ip.to_string(),
+                ];
+                let fields: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+                csv.push_str(&fields.join(","));
+                csv.push_str("\n");
+            }
+            let content_type = "text/csv; charset=utf-8".parse::<Mime>().unwrap();
+            let mut resp = Response::with((content_type, status::Ok, csv));
+            resp.headers.set_raw("Content-Disposition",
+                                 vec![b"attachment; filename=\"audit.csv\"".to_vec()]);
+            return Ok(resp);
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+}
@@ -0,0 +1,386 @@
+use iron::{Request, status};
+use iron::prelude::IronResult;
+use iron::prelude::*;
+use iron::headers::{Authorization, Bearer};
+use iron::mime::Mime;
+use router::Router;
+use persistent;
+use hbs::handlebars::to_json;
+use params::{Params, Value};
+use serde::Serialize;
+use url::percent_encoding::percent_decode;
+
+use db;
+use models;
+use helper;
+use handlers;
+use env::CONFIG;
+
+pub const PAGINATES_PER: i32 = 30;
+
+#[derive(Serialize)]
+struct ApiFile {
+    filename: String,
+    language: String,
+    // Empty when the language comes from the extension; sent back as `language` to keep it.
+    language_override: String,
+    raw_url: String,
+    // Left out of listings to keep them small.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ApiGist {
+    id: i32,
+    owner: String,
+    description: String,
+    visibility: String,
+    tags: Vec<String>,
+    files: Vec<ApiFile>,
+    created: String,
+    html_url: String,
+}
+
+fn api_gist(gist: models::gist::Gist, with_content: bool) -> ApiGist {
+    let id = gist.id;
+    let share_query = helper::share_query(gist.link_token());
+    ApiGist {
+        id: id,
+        owner: gist.user.username,
+        description: gist.description,
+        visibility: gist.visibility,
+        tags: gist.tags.into_iter().map(|t| t.name).collect(),
+        files: gist.files.into_iter().map(|f| ApiFile {
+            raw_url: format!("{}/api/gists/{}/raw/{}{}", CONFIG.team_domain, id, f.url_filename, share_query),
+            filename: f.filename,
+            language: f.language,
+            language_override: f.language_override,
+            content: if with_content { Some(f.code) } else { None },
+        }).collect(),
+        created: gist.formated_created,
+        html_url: format!("{}/gist/show/{}{}", CONFIG.team_domain, id, share_query),
+    }
+}
+
+fn json<T: Serialize>(status: status::Status, data: &T) -> Response {
+    let content_type = "application/json".parse::<Mime>().unwrap();
+    Response::with((content_type, status, to_json(data).to_string()))
+}
+
+fn json_error(status: status::Status, message: &str) -> Response {
+    #[derive(Serialize)]
+    struct Error<'a> {
+        error: &'a str,
+    }
+    json(status, &Error { error: message })
+}
+
+fn internal_error<E: ::std::fmt::Debug>(e: E) -> Response {
+    error!("Errored: {:?}", e);
+    json_error(status::InternalServerError, "internal error")
+}
+
+// The API never looks at the session: every request carries `Authorization: Bearer <token>`.
+fn api_user(req: &Request, conn: &db::PostgresConnection) -> Result<models::user::UserWithPreference, Response> {
+    let token = match req.headers.get::<Authorization<Bearer>>() {
+        Some(&Authorization(Bearer { ref token })) => token.to_string(),
+        None => return Err(json_error(status::Unauthorized, "missing API token")),
+    };
+    let user_id = match models::api_token::user_id_for(conn, &token) {
+        Ok(Some(user_id)) => user_id,
+        Ok(None) => return Err(json_error(status::Unauthorized, "invalid API token")),
+        Err(e) => return Err(internal_error(e)),
+    };
+    // The TwoFactor middleware only sees sessions, so the team's 2FA requirement is checked here.
+    match models::setting::get_bool(conn, models::setting::REQUIRE_2FA) {
+        Ok(false) => {}
+        Ok(true) => {
+            match models::totp::get_by_user_id(conn, &user_id) {
+                Ok(ref totp) if totp.enabled => {}
+                Ok(_) => return Err(json_error(status::Forbidden, "two-factor authentication required")),
+                Err(e) => return Err(internal_error(e)),
+            }
+        }
+        Err(e) => return Err(internal_error(e)),
+    }
+    // Deactivated accounts come back with id 0.
+    match models::user::get_current_user(conn, &user_id) {
+        Ok(ref user) if user.id == 0 => Err(json_error(status::Unauthorized, "invalid API token")),
+        Ok(user) => Ok(user),
+        Err(e) => Err(internal_error(e)),
+    }
+}
+
+fn id_param(req: &Request) -> Result<i32, Response> {
+    req.extensions.get::<Router>().unwrap().find("id").unwrap_or("").parse::<i32>()
+        .map_err(|_| json_error(status::BadRequest, "invalid gist id"))
+}
+
+// Loads a gist the user may read, answering 404 for missing and hidden ones alike.
+// `token` is the share token of an unlisted gist, passed as `t` like on the site.
+fn readable_gist(conn: &db::PostgresConnection, id: &i32, user_id: &i32, token: &str) -> Result<models::gist::Gist, Response> {
+    match models::gist::exists(conn, id) {
+        Ok(true) => {}
+        Ok(false) => return Err(json_error(status::NotFound, "gist not found")),
+        Err(e) => return Err(internal_error(e)),
+    }
+    match models::gist::get_by_id(conn, id) {
+        Ok(ref gist) if !gist.visible_to(user_id, token) => Err(json_error(status::NotFound, "gist not found")),
+        Ok(gist) => Ok(gist),
+        Err(e) => Err(internal_error(e)),
+    }
+}
+
+// The body of a create or update. Fields left out are `None`.
+#[derive(Default)]
+struct GistParams {
+    description: Option<String>,
+    visibility: Option<String>,
+    tags: Option<String>,
+    files: Option<Vec<models::gist::File>>,
+}
+
+fn string_field(value: Option<&Value>, name: &str) -> Result<Option<String>, String> {
+    match value {
+        Some(&Value::String(ref value)) => Ok(Some(value.to_string())),
+        Some(&Value::Null) | None => Ok(None),
+        Some(_) => Err(format!("{} must be a string", name)),
+    }
+}
+
+fn gist_params(req: &mut Request) -> Result<GistParams, Response> {
+    let map = match req.get_ref::<Params>() {
+        Ok(map) => map,
+        Err(_) => return Err(json_error(status::BadRequest, "invalid JSON body")),
+    };
+    let bad_request = |message: String| json_error(status::BadRequest, &message);
+    let mut params = GistParams::default();
+    params.description = try!(string_field(map.get("description"), "description").map_err(&bad_request));
+    params.visibility = try!(string_field(map.get("visibility"), "visibility").map_err(&bad_request));
+    if let Some(ref visibility) = params.visibility {
+        if !models::post::is_visibility(visibility) {
+            return Err(json_error(status::BadRequest, "visibility must be public, unlisted or private"));
+        }
+    }
+    params.tags = match map.get("tags") {
+        Some(&Value::Array(ref values)) => {
+            let mut tags: Vec<String> = Vec::new();
+            for value in values {
+                match *value {
+                    Value::String(ref tag) => tags.push(tag.to_string()),
+                    _ => return Err(json_error(status::BadRequest, "tags must be strings")),
+                }
+            }
+            Some(tags.join(","))
+        }
+        Some(&Value::Null) | None => None,
+        Some(_) => return Err(json_error(status::BadRequest, "tags must be a list")),
+    };
+    params.files = match map.get("files") {
+        Some(&Value::Array(ref values)) => {
+            let mut filenames: Vec<String> = Vec::new();
+            let mut codes: Vec<String> = Vec::new();
+            let mut languages: Vec<String> = Vec::new();
+            for value in values {
+                let file = match *value {
+                    Value::Map(ref file) => file,
+                    _ => return Err(json_error(status::BadRequest, "files must be objects")),
+                };
+                filenames.push(try!(string_field(file.get("filename"), "filename").map_err(&bad_request)).unwrap_or("".to_string()));
+                codes.push(try!(string_field(file.get("content"), "content").map_err(&bad_request)).unwrap_or("".to_string()));
+                languages.push(try!(string_field(file.get("language"), "language").map_err(&bad_request)).unwrap_or("".to_string()));
+            }
+            match handlers::gist::build_files(&filenames, &codes, languages) {
+                Ok(files) => Some(files),
+                Err(message) => return Err(json_error(status::BadRequest, message)),
+            }
+        }
+        Some(&Value::Null) | None => None,
+        Some(_) => return Err(json_error(status::BadRequest, "files must be a list")),
+    };
+    Ok(params)
+}
+
+/// GET /api/gists: the caller's own gists, newest first, without file contents.
+pub fn list_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let login_user = match api_user(req, &conn) {
+        Ok(user) => user,
+        Err(resp) => return Ok(resp),
+    };
+
+    let page = {
+        let map = match req.get_ref::<Params>() {
+            Ok(map) => map,
+            Err(_) => return Ok(json_error(status::BadRequest, "invalid parameters")),
+        };
+        match map.get("page") {
+            Some(&Value::String(ref page)) => page.parse::<i32>().unwrap_or(1).max(1),
+            _ => 1,
+        }
+    };
+    let offset = (page - 1) * PAGINATES_PER;
+
+    match models::gist::owned_by(&conn, &login_user.id, &offset, &PAGINATES_PER) {
+        Ok(gists) => {
+            let mut list: Vec<ApiGist> = Vec::new();
+            for mut gist in gists {
+                gist.files = match models::gist::get_files(&conn, &gist.id) {
+                    Ok(files) => files,
+                    Err(e) => return Ok(internal_error(e)),
+                };
+                list.push(api_gist(gist, false));
+            }
+            Ok(json(status::Ok, &list))
+        }
+        Err(e) => Ok(internal_error(e)),
+    }
+}
+
+/// POST /api/gists
+pub fn create_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let login_user = match api_user(req, &conn) {
+        Ok(user) => user,
+        Err(resp) => return Ok(resp),
+    };
+    let login_id = login_user.id;
+    if login_user.is_guest {
+        return Ok(json_error(status::Forbidden, "guests cannot create gists"));
+    }
+
+    let params = match gist_params(req) {
+        Ok(params) => params,
+        Err(resp) => return Ok(resp),
+    };
+    let files = match params.files {
+        Some(files) => files,
+        None => return Ok(json_error(status::BadRequest, "a gist needs at least one file")),
+    };
+    let description = params.description.unwrap_or("".to_string());
+    let visibility = params.visibility.unwrap_or("public".to_string());
+    let tags = params.tags.unwrap_or("".to_string());
+
+    match models::gist::create(&conn, &login_id, &description, &files, &visibility, &tags) {
+        Ok(id) => {
+            if visibility == "public" {
+                let title = String::from("New gist");
+                let path = String::from("gist");
+                let code = handlers::gist::slack_code(&files);
+                helper::post_to_slack(&conn, &login_id, &title, &code, &id, Vec::new(), &path);
+            }
+            match models::gist::get_by_id(&conn, &id) {
+                Ok(gist) => Ok(json(status::Created, &api_gist(gist, true))),
+                Err(e) => Ok(internal_error(e)),
+            }
+        }
+        Err(e) => Ok(internal_error(e)),
+    }
+}
+
+/// GET /api/gists/:id
+pub fn show_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let login_user = match api_user(req, &conn) {
+        Ok(user) => user,
+        Err(resp) => return Ok(resp),
+    };
+    let id = match id_param(req) {
+        Ok(id) => id,
+        Err(resp) => return Ok(resp),
+    };
+
+    let token = helper::share_token_param(req);
+    match readable_gist(&conn, &id, &login_user.id, &token) {
+        Ok(gist) => Ok(json(status::Ok, &api_gist(gist, true))),
+        Err(resp) => Ok(resp),
+    }
+}
+
+/// PUT /api/gists/:id. Fields left out keep their value; `files` replaces the whole file list.
+pub fn update_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let login_user = match api_user(req, &conn) {
+        Ok(user) => user,
+        Err(resp) => return Ok(resp),
+    };
+    let login_id = login_user.id;
+    if login_user.is_guest {
+        return Ok(json_error(status::Forbidden, "guests cannot edit gists"));
+    }
+    let id = match id_param(req) {
+        Ok(id) => id,
+        Err(resp) => return Ok(resp),
+    };
+
+    let token = helper::share_token_param(req);
+    let old_gist = match readable_gist(&conn, &id, &login_id, &token) {
+        Ok(gist) => gist,
+        Err(resp) => return Ok(resp),
+    };
+    if old_gist.user_id != login_id && !login_user.is_admin {
+        return Ok(json_error(status::Forbidden, "only the owner can edit this gist"));
+    }
+
+    let params = match gist_params(req) {
+        Ok(params) => params,
+        Err(resp) => return Ok(resp),
+    };
+    // As on the web, only the owner changes visibility.
+    if params.visibility.is_some() && old_gist.user_id != login_id {
+        return Ok(json_error(status::Forbidden, "only the owner can change visibility"));
+    }
+    let description = params.description.unwrap_or_else(|| old_gist.description.clone());
+    let visibility = params.visibility.unwrap_or_else(|| old_gist.visibility.clone());
+    let tags = params.tags.unwrap_or_else(|| old_gist.tag_names());
+    let files = params.files.unwrap_or_else(|| old_gist.files.clone());
+
+    match models::gist::update(&conn, &id, &login_id, &description, &files, &visibility, &tags) {
+        Ok(_) => {
+            if visibility == "public" {
+                let title = String::from("Edit gist");
+                let path = String::from("gist");
+                let code = handlers::gist::slack_code(&files);
+                helper::post_to_slack(&conn, &login_id, &title, &code, &id, Vec::new(), &path);
+            }
+            match models::gist::get_by_id(&conn, &id) {
+                Ok(gist) => Ok(json(status::Ok, &api_gist(gist, true))),
+                Err(e) => Ok(internal_error(e)),
+            }
+        }
+        Err(e) => Ok(internal_error(e)),
+    }
+}
+
+/// GET /api/gists/:id/raw/:filename: the file as plain text, like `/gist/raw`.
+pub fn raw_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let login_user = match api_user(req, &conn) {
+        Ok(user) => user,
+        Err(resp) => return Ok(resp),
+    };
+    let id = match id_param(req) {
+        Ok(id) => id,
+        Err(resp) => return Ok(resp),
+    };
+    let token = helper::share_token_param(req);
+    let gist = match readable_gist(&conn, &id, &login_user.id, &token) {
+        Ok(gist) => gist,
+        Err(resp) => return Ok(resp),
+    };
+
+    let filename = {
+        let param = req.extensions.get::<Router>().unwrap().find("filename").unwrap_or("");
+        percent_decode(param.as_bytes()).decode_utf8_lossy().to_string()
+    };
+    match gist.files.iter().find(|f| f.filename == filename) {
+        Some(file) => {
+            let content_type = "text/plain; charset=utf-8".parse::<Mime>().unwrap();
+            let mut resp = Response::with((content_type, status::Ok, file.code.to_string()));
+            resp.headers.set_raw("X-Content-Type-Options", vec![b"nosniff".to_vec()]);
+            Ok(resp)
+        }
+        None => Ok(json_error(status::NotFound, "file not found")),
+    }
+}
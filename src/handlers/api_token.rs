@@ -0,0 +1,129 @@
+use iron::{Request, status};
+use iron::modifiers::Redirect;
+use iron::prelude::IronResult;
+use iron::prelude::*;
+use router::Router;
+use hbs::Template;
+use persistent;
+use hbs::handlebars::to_json;
+
+use db;
+use models;
+use helper;
+use handlers;
+
+// Renders the token list; `new_token` is the plain token just issued, if any.
+fn render(conn: &db::PostgresConnection, login_user: models::user::UserWithPreference, new_token: String) -> IronResult<Response> {
+    #[derive(Serialize)]
+    struct Data {
+        logged_in: bool,
+        login_user: models::user::UserWithPreference,
+        tokens: Vec<models::api_token::ApiToken>,
+        new_token: String,
+    }
+
+    match models::api_token::list_by_user_id(conn, &login_user.id) {
+        Ok(tokens) => {
+            let data = Data {
+                logged_in: login_user.id != 0,
+                login_user: login_user,
+                tokens: tokens,
+                new_token: new_token,
+            };
+            let mut resp = Response::new();
+            resp.set_mut(Template::new("account/tokens", to_json(&data)))
+                .set_mut(status::Ok);
+            return Ok(resp);
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+}
+
+pub fn list_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let mut login_user: models::user::UserWithPreference = models::user::UserWithPreference{..Default::default()};
+    match handlers::account::current_user(req, &conn) {
+        Ok(user) => { login_user = user; }
+        Err(e) => { error!("Errored: {:?}", e); }
+    }
+    if login_user.id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+
+    render(&conn, login_user, "".to_string())
+}
+
+pub fn create_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let mut login_user: models::user::UserWithPreference = models::user::UserWithPreference{..Default::default()};
+    match handlers::account::current_user(req, &conn) {
+        Ok(user) => { login_user = user; }
+        Err(e) => { error!("Errored: {:?}", e); }
+    }
+    let login_id = login_user.id;
+    if login_id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+
+    let name: String;
+    {
+        use params::Params;
+
+        let map = &req.get_ref::<Params>().unwrap();
+        match helper::get_param(map, "name") {
+            Ok(value) => name = value.trim().to_string(),
+            Err(st) => return Ok(Response::with(st)),
+        }
+    }
+    if name == "" || name.chars().count() > 255 {
+        return Ok(Response::with(status::BadRequest));
+    }
+
+    match models::api_token::create(&conn, &login_id, &name) {
+        Ok(token) => {
+            helper::audit(req, &conn, &login_id, "token.create", &format!("user:{}", login_id), &name);
+            render(&conn, login_user, token)
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+}
+
+pub fn delete_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let mut login_user: models::user::UserWithPreference = models::user::UserWithPreference{..Default::default()};
+    match handlers::account::current_user(req, &conn) {
+        Ok(user) => { login_user = user; }
+        Err(e) => { error!("Errored: {:?}", e); }
+    }
+    let login_id = login_user.id;
+    if login_id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+
+    let id = match req.extensions
+        .get::<Router>()
+        .unwrap()
+        .find("id")
+        .unwrap_or("/")
+        .parse::<i32>() {
+        Ok(id) => id,
+        Err(_) => return Ok(Response::with(status::BadRequest)),
+    };
+
+    match models::api_token::delete(&conn, &id, &login_id) {
+        Ok(_) => {
+            helper::audit(req, &conn, &login_id, "token.delete", &format!("user:{}", login_id), &id.to_string());
+            return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/account/tokens")))));
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use iron::{Request, status};
+use iron::modifiers::Redirect;
+use iron::prelude::IronResult;
+use iron::prelude::*;
+use iron::mime::Mime;
+use persistent;
+use time;
+
+use db;
+use export;
+use helper;
+use handlers;
+use models;
+
+pub fn download_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let mut login_user: models::user::UserWithPreference = models::user::UserWithPreference{..Default::default()};
+    match handlers::account::current_user(req, &conn) {
+        Ok(user) => { login_user = user; }
+        Err(e) => { error!("Errored: {:?}", e); }
+    }
+    let login_id = login_user.id;
+    if login_id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+
+    let now = time::now_utc();
+    let exported = time::strftime("%Y-%m-%dT%H:%M:%SZ", &now).unwrap_or("".to_string());
+    match export::archive(&conn, &login_id, &exported) {
+        Ok(bytes) => {
+            let filename = format!("team-{}-{}.zip", login_user.username.replace("\"", ""),
+                                   time::strftime("%Y%m%d", &now).unwrap_or("".to_string()));
+            let content_type = "application/zip".parse::<Mime>().unwrap();
+            let mut resp = Response::with((content_type, status::Ok, bytes));
+            resp.headers.set_raw("Content-Disposition",
+                                 vec![format!("attachment; filename=\"{}\"", filename).into_bytes()]);
+            return Ok(resp);
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use iron::prelude::*;
+use iron::status;
+use iron::modifiers::Redirect;
+use router::Router;
+use url::form_urlencoded;
+use persistent;
+
+use db;
+use models;
+use helper;
+use handlers;
+
+pub fn follow_user_handler(req: &mut Request) -> IronResult<Response> {
+    set_user_follow(req, true)
+}
+
+pub fn unfollow_user_handler(req: &mut Request) -> IronResult<Response> {
+    set_user_follow(req, false)
+}
+
+fn set_user_follow(req: &mut Request, follow: bool) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let mut login_user: models::user::UserWithPreference = models::user::UserWithPreference{..Default::default()};
+    match handlers::account::current_user(req, &conn) {
+        Ok(user) => { login_user = user; }
+        Err(e) => { error!("Errored: {:?}", e); }
+    }
+    let login_id = login_user.id;
+    if login_id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+
+    let username = req.extensions
+        .get::<Router>()
+        .unwrap()
+        .find("username")
+        .unwrap_or("")
+        .to_string();
+
+    let user = match models::user::get_by_username(&conn, &username) {
+        Ok(user) => user,
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    };
+    if user.id == 0 {
+        return Ok(Response::with(status::NotFound));
+    }
+    if user.id == login_id {
+        return Ok(Response::with(status::BadRequest));
+    }
+
+    let result = if follow {
+        models::follow::follow_user(&conn, &login_id, &user.id)
+    } else {
+        models::follow::unfollow_user(&conn, &login_id, &user.id)
+    };
+    match result {
+        Ok(_) => {
+            Ok(Response::with((status::Found, Redirect(helper::redirect_url(&format!("/{}", username))))))
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            Ok(Response::with(status::InternalServerError))
+        }
+    }
+}
+
+pub fn follow_tag_handler(req: &mut Request) -> IronResult<Response> {
+    set_tag_follow(req, true)
+}
+
+pub fn unfollow_tag_handler(req: &mut Request) -> IronResult<Response> {
+    set_tag_follow(req, false)
+}
+
+fn set_tag_follow(req: &mut Request, follow: bool) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let mut login_user: models::user::UserWithPreference = models::user::UserWithPreference{..Default::default()};
+    match handlers::account::current_user(req, &conn) {
+        Ok(user) => { login_user = user; }
+        Err(e) => { error!("Errored: {:?}", e); }
+    }
+    let login_id = login_user.id;
+    if login_id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+
+    let name: String;
+
+    {
+        use params::Params;
+        let map = &req.get_ref::<Params>().unwrap();
+        match helper::get_param(map, "name") {
+            Ok(value) => name = value,
+            Err(st) => return Ok(Response::with(st)),
+        }
+    }
+    if name.trim() == "" {
+        return Ok(Response::with(status::BadRequest));
+    }
+
+    // Unfollowing a tag nobody has used leaves nothing to do, and must not create it.
+    let tag_id = if follow {
+        models::tag::select_or_create_tag_id(&conn, &name).map(|tag_id| Some(tag_id))
+    } else {
+        models::tag::get_tag_id(&conn, &name)
+    };
+    let tag_id = match tag_id {
+        Ok(tag_id) => tag_id,
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    };
+
+    let result = match tag_id {
+        Some(tag_id) if follow => models::follow::follow_tag(&conn, &login_id, &tag_id),
+        Some(tag_id) => models::follow::unfollow_tag(&conn, &login_id, &tag_id),
+        None => Ok(()),
+    };
+    match result {
+        Ok(_) => {
+            let query: String = form_urlencoded::byte_serialize(name.as_bytes()).collect();
+            Ok(Response::with((status::Found, Redirect(helper::redirect_url(&format!("/tag?name={}", query))))))
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            Ok(Response::with(status::InternalServerError))
+        }
+    }
+}
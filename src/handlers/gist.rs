@@ -3,18 +3,89 @@ use iron::modifiers::Redirect;
 use iron::prelude::IronResult;
 use iron::prelude::*;
 use iron::Url;
+use iron::mime::Mime;
 use router::Router;
 use hbs::Template;
 use persistent;
 use hbs::handlebars::to_json;
+use params::{Map, Value};
+use url::percent_encoding::percent_decode;
 
 use db;
+use export;
 use models;
 use helper;
 use handlers;
 use env::CONFIG;
 
 pub const PAGINATES_PER: i32 = 10;
+// Each revision carries a diff, so the history page shows fewer.
+const REVISIONS_PER_PAGE: i32 = 5;
+
+fn strings_param(map: &Map, name: &str) -> Vec<String> {
+    match map.get(name) {
+        Some(&Value::Array(ref values)) => values.iter().filter_map(|value| match *value {
+            Value::String(ref value) => Some(value.to_string()),
+            _ => None,
+        }).collect(),
+        Some(&Value::String(ref value)) => vec![value.to_string()],
+        _ => Vec::new(),
+    }
+}
+
+// Reads the parallel `gist-filename[]`, `gist-language[]` and `gist-code[]` lists
+// the form posts, one entry per file.
+fn files_param(map: &Map) -> Result<Vec<models::gist::File>, &'static str> {
+    build_files(&strings_param(map, "gist-filename"), &strings_param(map, "gist-code"), strings_param(map, "gist-language"))
+}
+
+/// Checks the files of a gist, shared by the form and the JSON API. Empty files are dropped
+/// and a file without a name is given one.
+pub fn build_files(filenames: &Vec<String>, codes: &Vec<String>, mut languages: Vec<String>) -> Result<Vec<models::gist::File>, &'static str> {
+    if filenames.len() != codes.len() {
+        return Err("filenames and files do not match");
+    }
+    languages.resize(filenames.len(), "".to_string());
+    let names = models::gist::language_names();
+    let mut files: Vec<models::gist::File> = Vec::new();
+    for ((filename, code), language) in filenames.iter().zip(codes.iter()).zip(languages.iter()) {
+        if code.trim() == "" {
+            continue;
+        }
+        let mut filename = filename.trim().replace("/", "_");
+        if filename == "" {
+            filename = format!("gistfile{}.txt", files.len() + 1);
+        }
+        if filename == "." || filename == ".." {
+            return Err("that filename is not allowed");
+        }
+        // Filenames end up in `filename/override` lines, Content-Disposition and zip entries.
+        if filename.chars().any(|c| c.is_control() || c == '\\') {
+            return Err("filenames cannot contain control characters or backslashes");
+        }
+        if filename.chars().count() > 255 {
+            return Err("filename is too long");
+        }
+        if files.iter().any(|f| f.filename == filename) {
+            return Err("filenames must be unique");
+        }
+        if language != "" && !names.contains(&language.as_str()) {
+            return Err("unknown language");
+        }
+        files.push(models::gist::File::new(&filename, code, language));
+    }
+    if files.is_empty() {
+        return Err("a gist needs at least one file");
+    }
+    Ok(files)
+}
+
+pub fn slack_code(files: &Vec<models::gist::File>) -> String {
+    files.iter()
+        .map(|f| format!("{}\n```\n{}\n```", f.filename, f.code))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
 
 pub fn new_handler(req: &mut Request) -> IronResult<Response> {
     let conn = get_pg_connection!(req);
@@ -28,16 +99,26 @@ pub fn new_handler(req: &mut Request) -> IronResult<Response> {
         return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
     }
 
+    if login_user.is_guest {
+        return Ok(Response::with(status::Forbidden));
+    }
+
     let mut resp = Response::new();
 
     #[derive(Serialize)]
     struct Data {
         logged_in: bool,
         login_user: models::user::UserWithPreference,
+        files: Vec<models::gist::File>,
+        languages: Vec<&'static str>,
+        visibilities: Vec<models::post::VisibilityOption>,
     }
     let data = Data {
         logged_in: login_id != 0,
         login_user: login_user,
+        files: vec![models::gist::File::default()],
+        languages: models::gist::language_names(),
+        visibilities: models::post::visibility_options("public"),
     };
     resp.set_mut(helper::template("gist/form", to_json(&data)))
         .set_mut(status::Ok);
@@ -56,9 +137,14 @@ pub fn create_handler(req: &mut Request) -> IronResult<Response> {
         return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
     }
 
+    if login_user.is_guest {
+        return Ok(Response::with(status::Forbidden));
+    }
+
     let description: String;
-    let filename: String;
-    let code: String;
+    let files: Vec<models::gist::File>;
+    let visibility: String;
+    let tags: String;
 
     {
         use params::{Params, Value};
@@ -71,26 +157,38 @@ pub fn create_handler(req: &mut Request) -> IronResult<Response> {
             _ => return Ok(Response::with(status::BadRequest)),
         }
 
-        match map.get("gist-filename") {
+        match files_param(map) {
+            Ok(value) => files = value,
+            Err(message) => return Ok(Response::with((status::BadRequest, message))),
+        }
+
+        match map.get("visibility") {
             Some(&Value::String(ref name)) => {
-                filename = name.to_string();
+                visibility = name.to_string();
             }
-            _ => return Ok(Response::with(status::BadRequest)),
+            _ => visibility = "public".to_string(),
         }
-        match map.get("gist-code") {
+
+        match map.get("tags") {
             Some(&Value::String(ref name)) => {
-                code = name.to_string();
+                tags = name.to_string();
             }
-            _ => return Ok(Response::with(status::BadRequest)),
+            _ => tags = "".to_string(),
         }
     }
 
-    match models::gist::create(&conn, &login_id, &description, &filename, &code) {
+    if !models::post::is_visibility(&visibility) {
+        return Ok(Response::with(status::BadRequest));
+    }
+
+    match models::gist::create(&conn, &login_id, &description, &files, &visibility, &tags) {
         Ok(id) => {
-            let title = String::from("New gist");
-            let path = String::from("gist");
-            let code = &format!("{}{}{}", "```\n", &code, "\n```").to_string();
-            helper::post_to_slack(&conn, &login_id, &title, &code, &id, Vec::new(), &path);
+            if visibility == "public" {
+                let title = String::from("New gist");
+                let path = String::from("gist");
+                let code = slack_code(&files);
+                helper::post_to_slack(&conn, &login_id, &title, &code, &id, Vec::new(), &path);
+            }
             let url = Url::parse(&format!("{}/gist/show/{}", &CONFIG.team_domain, id)
                                      .to_string()).unwrap();
             return Ok(Response::with((status::Found, Redirect(url))));
@@ -116,6 +214,7 @@ pub fn list_handler(req: &mut Request) -> IronResult<Response> {
     }
 
     let page_param: String;
+    let tag_param: String;
 
     {
         use params::{Params, Value};
@@ -126,6 +225,12 @@ pub fn list_handler(req: &mut Request) -> IronResult<Response> {
             }
             _ => page_param = "1".to_string(),
         }
+        match map.get("tag") {
+            Some(&Value::String(ref name)) => {
+                tag_param = name.trim().to_string();
+            }
+            _ => tag_param = "".to_string(),
+        }
     }
 
     let mut resp = Response::new();
@@ -135,6 +240,8 @@ pub fn list_handler(req: &mut Request) -> IronResult<Response> {
         logged_in: bool,
         login_user: models::user::UserWithPreference,
         gists: Vec<models::gist::Gist>,
+        // Set when listing one tag's gists, `/gist/list?tag=name`.
+        tag_name: String,
         current_page: i32,
         total_page: i32,
         next_page: i32,
@@ -151,7 +258,12 @@ pub fn list_handler(req: &mut Request) -> IronResult<Response> {
     let gists: Vec<models::gist::Gist>;
     let count: i32;
 
-    match models::gist::list(&conn, &offset, &limit) {
+    let gists_db = if tag_param == "" {
+        models::gist::list(&conn, &login_id, &offset, &limit)
+    } else {
+        models::gist::tagged(&conn, &tag_param, &login_id, &offset, &limit)
+    };
+    match gists_db {
         Ok(gists_db) => {
             gists = gists_db;
         }
@@ -161,7 +273,12 @@ pub fn list_handler(req: &mut Request) -> IronResult<Response> {
         }
     }
 
-    match models::gist::count(&conn) {
+    let count_db = if tag_param == "" {
+        models::gist::count(&conn, &login_id)
+    } else {
+        models::gist::tagged_count(&conn, &tag_param, &login_id)
+    };
+    match count_db {
         Ok(count_db) => {
             count = count_db;
         }
@@ -175,6 +292,7 @@ pub fn list_handler(req: &mut Request) -> IronResult<Response> {
         logged_in: login_id != 0,
         login_user: login_user,
         gists: gists,
+        tag_name: tag_param,
         current_page: page,
         total_page: count / PAGINATES_PER + 1,
         next_page: page + 1,
@@ -199,6 +317,7 @@ pub fn show_handler(req: &mut Request) -> IronResult<Response> {
     }
 
     let mut resp = Response::new();
+    let token = helper::share_token_param(req);
 
     let ref id_str = req.extensions
         .get::<Router>()
@@ -220,14 +339,26 @@ pub fn show_handler(req: &mut Request) -> IronResult<Response> {
         gist: models::gist::Gist,
         editable: bool,
         deletable: bool,
+        forkable: bool,
         comments: Vec<GistComment>,
+        revision_count: i32,
+        embed_url: String,
+        starred: bool,
+        share_token: String,
+        share_url: String,
     }
 
     let gist: models::gist::Gist;
     let comments: Vec<models::gist::Comment>;
 
     match models::gist::get_by_id(&conn, &id) {
-        Ok(gist_obj) => {
+        Ok(mut gist_obj) => {
+            if !gist_obj.visible_to(&login_id, &token) {
+                return Ok(Response::with(status::NotFound));
+            }
+            for file in gist_obj.files.iter_mut() {
+                file.highlight();
+            }
             gist = gist_obj;
         }
         Err(e) => {
@@ -246,26 +377,51 @@ pub fn show_handler(req: &mut Request) -> IronResult<Response> {
         }
     }
 
+    let revision_count = match models::gist::revision_count(&conn, &id) {
+        Ok(count) => count,
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    };
+
+    let starred = match models::gist::is_starred(&conn, &login_id, &id) {
+        Ok(starred) => starred,
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    };
+
     let mut gist_comments: Vec<GistComment> = Vec::new();
     for comment in comments {
         let owner_id = comment.user_id;
         let pc = GistComment{
             comment: comment,
-            editable: owner_id == login_id,
+            editable: owner_id == login_id || login_user.is_admin,
         };
         gist_comments.push(pc);
     }
 
     let owner_id = gist.user_id;
-    let deletable = owner_id == login_id;
-    let editable = owner_id == login_id;
+    let deletable = owner_id == login_id || login_user.is_admin;
+    let editable = owner_id == login_id || login_user.is_admin;
+    let forkable = owner_id != login_id && !login_user.is_guest;
+    let share_token = gist.link_token().to_string();
+    let share_query = helper::share_query(&share_token);
     let data = Data {
         logged_in: login_id != 0,
         login_user: login_user,
         gist: gist,
         editable: editable,
         deletable: deletable,
+        forkable: forkable,
         comments: gist_comments,
+        revision_count: revision_count,
+        embed_url: format!("{}/gist/embed/{}/embed.js{}", &CONFIG.team_domain, id, share_query),
+        starred: starred,
+        share_url: if share_token != "" { format!("{}/gist/show/{}{}", &CONFIG.team_domain, id, share_query) } else { String::new() },
+        share_token: share_token,
     };
 
     resp.set_mut(Template::new("gist/show", to_json(&data)))
@@ -273,6 +429,109 @@ pub fn show_handler(req: &mut Request) -> IronResult<Response> {
     return Ok(resp);
 }
 
+pub fn revisions_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let mut login_user: models::user::UserWithPreference = models::user::UserWithPreference{..Default::default()};
+    match handlers::account::current_user(req, &conn) {
+        Ok(user) => { login_user = user; }
+        Err(e) => { error!("Errored: {:?}", e); }
+    }
+    let login_id = login_user.id;
+    if login_id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+
+    let id = match req.extensions
+        .get::<Router>()
+        .unwrap()
+        .find("id")
+        .unwrap_or("/")
+        .parse::<i32>() {
+        Ok(id) => id,
+        Err(_) => return Ok(Response::with(status::BadRequest)),
+    };
+
+    let token = helper::share_token_param(req);
+    let page_param: String;
+    {
+        use params::Params;
+        let map = req.get_ref::<Params>().unwrap();
+        match map.get("page") {
+            Some(&Value::String(ref name)) => {
+                page_param = name.to_string();
+            }
+            _ => page_param = "1".to_string(),
+        }
+    }
+
+    #[derive(Serialize)]
+    struct Data {
+        logged_in: bool,
+        login_user: models::user::UserWithPreference,
+        gist: models::gist::Gist,
+        revisions: Vec<models::gist::Revision>,
+        revision_count: i32,
+        current_page: i32,
+        total_page: i32,
+        next_page: i32,
+        prev_page: i32,
+        share_token: String,
+    }
+
+    let mut page = page_param.parse::<i32>().unwrap_or(1);
+    if page <= 0 {
+        page = 1;
+    }
+    let offset = (page - 1) * REVISIONS_PER_PAGE;
+
+    match models::gist::exists(&conn, &id) {
+        Ok(true) => {}
+        Ok(false) => return Ok(Response::with(status::NotFound)),
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+
+    let gist = match models::gist::get_by_id(&conn, &id) {
+        Ok(gist) => gist,
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    };
+    if !gist.visible_to(&login_id, &token) {
+        return Ok(Response::with(status::NotFound));
+    }
+
+    let (revisions, count) = match (models::gist::revisions(&conn, &id, &offset, &REVISIONS_PER_PAGE),
+                                    models::gist::revision_count(&conn, &id)) {
+        (Ok(revisions), Ok(count)) => (revisions, count),
+        (Err(e), _) | (_, Err(e)) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    };
+
+    let data = Data {
+        logged_in: login_id != 0,
+        login_user: login_user,
+        share_token: gist.link_token().to_string(),
+        gist: gist,
+        revisions: revisions,
+        revision_count: count,
+        current_page: page,
+        total_page: (count + REVISIONS_PER_PAGE - 1) / REVISIONS_PER_PAGE,
+        next_page: page + 1,
+        prev_page: page - 1,
+    };
+
+    let mut resp = Response::new();
+    resp.set_mut(Template::new("gist/revisions", to_json(&data)))
+        .set_mut(status::Ok);
+    return Ok(resp);
+}
+
 pub fn edit_handler(req: &mut Request) -> IronResult<Response> {
     let conn = get_pg_connection!(req);
     let mut login_user: models::user::UserWithPreference = models::user::UserWithPreference{..Default::default()};
@@ -285,15 +544,25 @@ pub fn edit_handler(req: &mut Request) -> IronResult<Response> {
         return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
     }
 
+    if login_user.is_guest {
+        return Ok(Response::with(status::Forbidden));
+    }
+
     let mut resp = Response::new();
     #[derive(Serialize)]
     struct Data {
         logged_in: bool,
         login_user: models::user::UserWithPreference,
         gist: models::gist::Gist,
+        files: Vec<models::gist::File>,
+        languages: Vec<&'static str>,
+        visibilities: Vec<models::post::VisibilityOption>,
+        tags: String,
+        share_token: String,
     }
 
     let gist: models::gist::Gist;
+    let token = helper::share_token_param(req);
 
     let ref id_str = req.extensions
         .get::<Router>()
@@ -304,7 +573,10 @@ pub fn edit_handler(req: &mut Request) -> IronResult<Response> {
 
     match models::gist::get_by_id(&conn, &id) {
         Ok(gist_obj) => {
-            if gist_obj.user_id != login_id {
+            if !gist_obj.visible_to(&login_id, &token) {
+                return Ok(Response::with(status::NotFound));
+            }
+            if gist_obj.user_id != login_id && !login_user.is_admin {
                 return Ok(Response::with(status::Forbidden));
             }
             gist = gist_obj;
@@ -318,6 +590,11 @@ pub fn edit_handler(req: &mut Request) -> IronResult<Response> {
     let data = Data {
         logged_in: login_id != 0,
         login_user: login_user,
+        files: gist.files.clone(),
+        languages: models::gist::language_names(),
+        visibilities: if gist.user_id == login_id { models::post::visibility_options(&gist.visibility) } else { Vec::new() },
+        tags: gist.tag_names(),
+        share_token: gist.link_token().to_string(),
         gist: gist,
     };
     resp.set_mut(Template::new("gist/edit", to_json(&data)))
@@ -337,14 +614,20 @@ pub fn update_handler(req: &mut Request) -> IronResult<Response> {
         return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
     }
 
+    if login_user.is_guest {
+        return Ok(Response::with(status::Forbidden));
+    }
+
     use params::{Params, Value};
 
     let id: i32;
     let description: String;
-    let filename: String;
-    let code: String;
+    let files: Vec<models::gist::File>;
+    let visibility_param: Option<String>;
+    let tags_param: Option<String>;
 
     let old_gist: models::gist::Gist;
+    let token = helper::share_token_param(req);
     {
         let map = req.get_ref::<Params>().unwrap();
         match map.find(&["id"]) {
@@ -361,25 +644,33 @@ pub fn update_handler(req: &mut Request) -> IronResult<Response> {
             _ => return Ok(Response::with(status::BadRequest)),
         }
 
-        match map.find(&["gist-filename"]) {
+        match files_param(map) {
+            Ok(value) => files = value,
+            Err(message) => return Ok(Response::with((status::BadRequest, message))),
+        }
+
+        match map.find(&["visibility"]) {
             Some(&Value::String(ref name)) => {
-                filename = name.to_string();
+                visibility_param = Some(name.to_string());
             }
-            _ => return Ok(Response::with(status::BadRequest)),
+            _ => visibility_param = None,
         }
 
-        match map.find(&["gist-code"]) {
+        match map.find(&["tags"]) {
             Some(&Value::String(ref name)) => {
-                code = name.to_string();
-            },
-            _ => return Ok(Response::with(status::BadRequest)),
+                tags_param = Some(name.to_string());
+            }
+            _ => tags_param = None,
         }
     }
 
     match models::gist::get_by_id(&conn, &id) {
         Ok(gist_obj) => {
             old_gist = gist_obj;
-            if old_gist.user_id != login_id {
+            if !old_gist.visible_to(&login_id, &token) {
+                return Ok(Response::with(status::NotFound));
+            }
+            if old_gist.user_id != login_id && !login_user.is_admin {
                 return Ok(Response::with(status::Forbidden));
             }
         }
@@ -389,13 +680,26 @@ pub fn update_handler(req: &mut Request) -> IronResult<Response> {
         }
     }
 
-    match models::gist::update(&conn, &id, &description, &filename, &code) {
+    // Only the owner changes visibility; an admin editing keeps what is set.
+    let visibility = match visibility_param {
+        Some(ref v) if old_gist.user_id == login_id => v.to_string(),
+        _ => old_gist.visibility.clone(),
+    };
+    if !models::post::is_visibility(&visibility) {
+        return Ok(Response::with(status::BadRequest));
+    }
+    let tags = tags_param.unwrap_or_else(|| old_gist.tag_names());
+
+    match models::gist::update(&conn, &id, &login_id, &description, &files, &visibility, &tags) {
         Ok(_) => {
-            let title = String::from("Edit gist");
-            let path = String::from("post");
-            let code = &format!("{}{}{}", "```\n", &code, "\n```").to_string();
-            helper::post_to_slack(&conn, &login_id, &title, &code, &id, Vec::new(), &path);
-            let url = Url::parse(&format!("{}/gist/show/{}", &CONFIG.team_domain, id)
+            if visibility == "public" {
+                let title = String::from("Edit gist");
+                let path = String::from("post");
+                let code = slack_code(&files);
+                helper::post_to_slack(&conn, &login_id, &title, &code, &id, Vec::new(), &path);
+            }
+            let share_query = helper::share_query(models::post::link_token(&visibility, &old_gist.share_token));
+            let url = Url::parse(&format!("{}/gist/show/{}{}", &CONFIG.team_domain, id, share_query)
                                      .to_string()).unwrap();
             return Ok(Response::with((status::Found, Redirect(url))));
         }
@@ -406,6 +710,193 @@ pub fn update_handler(req: &mut Request) -> IronResult<Response> {
     }
 }
 
+pub fn fork_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let mut login_user: models::user::UserWithPreference = models::user::UserWithPreference{..Default::default()};
+    match handlers::account::current_user(req, &conn) {
+        Ok(user) => { login_user = user; }
+        Err(e) => { error!("Errored: {:?}", e); }
+    }
+    let login_id = login_user.id;
+    if login_id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+
+    if login_user.is_guest {
+        return Ok(Response::with(status::Forbidden));
+    }
+
+    let token = helper::share_token_param(req);
+    let id = match req.extensions.get::<Router>().unwrap().find("id").unwrap_or("").parse::<i32>() {
+        Ok(id) => id,
+        Err(_) => return Ok(Response::with(status::BadRequest)),
+    };
+
+    let gist = match models::gist::get_by_id(&conn, &id) {
+        Ok(gist) => gist,
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    };
+    if !gist.visible_to(&login_id, &token) {
+        return Ok(Response::with(status::NotFound));
+    }
+    if gist.user_id == login_id {
+        return Ok(Response::with((status::BadRequest, "cannot fork your own gist")));
+    }
+
+    match models::gist::fork(&conn, &login_id, &gist) {
+        Ok(fork_id) => {
+            return Ok(Response::with((status::Found, Redirect(helper::redirect_url(&format!("/gist/show/{}", fork_id))))));
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+}
+
+pub fn star_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let gist = match readable_gist(req, &conn) {
+        Ok(gist) => gist,
+        Err(resp) => return Ok(resp),
+    };
+    let login_id = match handlers::account::current_user(req, &conn) {
+        Ok(user) => user.id,
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    };
+
+    match models::gist::star(&conn, &login_id, &gist.id) {
+        Ok(_) => {
+            return Ok(Response::with((status::Found, Redirect(helper::redirect_url(&format!("/gist/show/{}{}", gist.id, helper::share_query(gist.link_token())))))));
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+}
+
+pub fn unstar_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let mut login_user: models::user::UserWithPreference = models::user::UserWithPreference{..Default::default()};
+    match handlers::account::current_user(req, &conn) {
+        Ok(user) => { login_user = user; }
+        Err(e) => { error!("Errored: {:?}", e); }
+    }
+    let login_id = login_user.id;
+    if login_id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+
+    let share_query = helper::share_query(&helper::share_token_param(req));
+    let id = match req.extensions.get::<Router>().unwrap().find("id").unwrap_or("").parse::<i32>() {
+        Ok(id) => id,
+        Err(_) => return Ok(Response::with(status::BadRequest)),
+    };
+
+    match models::gist::unstar(&conn, &login_id, &id) {
+        Ok(_) => {
+            return Ok(Response::with((status::Found, Redirect(helper::redirect_url(&format!("/gist/show/{}{}", id, share_query))))));
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+}
+
+pub fn starred_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let mut login_user: models::user::UserWithPreference = models::user::UserWithPreference{..Default::default()};
+    match handlers::account::current_user(req, &conn) {
+        Ok(user) => { login_user = user; }
+        Err(e) => { error!("Errored: {:?}", e); }
+    }
+    let login_id = login_user.id;
+    if login_id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+
+    let page_param: String;
+
+    {
+        use params::{Params, Value};
+        let map = req.get_ref::<Params>().unwrap();
+        match map.get("page") {
+            Some(&Value::String(ref name)) => {
+                page_param = name.to_string();
+            }
+            _ => page_param = "1".to_string(),
+        }
+    }
+
+    let mut resp = Response::new();
+
+    // Unlisted gists were starred from their share link, which the list links keep.
+    #[derive(Serialize, Debug)]
+    struct StarredGist {
+        gist: models::gist::Gist,
+        share_token: String,
+    }
+
+    #[derive(Serialize, Debug)]
+    struct Data {
+        logged_in: bool,
+        login_user: models::user::UserWithPreference,
+        gists: Vec<StarredGist>,
+        current_page: i32,
+        total_page: i32,
+        next_page: i32,
+        prev_page: i32,
+    }
+
+    let mut page = page_param.parse::<i32>().unwrap_or(1);
+    if page <= 0 {
+        page = 1;
+    }
+    let offset = (page - 1) * PAGINATES_PER;
+    let limit = PAGINATES_PER;
+
+    let gists: Vec<StarredGist> = match models::gist::starred_list(&conn, &login_id, &offset, &limit) {
+        Ok(gists) => gists.into_iter().map(|gist| StarredGist {
+            share_token: gist.link_token().to_string(),
+            gist: gist,
+        }).collect(),
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    };
+
+    let count = match models::gist::starred_count(&conn, &login_id) {
+        Ok(count) => count,
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    };
+
+    let data = Data {
+        logged_in: login_id != 0,
+        login_user: login_user,
+        gists: gists,
+        current_page: page,
+        total_page: count / PAGINATES_PER + 1,
+        next_page: page + 1,
+        prev_page: page - 1,
+    };
+
+    resp.set_mut(Template::new("gist/starred", to_json(&data)))
+        .set_mut(status::Ok);
+    return Ok(resp);
+}
+
 pub fn delete_handler(req: &mut Request) -> IronResult<Response> {
     let conn = get_pg_connection!(req);
     let mut login_user: models::user::UserWithPreference = models::user::UserWithPreference{..Default::default()};
@@ -424,11 +915,13 @@ pub fn delete_handler(req: &mut Request) -> IronResult<Response> {
         .unwrap_or("/");
     let id = id_str.parse::<i32>().unwrap();
 
+    let description: String;
     match models::gist::get_by_id(&conn, &id) {
         Ok(gist) => {
-            if gist.user_id != login_user.id {
+            if gist.user_id != login_user.id && !login_user.is_admin {
                 return Ok(Response::with(status::Forbidden));
             }
+            description = gist.description;
         }
         Err(e) => {
             error!("Errored: {:?}", e);
@@ -438,6 +931,7 @@ pub fn delete_handler(req: &mut Request) -> IronResult<Response> {
 
     match models::gist::delete_by_id(&conn, &id) {
         Ok(_) => {
+            helper::audit(req, &conn, &login_user.id, "gist.delete", &format!("/gist/show/{}", id), &description);
             return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/gist/list")))));
         }
         Err(e) => {
@@ -459,8 +953,13 @@ pub fn comment_handler(req: &mut Request) -> IronResult<Response> {
         return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
     }
 
+    if login_user.is_guest {
+        return Ok(Response::with(status::Forbidden));
+    }
+
     let id: i32;
     let body: String;
+    let token = helper::share_token_param(req);
 
     use params::{Params, Value};
     {
@@ -482,9 +981,16 @@ pub fn comment_handler(req: &mut Request) -> IronResult<Response> {
     }
 
     let mut mentions = Vec::new();
+    let public: bool;
+    let share_query: String;
 
     match models::gist::get_by_id(&conn, &id) {
         Ok(post_obj) => {
+            if !post_obj.visible_to(&login_id, &token) {
+                return Ok(Response::with(status::NotFound));
+            }
+            public = post_obj.visibility == "public";
+            share_query = helper::share_query(post_obj.link_token());
             mentions.push(post_obj.user.username);
         }
         Err(e) => {
@@ -513,8 +1019,10 @@ pub fn comment_handler(req: &mut Request) -> IronResult<Response> {
         Ok(_) => {
             let title = String::from("New comment");
             let path = String::from("gist");
-            helper::post_to_slack(&conn, &login_id, &title, &body, &id, mentions, &path);
-            let url = Url::parse(&format!("{}/gist/show/{}", &CONFIG.team_domain, id)
+            if public {
+                helper::post_to_slack(&conn, &login_id, &title, &body, &id, mentions, &path);
+            }
+            let url = Url::parse(&format!("{}/gist/show/{}{}", &CONFIG.team_domain, id, share_query)
                                      .to_string()).unwrap();
             return Ok(Response::with((status::Found, Redirect(url))));
         }
@@ -537,10 +1045,15 @@ pub fn comment_update_handler(req: &mut Request) -> IronResult<Response> {
         return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
     }
 
+    if login_user.is_guest {
+        return Ok(Response::with(status::Forbidden));
+    }
+
     let id: i32;
     let action: String;
     let body: String;
     let comment: models::gist::Comment;
+    let share_query = helper::share_query(&helper::share_token_param(req));
 
     {
         let ref id_str = req.extensions
@@ -573,7 +1086,7 @@ pub fn comment_update_handler(req: &mut Request) -> IronResult<Response> {
     match models::gist::get_comment_by_id(&conn, &id) {
         Ok(db_comment) => {
             comment = db_comment;
-            if comment.user_id != login_id {
+            if comment.user_id != login_id && !login_user.is_admin {
                 return Ok(Response::with(status::Forbidden));
             }
         }
@@ -586,7 +1099,7 @@ pub fn comment_update_handler(req: &mut Request) -> IronResult<Response> {
     if action == "update" {
         match models::gist::update_comment_by_id(&conn, &id, &body) {
             Ok(_) => {
-                let url = Url::parse(&format!("{}/gist/show/{}", &CONFIG.team_domain, comment.gist_id)
+                let url = Url::parse(&format!("{}/gist/show/{}{}", &CONFIG.team_domain, comment.gist_id, share_query)
                                          .to_string()).unwrap();
                 return Ok(Response::with((status::Found, Redirect(url))));
             }
@@ -599,7 +1112,7 @@ pub fn comment_update_handler(req: &mut Request) -> IronResult<Response> {
     if action == "delete" {
         match models::gist::delete_comment_by_id(&conn, &id) {
             Ok(_) => {
-                let url = Url::parse(&format!("{}/gist/show/{}", &CONFIG.team_domain, comment.gist_id)
+                let url = Url::parse(&format!("{}/gist/show/{}{}", &CONFIG.team_domain, comment.gist_id, share_query)
                                          .to_string()).unwrap();
                 return Ok(Response::with((status::Found, Redirect(url))));
             }
@@ -611,3 +1124,139 @@ pub fn comment_update_handler(req: &mut Request) -> IronResult<Response> {
     }
     return Ok(Response::with(status::InternalServerError));
 }
+
+// Shared by the endpoints that only need to read a gist: raw, download, embed and star.
+fn readable_gist(req: &mut Request, conn: &db::PostgresConnection) -> Result<models::gist::Gist, Response> {
+    let mut login_user: models::user::UserWithPreference = models::user::UserWithPreference{..Default::default()};
+    match handlers::account::current_user(req, conn) {
+        Ok(user) => { login_user = user; }
+        Err(e) => { error!("Errored: {:?}", e); }
+    }
+    if login_user.id == 0 {
+        return Err(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+
+    let token = helper::share_token_param(req);
+    let id = match req.extensions.get::<Router>().unwrap().find("id").unwrap_or("").parse::<i32>() {
+        Ok(id) => id,
+        Err(_) => return Err(Response::with(status::BadRequest)),
+    };
+    match models::gist::exists(conn, &id) {
+        Ok(true) => {}
+        Ok(false) => return Err(Response::with(status::NotFound)),
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Err(Response::with(status::InternalServerError));
+        }
+    }
+    match models::gist::get_by_id(conn, &id) {
+        Ok(ref gist) if !gist.visible_to(&login_user.id, &token) => Err(Response::with(status::NotFound)),
+        Ok(gist) => Ok(gist),
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            Err(Response::with(status::InternalServerError))
+        }
+    }
+}
+
+pub fn raw_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let gist = match readable_gist(req, &conn) {
+        Ok(gist) => gist,
+        Err(resp) => return Ok(resp),
+    };
+
+    let filename = {
+        let param = req.extensions.get::<Router>().unwrap().find("filename").unwrap_or("");
+        percent_decode(param.as_bytes()).decode_utf8_lossy().to_string()
+    };
+    match gist.files.iter().find(|f| f.filename == filename) {
+        Some(file) => {
+            // Always plain text, so a gist holding HTML or SVG is never rendered as a page.
+            let content_type = "text/plain; charset=utf-8".parse::<Mime>().unwrap();
+            let mut resp = Response::with((content_type, status::Ok, file.code.to_string()));
+            resp.headers.set_raw("X-Content-Type-Options", vec![b"nosniff".to_vec()]);
+            Ok(resp)
+        }
+        None => Ok(Response::with(status::NotFound)),
+    }
+}
+
+pub fn download_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let gist = match readable_gist(req, &conn) {
+        Ok(gist) => gist,
+        Err(resp) => return Ok(resp),
+    };
+
+    match export::gist_archive(&gist) {
+        Ok(bytes) => {
+            let content_type = "application/zip".parse::<Mime>().unwrap();
+            let mut resp = Response::with((content_type, status::Ok, bytes));
+            resp.headers.set_raw("Content-Disposition",
+                                 vec![format!("attachment; filename=\"gist-{}.zip\"", gist.id).into_bytes()]);
+            Ok(resp)
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            Ok(Response::with(status::InternalServerError))
+        }
+    }
+}
+
+pub fn embed_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let mut gist = match readable_gist(req, &conn) {
+        Ok(gist) => gist,
+        Err(resp) => return Ok(resp),
+    };
+    for file in gist.files.iter_mut() {
+        file.highlight();
+    }
+
+    #[derive(Serialize)]
+    struct Data {
+        gist: models::gist::Gist,
+        team_domain: String,
+        share_token: String,
+    }
+    // Only a token the embedding page already had goes back out; the owner's own
+    // session opens the gist without one, and must not hand it to whoever embeds it.
+    let data = Data {
+        share_token: helper::share_token_param(req),
+        gist: gist,
+        team_domain: CONFIG.team_domain.to_string(),
+    };
+    let mut resp = Response::new();
+    resp.set_mut(Template::new("gist/embed", to_json(&data)))
+        .set_mut(status::Ok);
+    Ok(resp)
+}
+
+// A script tag that drops an auto-sizing iframe of the embed page where it stands.
+pub fn embed_script_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let gist = match readable_gist(req, &conn) {
+        Ok(gist) => gist,
+        Err(resp) => return Ok(resp),
+    };
+    let share_query = helper::share_query(&helper::share_token_param(req));
+
+    let script = format!(r#"(function() {{
+  var script = document.currentScript;
+  var iframe = document.createElement("iframe");
+  iframe.src = "{domain}/gist/embed/{id}{query}";
+  iframe.style.width = "100%";
+  iframe.style.border = "0";
+  iframe.height = "300";
+  window.addEventListener("message", function(e) {{
+    if (e.source === iframe.contentWindow && e.data && e.data.teamGist === {id}) {{
+      iframe.height = String(e.data.height);
+    }}
+  }});
+  script.parentNode.insertBefore(iframe, script);
+}})();
+"#, domain = CONFIG.team_domain, id = gist.id, query = share_query);
+    let content_type = "application/javascript; charset=utf-8".parse::<Mime>().unwrap();
+    Ok(Response::with((content_type, status::Ok, script)))
+}
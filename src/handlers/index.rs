@@ -11,6 +11,9 @@ use handlers;
 use helper;
 
 const PAGINATES_PER: i32 = 10;
+// Gists and hashtag tweets shown at the top of a tag page.
+const TAGGED_GISTS: i32 = 5;
+const TAGGED_TWEETS: i32 = 5;
 
 pub fn index_handler(req: &mut Request) -> IronResult<Response> {
     let conn = get_pg_connection!(req);
@@ -25,6 +28,7 @@ pub fn index_handler(req: &mut Request) -> IronResult<Response> {
     }
 
     let page_param: String;
+    let following: bool;
 
     {
         use params::{Params, Value};
@@ -35,6 +39,10 @@ pub fn index_handler(req: &mut Request) -> IronResult<Response> {
             }
             _ => page_param = "1".to_string(),
         }
+        following = match map.get("feed") {
+            Some(&Value::String(ref name)) => name == "following",
+            _ => false,
+        };
     }
 
     let mut resp = Response::new();
@@ -49,6 +57,7 @@ pub fn index_handler(req: &mut Request) -> IronResult<Response> {
         next_page: i32,
         prev_page: i32,
         pinneds: Vec<models::post::Post>,
+        following: bool,
     }
 
     let mut page = page_param.parse::<i32>().unwrap();
@@ -62,7 +71,12 @@ pub fn index_handler(req: &mut Request) -> IronResult<Response> {
     let pinneds: Vec<models::post::Post>;
     let count: i32;
 
-    match models::post::get_feeds(&conn, &offset, &limit) {
+    let feeds_result = if following {
+        models::post::get_following_feeds(&conn, &login_id, &offset, &limit)
+    } else {
+        models::post::get_feeds(&conn, &offset, &limit)
+    };
+    match feeds_result {
         Ok(feeds_db) => {
             feeds = feeds_db;
         }
@@ -72,7 +86,12 @@ pub fn index_handler(req: &mut Request) -> IronResult<Response> {
         }
     }
 
-    match models::post::get_feed_count(&conn) {
+    let count_result = if following {
+        models::post::get_following_feed_count(&conn, &login_id)
+    } else {
+        models::post::get_feed_count(&conn)
+    };
+    match count_result {
         Ok(count_db) => {
             count = count_db;
         }
@@ -103,6 +122,7 @@ pub fn index_handler(req: &mut Request) -> IronResult<Response> {
         next_page: page + 1,
         prev_page: page - 1,
         pinneds: pinneds,
+        following: following,
     };
 
     resp.set_mut(Template::new("index", to_json(&data)))
@@ -181,7 +201,7 @@ pub fn search_handler(req: &mut Request) -> IronResult<Response> {
     let posts: Vec<models::post::Post>;
     let count: i32;
 
-    match models::post::search(&conn, &keyword_search, &kind_search, &offset, &limit) {
+    match models::post::search(&conn, &keyword_search, &kind_search, &login_id, &offset, &limit) {
         Ok(posts_db) => {
             posts = posts_db;
         }
@@ -191,7 +211,7 @@ pub fn search_handler(req: &mut Request) -> IronResult<Response> {
         }
     }
 
-    match models::post::search_count(&conn, &keyword_count, &kind_count) {
+    match models::post::search_count(&conn, &keyword_count, &kind_count, &login_id) {
         Ok(count_db) => {
             count = count_db;
         }
@@ -280,6 +300,11 @@ pub fn tag_handler(req: &mut Request) -> IronResult<Response> {
         next_page: i32,
         prev_page: i32,
         tag_name: String,
+        following_tag: bool,
+        gists: Vec<models::gist::Gist>,
+        gist_count: i32,
+        tweets: Vec<models::tweet::Tweet>,
+        tweet_count: i32,
     }
 
     let mut page = page_param.parse::<i32>().unwrap();
@@ -289,7 +314,7 @@ pub fn tag_handler(req: &mut Request) -> IronResult<Response> {
     let posts: Vec<models::post::Post>;
     let count: i32;
 
-    match models::tag::tag_search(&conn, &tag_param, offset, limit) {
+    match models::tag::tag_search(&conn, &tag_param, &login_id, offset, limit) {
         Ok(posts_db) => {
             posts = posts_db;
         },
@@ -299,7 +324,7 @@ pub fn tag_handler(req: &mut Request) -> IronResult<Response> {
         }
     }
 
-    match models::tag::tag_count(&conn, &tag_param) {
+    match models::tag::tag_count(&conn, &tag_param, &login_id) {
         Ok(count_db) => {
             count = count_db;
         },
@@ -309,6 +334,54 @@ pub fn tag_handler(req: &mut Request) -> IronResult<Response> {
         }
     }
 
+    // Only the latest gists; the rest are on the `/gist/list?tag=` page.
+    let gists = if page <= 1 {
+        match models::gist::tagged(&conn, &tag_param, &login_id, &0, &TAGGED_GISTS) {
+            Ok(gists) => gists,
+            Err(e) => {
+                error!("Errored: {:?}", e);
+                return Ok(Response::with(status::InternalServerError));
+            }
+        }
+    } else {
+        Vec::new()
+    };
+    let gist_count = match models::gist::tagged_count(&conn, &tag_param, &login_id) {
+        Ok(count) => count,
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    };
+
+    // Only the latest hashtag tweets; the rest are on the `/tweet/list?tag=` timeline.
+    let tweets = if page <= 1 {
+        match models::tweet::tagged(&conn, &tag_param, &0, &TAGGED_TWEETS) {
+            Ok(tweets) => tweets,
+            Err(e) => {
+                error!("Errored: {:?}", e);
+                return Ok(Response::with(status::InternalServerError));
+            }
+        }
+    } else {
+        Vec::new()
+    };
+    let tweet_count = match models::tweet::tagged_count(&conn, &tag_param) {
+        Ok(count) => count,
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    };
+
+    let following_tag = match models::follow::is_following_tag(&conn, &login_id, &tag_param) {
+        Ok(following) => following,
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    };
+
     if page == 0 {
         page = 1;
     }
@@ -321,6 +394,11 @@ pub fn tag_handler(req: &mut Request) -> IronResult<Response> {
         next_page: page + 1,
         prev_page: page - 1,
         tag_name: tag_param,
+        following_tag: following_tag,
+        gists: gists,
+        gist_count: gist_count,
+        tweets: tweets,
+        tweet_count: tweet_count,
     };
 
     resp.set_mut(Template::new("tag", to_json(&data))).set_mut(status::Ok);
@@ -0,0 +1,108 @@
+use iron::{Request, status};
+use iron::modifiers::Redirect;
+use iron::prelude::IronResult;
+use iron::prelude::*;
+use hbs::Template;
+use persistent;
+use hbs::handlebars::to_json;
+
+use db;
+use models;
+use helper;
+use handlers;
+
+pub const PAGINATES_PER: i32 = 30;
+
+pub fn list_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let mut login_user: models::user::UserWithPreference = models::user::UserWithPreference{..Default::default()};
+    match handlers::account::current_user(req, &conn) {
+        Ok(user) => { login_user = user; }
+        Err(e) => { error!("Errored: {:?}", e); }
+    }
+    let login_id = login_user.id;
+    if login_id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+
+    let page_param: String;
+    let keyword: String;
+
+    {
+        use params::{Params, Value};
+        let map = req.get_ref::<Params>().unwrap();
+        match map.get("page") {
+            Some(&Value::String(ref name)) => {
+                page_param = name.to_string();
+            }
+            _ => page_param = "1".to_string(),
+        }
+        match map.get("q") {
+            Some(&Value::String(ref name)) => {
+                keyword = name.trim().to_string();
+            }
+            _ => keyword = "".to_string(),
+        }
+    }
+
+    let mut resp = Response::new();
+
+    #[derive(Serialize, Debug)]
+    struct Data {
+        logged_in: bool,
+        login_user: models::user::UserWithPreference,
+        members: Vec<models::member::Member>,
+        q: String,
+        count: i32,
+        current_page: i32,
+        total_page: i32,
+        next_page: i32,
+        prev_page: i32,
+    }
+
+    let mut page = page_param.parse::<i32>().unwrap_or(1);
+    if page <= 0 {
+        page = 1;
+    }
+    let offset = (page - 1) * PAGINATES_PER;
+    let limit = PAGINATES_PER;
+
+    let members: Vec<models::member::Member>;
+    let count: i32;
+
+    match models::member::list(&conn, &keyword, &offset, &limit) {
+        Ok(members_db) => {
+            members = members_db;
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+
+    match models::member::count(&conn, &keyword) {
+        Ok(count_db) => {
+            count = count_db;
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+
+    let data = Data {
+        logged_in: login_id != 0,
+        login_user: login_user,
+        members: members,
+        q: keyword,
+        count: count,
+        current_page: page,
+        total_page: count / PAGINATES_PER + 1,
+        next_page: page + 1,
+        prev_page: page - 1,
+    };
+
+    resp.set_mut(Template::new("member/list", to_json(&data)))
+        .set_mut(status::Ok);
+    return Ok(resp);
+}
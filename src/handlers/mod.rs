@@ -3,4 +3,13 @@ pub mod index;
 pub mod account;
 pub mod post;
 pub mod gist;
-pub mod tweet;
\ No newline at end of file
+pub mod tweet;
+pub mod totp;
+pub mod session;
+pub mod admin;
+pub mod password;
+pub mod export;
+pub mod member;
+pub mod follow;
+pub mod api_token;
+pub mod api;
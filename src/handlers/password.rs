@@ -0,0 +1,226 @@
+use iron::{Request, status};
+use iron::modifiers::Redirect;
+use iron::prelude::IronResult;
+use iron::prelude::*;
+use hbs::Template;
+use persistent;
+use hbs::handlebars::to_json;
+
+use db;
+use models;
+use helper;
+use mail;
+use middlewares;
+
+const MAX_REQUESTS_PER_USER: i32 = 3;
+const MAX_REQUESTS_PER_IP: i32 = 10;
+
+#[derive(Serialize, Debug, Default)]
+struct FormData {
+    csrf_token: String,
+    message: String,
+    notice: String,
+    token: String,
+}
+
+fn render(req: &mut Request, name: &str, st: status::Status, message: &str, notice: &str, token: &str) -> IronResult<Response> {
+    let data = FormData {
+        csrf_token: middlewares::csrf::token(req),
+        message: message.to_string(),
+        notice: notice.to_string(),
+        token: token.to_string(),
+    };
+    let mut resp = Response::new();
+    resp.set_mut(Template::new(name, to_json(&data)))
+        .set_mut(st);
+    return Ok(resp);
+}
+
+pub fn get_forgot_handler(req: &mut Request) -> IronResult<Response> {
+    render(req, "account/password_forgot", status::Ok, "", "", "")
+}
+
+pub fn post_forgot_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+
+    let email: String;
+    {
+        use params::Params;
+        let map = &req.get_ref::<Params>().unwrap();
+        match helper::get_param(map, "email") {
+            Ok(value) => email = value.trim().to_string(),
+            Err(st) => return Ok(Response::with(st)),
+        }
+    }
+
+    // The same notice is shown whether or not the address is known.
+    let notice = "If that address belongs to an account, a reset link is on its way.";
+    let ip = helper::client_ip(req);
+
+    match models::password_reset::recent_count_by_ip(&conn, &ip) {
+        Ok(count) => {
+            if count >= MAX_REQUESTS_PER_IP {
+                return render(req, "account/password_forgot", status::TooManyRequests,
+                              "Too many reset requests. Try again later.", "", "");
+            }
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+
+    let user: models::user::UserWithEmail;
+    match models::user::get_by_email(&conn, &email) {
+        Ok(u) => user = u,
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+    if user.id == 0 || email == "" {
+        return render(req, "account/password_forgot", status::Ok, "", notice, "");
+    }
+
+    // Directory passwords are not ours to reset, and Google and OpenID Connect
+    // accounts (auth_source 'local', but no password) sign in through their provider.
+    match (models::user::get_auth_source(&conn, &user.id), models::user::has_password(&conn, &user.id)) {
+        (Ok(source), Ok(has_password)) => {
+            if source != "local" || !has_password {
+                return render(req, "account/password_forgot", status::Ok, "", notice, "");
+            }
+        }
+        (Err(e), _) | (_, Err(e)) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+
+    match models::password_reset::recent_count_by_user_id(&conn, &user.id) {
+        Ok(count) => {
+            if count >= MAX_REQUESTS_PER_USER {
+                return render(req, "account/password_forgot", status::Ok, "", notice, "");
+            }
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+
+    let token = helper::random_token();
+    if let Err(e) = models::password_reset::create(&conn, &user.id, &token, &ip) {
+        error!("Errored: {:?}", e);
+        return Ok(Response::with(status::InternalServerError));
+    }
+
+    let url = helper::redirect_url(&format!("/password/reset?token={}", token));
+    let body = format!("Hi @{},\n\n\
+                        Someone asked to reset the password for your Team account.\n\
+                        Open this link within an hour to choose a new one:\n\n\
+                        {}\n\n\
+                        If it wasn't you, you can ignore this email.\n",
+                       user.username, url);
+    if let Err(e) = mail::send(&email, "Reset your Team password", &body) {
+        error!("Errored: {:?}", e);
+    }
+
+    render(req, "account/password_forgot", status::Ok, "", notice, "")
+}
+
+pub fn get_reset_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+
+    let token: String;
+    {
+        use params::Params;
+        let map = &req.get_ref::<Params>().unwrap();
+        token = helper::get_param(map, "token").unwrap_or("".to_string());
+    }
+
+    match models::password_reset::get_user_id(&conn, &token) {
+        Ok(Some(_)) => render(req, "account/password_reset", status::Ok, "", "", &token),
+        Ok(None) => render(req, "account/password_forgot", status::BadRequest,
+                           "That reset link is invalid or has expired.", "", ""),
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+}
+
+pub fn post_reset_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+
+    let token: String;
+    let new_password: String;
+    let confirm_password: String;
+    {
+        use params::Params;
+        let map = &req.get_ref::<Params>().unwrap();
+        match helper::get_param(map, "token") {
+            Ok(value) => token = value,
+            Err(st) => return Ok(Response::with(st)),
+        }
+        match helper::get_param(map, "new_password") {
+            Ok(value) => new_password = value,
+            Err(st) => return Ok(Response::with(st)),
+        }
+        match helper::get_param(map, "confirm_password") {
+            Ok(value) => confirm_password = value,
+            Err(st) => return Ok(Response::with(st)),
+        }
+    }
+
+    let user_id = match models::password_reset::get_user_id(&conn, &token) {
+        Ok(Some(id)) => id,
+        Ok(None) => {
+            return render(req, "account/password_forgot", status::BadRequest,
+                          "That reset link is invalid or has expired.", "", "");
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    };
+
+    if new_password != confirm_password {
+        return render(req, "account/password_reset", status::BadRequest, "Passwords do not match.", "", &token);
+    }
+    let username = match models::user::get_by_id(&conn, &user_id) {
+        Ok(user) => user.username,
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    };
+    if let Err(message) = helper::check_password_policy(&new_password, &username) {
+        return render(req, "account/password_reset", status::BadRequest, &message, "", &token);
+    }
+
+    match models::password_reset::consume(&conn, &token) {
+        Ok(Some(id)) if id == user_id => {}
+        Ok(_) => {
+            return render(req, "account/password_forgot", status::BadRequest,
+                          "That reset link is invalid or has expired.", "", "");
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+
+    // A reset usually means the old password is compromised, so sign out everywhere.
+    let result = models::user::update_password(&conn, &user_id, &helper::encrypt_password(new_password))
+        .and_then(|_| models::session::delete_by_user_id(&conn, &user_id));
+    match result {
+        Ok(_) => {
+            helper::audit(req, &conn, &user_id, "password.reset", &format!("user:{}", user_id), "");
+            return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+}
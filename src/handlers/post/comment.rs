@@ -23,8 +23,13 @@ pub fn comment_handler(req: &mut Request) -> IronResult<Response> {
         return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
     }
 
+    if login_user.is_guest {
+        return Ok(Response::with(status::Forbidden));
+    }
+
     let id: i32;
     let body: String;
+    let token = helper::share_token_param(req);
 
     use params::{Params, Value};
     {
@@ -52,9 +57,16 @@ pub fn comment_handler(req: &mut Request) -> IronResult<Response> {
         .unwrap_or("/");
 
     let mut mentions = Vec::new();
+    let public: bool;
+    let share_query: String;
 
     match models::post::get_by_id(&conn, &id) {
         Ok(post_obj) => {
+            if !post_obj.visible_to(&login_id, &token) {
+                return Ok(Response::with(status::NotFound));
+            }
+            public = post_obj.visibility == "public";
+            share_query = helper::share_query(post_obj.link_token());
             mentions.push(post_obj.user.username);
         }
         Err(e) => {
@@ -83,8 +95,10 @@ pub fn comment_handler(req: &mut Request) -> IronResult<Response> {
         Ok(_) => {
             let title = String::from("New comment");
             let path = String::from("post");
-            helper::post_to_slack(&conn, &login_id, &title, &body, &id, mentions, &path);
-            let url = Url::parse(&format!("{}/{}/show/{}", &CONFIG.team_domain, kind, id)
+            if public {
+                helper::post_to_slack(&conn, &login_id, &title, &body, &id, mentions, &path);
+            }
+            let url = Url::parse(&format!("{}/{}/show/{}{}", &CONFIG.team_domain, kind, id, share_query)
                                      .to_string()).unwrap();
             return Ok(Response::with((status::Found, Redirect(url))));
         }
@@ -107,10 +121,15 @@ pub fn comment_update_handler(req: &mut Request) -> IronResult<Response> {
         return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
     }
 
+    if login_user.is_guest {
+        return Ok(Response::with(status::Forbidden));
+    }
+
     let id: i32;
     let action: String;
     let body: String;
     let comment: models::post::Comment;
+    let share_query = helper::share_query(&helper::share_token_param(req));
 
     {
         let ref id_str = req.extensions
@@ -149,7 +168,7 @@ pub fn comment_update_handler(req: &mut Request) -> IronResult<Response> {
     match models::post::get_comment_by_id(&conn, &id) {
         Ok(db_comment) => {
             comment = db_comment;
-            if comment.user_id != login_id {
+            if comment.user_id != login_id && !login_user.is_admin {
                 return Ok(Response::with(status::Forbidden));
             }
         }
@@ -162,7 +181,7 @@ pub fn comment_update_handler(req: &mut Request) -> IronResult<Response> {
     if action == "update" {
         match models::post::update_comment_by_id(&conn, &id, &body) {
             Ok(_) => {
-                let url = Url::parse(&format!("{}/{}/show/{}", &CONFIG.team_domain, kind, comment.post_id)
+                let url = Url::parse(&format!("{}/{}/show/{}{}", &CONFIG.team_domain, kind, comment.post_id, share_query)
                                          .to_string()).unwrap();
                 return Ok(Response::with((status::Found, Redirect(url))));
             }
@@ -175,7 +194,7 @@ pub fn comment_update_handler(req: &mut Request) -> IronResult<Response> {
     if action == "delete" {
         match models::post::delete_comment_by_id(&conn, &id) {
             Ok(_) => {
-                let url = Url::parse(&format!("{}/{}/show/{}", &CONFIG.team_domain, kind, comment.post_id)
+                let url = Url::parse(&format!("{}/{}/show/{}{}", &CONFIG.team_domain, kind, comment.post_id, share_query)
                                          .to_string()).unwrap();
                 return Ok(Response::with((status::Found, Redirect(url))));
             }
@@ -27,6 +27,11 @@ pub fn pin_handler(req: &mut Request) -> IronResult<Response> {
         return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
     }
 
+    if !login_user.is_admin {
+        return Ok(Response::with(status::Forbidden));
+    }
+
+    let share_query = helper::share_query(&helper::share_token_param(req));
     let ref kind = req.extensions
         .get::<Router>()
         .unwrap()
@@ -42,7 +47,8 @@ pub fn pin_handler(req: &mut Request) -> IronResult<Response> {
 
     match models::post::pin_post(&conn, &login_id, &id) {
         Ok(_) => {
-            let url = Url::parse(&format!("{}/{}/show/{}", &CONFIG.team_domain, kind, id)
+            helper::audit(req, &conn, &login_id, "post.pin", &format!("/{}/show/{}", kind, id), "");
+            let url = Url::parse(&format!("{}/{}/show/{}{}", &CONFIG.team_domain, kind, id, share_query)
                     .to_string())
                     .unwrap();
             return Ok(Response::with((status::Found, Redirect(url))));
@@ -66,6 +72,11 @@ pub fn unpin_handler(req: &mut Request) -> IronResult<Response> {
         return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
     }
 
+    if !login_user.is_admin {
+        return Ok(Response::with(status::Forbidden));
+    }
+
+    let share_query = helper::share_query(&helper::share_token_param(req));
     let ref kind = req.extensions
         .get::<Router>()
         .unwrap()
@@ -81,7 +92,8 @@ pub fn unpin_handler(req: &mut Request) -> IronResult<Response> {
 
     match models::post::unpin_post(&conn, &id) {
         Ok(_) => {
-            let url = Url::parse(&format!("{}/{}/show/{}", &CONFIG.team_domain, kind, id)
+            helper::audit(req, &conn, &login_id, "post.unpin", &format!("/{}/show/{}", kind, id), "");
+            let url = Url::parse(&format!("{}/{}/show/{}{}", &CONFIG.team_domain, kind, id, share_query)
                     .to_string())
                     .unwrap();
             return Ok(Response::with((status::Found, Redirect(url))));
@@ -31,6 +31,10 @@ pub fn new_handler(req: &mut Request) -> IronResult<Response> {
         return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
     }
 
+    if login_user.is_guest {
+        return Ok(Response::with(status::Forbidden));
+    }
+
     let ref kind = req.extensions
         .get::<Router>()
         .unwrap()
@@ -45,12 +49,14 @@ pub fn new_handler(req: &mut Request) -> IronResult<Response> {
         login_user: models::user::UserWithPreference,
         kind: String,
         kind_title: String,
+        visibilities: Vec<models::post::VisibilityOption>,
     }
     let data = Data {
         logged_in: login_id != 0,
         login_user: login_user,
         kind: kind.to_string(),
         kind_title: helper::uppercase_first_letter(kind),
+        visibilities: models::post::visibility_options("public"),
     };
     resp.set_mut(helper::template("post/form", to_json(&data)))
         .set_mut(status::Ok);
@@ -69,10 +75,15 @@ pub fn create_handler(req: &mut Request) -> IronResult<Response> {
         return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
     }
 
+    if login_user.is_guest {
+        return Ok(Response::with(status::Forbidden));
+    }
+
     let action: String;
     let title: String;
     let body: String;
     let tags: String;
+    let visibility: String;
 
     {
         use params::{Params, Value};
@@ -103,6 +114,16 @@ pub fn create_handler(req: &mut Request) -> IronResult<Response> {
             },
             _ => return Ok(Response::with(status::BadRequest)),
         }
+        match map.get("visibility") {
+            Some(&Value::String(ref name)) => {
+                visibility = name.to_string();
+            },
+            _ => visibility = "public".to_string(),
+        }
+    }
+
+    if !models::post::is_visibility(&visibility) {
+        return Ok(Response::with(status::BadRequest));
     }
 
     let ref kind = req.extensions
@@ -111,12 +132,17 @@ pub fn create_handler(req: &mut Request) -> IronResult<Response> {
         .find("kind")
         .unwrap_or("/");
 
-    match models::post::create(&conn, kind, &login_id, &action, &title, &body, &tags) {
+    match models::post::create(&conn, kind, &login_id, &action, &title, &body, &tags, &visibility) {
         Ok(id) => {
             let url_str = format!("{}/{}/show/{}", &CONFIG.team_domain, kind, id)
                          .to_string();
 
-            if action == "publish" {
+            // Unlisted and private posts are not announced anywhere.
+            if action == "publish" && visibility == "public" {
+                let show_path = format!("/{}/show/{}", kind, id);
+                if let Err(e) = models::notification::create_for_followers(&conn, &show_path, &login_id, &title) {
+                    error!("Errored: {:?}", e);
+                }
                 let mut title = String::from("New post");
                 let path = String::from("post");
                 if kind == &"nippo" {
@@ -196,7 +222,7 @@ pub fn list_handler(req: &mut Request) -> IronResult<Response> {
     let posts: Vec<models::post::Post>;
     let count: i32;
 
-    match models::post::list(&conn, kind, &offset, &limit) {
+    match models::post::list(&conn, kind, &login_id, &offset, &limit) {
         Ok(posts_db) => {
             posts = posts_db;
         }
@@ -206,7 +232,7 @@ pub fn list_handler(req: &mut Request) -> IronResult<Response> {
         }
     }
 
-    match models::post::count(&conn, kind) {
+    match models::post::count(&conn, kind, &login_id) {
         Ok(count_db) => {
             count = count_db;
         }
@@ -255,6 +281,7 @@ pub fn show_handler(req: &mut Request) -> IronResult<Response> {
     }
 
     let mut resp = Response::new();
+    let token = helper::share_token_param(req);
 
     let ref id_str = req.extensions
         .get::<Router>()
@@ -289,6 +316,8 @@ pub fn show_handler(req: &mut Request) -> IronResult<Response> {
         kind: String,
         kind_title: String,
         pinned: bool,
+        share_token: String,
+        share_url: String,
     }
 
     let post: models::post::Post;
@@ -298,6 +327,9 @@ pub fn show_handler(req: &mut Request) -> IronResult<Response> {
 
     match models::post::get_by_id(&conn, &id) {
         Ok(post_obj) => {
+            if !post_obj.visible_to(&login_id, &token) {
+                return Ok(Response::with(status::NotFound));
+            }
             post = post_obj;
         }
         Err(e) => {
@@ -341,7 +373,7 @@ pub fn show_handler(req: &mut Request) -> IronResult<Response> {
         let owner_id = comment.user_id;
         let pc = PostComment{
             comment: comment,
-            editable: owner_id == login_id,
+            editable: owner_id == login_id || login_user.is_admin,
             kind: kind.to_string(),
         };
         post_comments.push(pc);
@@ -349,8 +381,14 @@ pub fn show_handler(req: &mut Request) -> IronResult<Response> {
 
     let shared = post.shared;
     let owner_id = post.user_id;
-    let deletable = owner_id == login_id || post.shared;
-    let editable = owner_id == login_id || post.shared;
+    let deletable = owner_id == login_id || post.shared || login_user.is_admin;
+    let editable = owner_id == login_id || post.shared || login_user.is_admin;
+    let share_token = post.link_token().to_string();
+    let share_url = if share_token != "" {
+        format!("{}/{}/show/{}{}", &CONFIG.team_domain, kind, id, helper::share_query(&share_token))
+    } else {
+        String::new()
+    };
     let data = Data {
         logged_in: login_id != 0,
         login_user: login_user,
@@ -363,6 +401,8 @@ pub fn show_handler(req: &mut Request) -> IronResult<Response> {
         kind: kind.to_string(),
         kind_title: helper::uppercase_first_letter(kind),
         pinned: pinned,
+        share_token: share_token,
+        share_url: share_url,
     };
 
     resp.set_mut(Template::new("post/show", to_json(&data)))
@@ -388,11 +428,15 @@ pub fn delete_handler(req: &mut Request) -> IronResult<Response> {
         .unwrap_or("/");
     let id = id_str.parse::<i32>().unwrap();
 
+    let target: String;
+    let title: String;
     match models::post::get_by_id(&conn, &id) {
         Ok(post) => {
-            if post.user_id != login_user.id {
+            if post.user_id != login_user.id && !login_user.is_admin {
                 return Ok(Response::with(status::Forbidden));
             }
+            target = format!("/{}/show/{}", post.kind, id);
+            title = post.title;
         }
         Err(e) => {
             error!("Errored: {:?}", e);
@@ -402,6 +446,7 @@ pub fn delete_handler(req: &mut Request) -> IronResult<Response> {
 
     match models::post::delete_by_id(&conn, &id) {
         Ok(_) => {
+            helper::audit(req, &conn, &login_user.id, "post.delete", &target, &title);
             return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/post/list")))));
         }
         Err(e) => {
@@ -423,6 +468,10 @@ pub fn edit_handler(req: &mut Request) -> IronResult<Response> {
         return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
     }
 
+    if login_user.is_guest {
+        return Ok(Response::with(status::Forbidden));
+    }
+
     let mut resp = Response::new();
     #[derive(Serialize)]
     struct Data {
@@ -432,9 +481,12 @@ pub fn edit_handler(req: &mut Request) -> IronResult<Response> {
         tags: String,
         kind: String,
         kind_title: String,
+        visibilities: Vec<models::post::VisibilityOption>,
+        share_token: String,
     }
 
     let post: models::post::Post;
+    let token = helper::share_token_param(req);
 
     let ref id_str = req.extensions
         .get::<Router>()
@@ -451,7 +503,10 @@ pub fn edit_handler(req: &mut Request) -> IronResult<Response> {
 
     match models::post::get_by_id(&conn, &id) {
         Ok(post_obj) => {
-            if post_obj.user_id != login_id && post_obj.shared == false {
+            if !post_obj.visible_to(&login_id, &token) {
+                return Ok(Response::with(status::NotFound));
+            }
+            if post_obj.user_id != login_id && post_obj.shared == false && !login_user.is_admin {
                 return Ok(Response::with(status::Forbidden));
             }
             post = post_obj;
@@ -473,6 +528,8 @@ pub fn edit_handler(req: &mut Request) -> IronResult<Response> {
     let data = Data {
         logged_in: login_id != 0,
         login_user: login_user,
+        visibilities: if post.user_id == login_id { models::post::visibility_options(&post.visibility) } else { Vec::new() },
+        share_token: post.link_token().to_string(),
         post: post,
         tags: tag_str,
         kind: kind.to_string(),
@@ -495,6 +552,10 @@ pub fn update_handler(req: &mut Request) -> IronResult<Response> {
         return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
     }
 
+    if login_user.is_guest {
+        return Ok(Response::with(status::Forbidden));
+    }
+
     use params::{Params, Value};
 
     let id: i32;
@@ -502,8 +563,10 @@ pub fn update_handler(req: &mut Request) -> IronResult<Response> {
     let body: String;
     let tags: String;
     let action: String;
+    let visibility_param: Option<String>;
 
     let old_post: models::post::Post;
+    let token = helper::share_token_param(req);
     {
         let map = req.get_ref::<Params>().unwrap();
         match map.find(&["id"]) {
@@ -540,6 +603,13 @@ pub fn update_handler(req: &mut Request) -> IronResult<Response> {
             },
             _ => return Ok(Response::with(status::BadRequest)),
         }
+
+        match map.find(&["visibility"]) {
+            Some(&Value::String(ref name)) => {
+                visibility_param = Some(name.to_string());
+            },
+            _ => visibility_param = None,
+        }
     }
 
     let ref kind = req.extensions
@@ -551,7 +621,10 @@ pub fn update_handler(req: &mut Request) -> IronResult<Response> {
     match models::post::get_by_id(&conn, &id) {
         Ok(post_obj) => {
             old_post = post_obj;
-            if old_post.user_id != login_id && old_post.shared == false {
+            if !old_post.visible_to(&login_id, &token) {
+                return Ok(Response::with(status::NotFound));
+            }
+            if old_post.user_id != login_id && old_post.shared == false && !login_user.is_admin {
                 return Ok(Response::with(status::Forbidden));
             }
         }
@@ -561,8 +634,26 @@ pub fn update_handler(req: &mut Request) -> IronResult<Response> {
         }
     }
 
-    match models::post::update(&conn, &id, &title, &body, &tags, &action) {
+    // Only the author decides who can see a post; shared editors keep what is set.
+    let visibility = match visibility_param {
+        Some(ref v) if old_post.user_id == login_id => v.to_string(),
+        _ => old_post.visibility.clone(),
+    };
+    if !models::post::is_visibility(&visibility) {
+        return Ok(Response::with(status::BadRequest));
+    }
+    // A post counts as new to the team the first time it is both published and public.
+    let was_public = old_post.status == "publish" && old_post.visibility == "public";
+    let announced = action == "publish" && visibility == "public";
+
+    match models::post::update(&conn, &id, &title, &body, &tags, &action, &visibility) {
         Ok(_) => {
+            if announced && !was_public {
+                let show_path = format!("/{}/show/{}", kind, id);
+                if let Err(e) = models::notification::create_for_followers(&conn, &show_path, &login_id, &title) {
+                    error!("Errored: {:?}", e);
+                }
+            }
             let title = String::from("Edit post");
             let path = String::from("post");
             let left = &old_post.body;
@@ -575,12 +666,12 @@ pub fn update_handler(req: &mut Request) -> IronResult<Response> {
                     diff::Result::Right(r)   => diff_body += &format!("+{}\n", r)
                 }
             }
-            if action == "publish" {
-                if old_post.status == "draft" {
+            if announced {
+                if !was_public {
                     diff_body = body.clone();
                 }
                 helper::post_to_slack(&conn, &login_id, &title, &diff_body, &id, Vec::new(), &path);
-                if kind == &"nippo" && old_post.status == "draft" {
+                if kind == &"nippo" && !was_public {
                     let title = String::from("New 日報");
                     let webhook_body = body.clone();
                     let url_str = format!("{}/{}/show/{}", &CONFIG.team_domain, kind, id).to_string();
@@ -588,7 +679,8 @@ pub fn update_handler(req: &mut Request) -> IronResult<Response> {
                 }
             }
 
-            let url = Url::parse(&format!("{}/{}/show/{}", &CONFIG.team_domain, kind, id)
+            let share_query = helper::share_query(models::post::link_token(&visibility, &old_post.share_token));
+            let url = Url::parse(&format!("{}/{}/show/{}{}", &CONFIG.team_domain, kind, id, share_query)
                                      .to_string())
                     .unwrap();
             return Ok(Response::with((status::Found, Redirect(url))));
@@ -612,6 +704,10 @@ pub fn tags_update_handler(req: &mut Request) -> IronResult<Response> {
         return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
     }
 
+    if login_user.is_guest {
+        return Ok(Response::with(status::Forbidden));
+    }
+
     use params::{Params, Value};
 
     let id: i32;
@@ -621,6 +717,7 @@ pub fn tags_update_handler(req: &mut Request) -> IronResult<Response> {
     let action: String = String::from("publish");
 
     let old_post: models::post::Post;
+    let token = helper::share_token_param(req);
     {
         let map = req.get_ref::<Params>().unwrap();
         match map.find(&["id"]) {
@@ -647,6 +744,9 @@ pub fn tags_update_handler(req: &mut Request) -> IronResult<Response> {
     match models::post::get_by_id(&conn, &id) {
         Ok(post_obj) => {
             old_post = post_obj;
+            if !old_post.visible_to(&login_id, &token) {
+                return Ok(Response::with(status::NotFound));
+            }
             title = old_post.title.clone();
             body = old_post.body.clone();
         }
@@ -656,7 +756,7 @@ pub fn tags_update_handler(req: &mut Request) -> IronResult<Response> {
         }
     }
 
-    match models::post::update(&conn, &id, &title, &body, &tags, &action) {
+    match models::post::update(&conn, &id, &title, &body, &tags, &action, &old_post.visibility) {
         Ok(_) => {
             let title = String::from("Update tag");
             let path = String::from("post");
@@ -670,9 +770,11 @@ pub fn tags_update_handler(req: &mut Request) -> IronResult<Response> {
                     diff::Result::Right(r)   => diff_body += &format!("+{}\n", r)
                 }
             }
-            helper::post_to_slack(&conn, &login_id, &title, &diff_body, &id, Vec::new(), &path);
+            if old_post.visibility == "public" {
+                helper::post_to_slack(&conn, &login_id, &title, &diff_body, &id, Vec::new(), &path);
+            }
 
-            let url = Url::parse(&format!("{}/{}/show/{}", &CONFIG.team_domain, kind, id)
+            let url = Url::parse(&format!("{}/{}/show/{}{}", &CONFIG.team_domain, kind, id, helper::share_query(old_post.link_token()))
                                      .to_string())
                     .unwrap();
             return Ok(Response::with((status::Found, Redirect(url))));
@@ -800,7 +902,6 @@ pub fn notification_count_handler(req: &mut Request) -> IronResult<Response> {
     return Ok(Response::with((content_type, status::Ok, to_json(&data).to_string())));
 }
 
-use time;
 use std::fs;
 pub fn image_upload_handler(req: &mut Request) -> IronResult<Response> {
     let conn = get_pg_connection!(req);
@@ -814,20 +915,32 @@ pub fn image_upload_handler(req: &mut Request) -> IronResult<Response> {
         return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
     }
 
+    if login_user.is_guest {
+        return Ok(Response::with(status::Forbidden));
+    }
+
     use params::{Params, Value};
 
-    let timestamp = time::get_time().sec;
-    let filepath = format!("public/img/posts/{:?}.png", timestamp);
-    let fileurl = format!("img/posts/{:?}.png", timestamp);
+    // A random name, so uploads in the same second don't overwrite each other.
+    let name = helper::random_token();
+    let filepath = format!("public/img/posts/{}.png", name);
+    let fileurl = format!("img/posts/{}.png", name);
 
     match req.get_ref::<Params>().unwrap().find(&["file"]) {
         Some(&Value::File(ref file)) => {
             let a = &file.path;
             let b = &filepath;
-            let _ = fs::copy(a, b);
+            match fs::copy(a, b) {
+                Ok(_) => {
+                    if let Err(e) = models::upload::create(&conn, &login_id, &fileurl) {
+                        error!("Errored: {:?}", e);
+                    }
+                }
+                Err(e) => error!("Errored: {:?}", e),
+            }
         }
         _ => {
-            println!("no file");
+            error!("no file");
         }
     }
     #[derive(Serialize, Debug)]
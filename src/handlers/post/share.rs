@@ -36,8 +36,21 @@ pub fn share_handler(req: &mut Request) -> IronResult<Response> {
         .unwrap_or("/");
     let id = id_str.parse::<i32>().unwrap();
 
+    match models::post::get_by_id(&conn, &id) {
+        Ok(post) => {
+            if post.user_id != login_id && !login_user.is_admin {
+                return Ok(Response::with(status::Forbidden));
+            }
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+
     match models::post::share_post(&conn, &id) {
         Ok(_) => {
+            helper::audit(req, &conn, &login_id, "post.share", &format!("/{}/show/{}", kind, id), "");
             let url = Url::parse(&format!("{}/{}/show/{}", &CONFIG.team_domain, kind, id)
                     .to_string())
                     .unwrap();
@@ -27,6 +27,8 @@ pub fn stock_handler(req: &mut Request) -> IronResult<Response> {
         return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
     }
 
+    let token = helper::share_token_param(req);
+    let share_query = helper::share_query(&token);
     let ref kind = req.extensions
         .get::<Router>()
         .unwrap()
@@ -40,9 +42,21 @@ pub fn stock_handler(req: &mut Request) -> IronResult<Response> {
         .unwrap_or("/");
     let id = id_str.parse::<i32>().unwrap();
 
+    match models::post::get_by_id(&conn, &id) {
+        Ok(post) => {
+            if !post.visible_to(&login_id, &token) {
+                return Ok(Response::with(status::NotFound));
+            }
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+
     match models::post::stock_post(&conn, &login_id, &id) {
         Ok(_) => {
-            let url = Url::parse(&format!("{}/{}/show/{}", &CONFIG.team_domain, kind, id)
+            let url = Url::parse(&format!("{}/{}/show/{}{}", &CONFIG.team_domain, kind, id, share_query)
                     .to_string())
                     .unwrap();
             return Ok(Response::with((status::Found, Redirect(url))));
@@ -66,6 +80,7 @@ pub fn unstock_handler(req: &mut Request) -> IronResult<Response> {
         return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
     }
 
+    let share_query = helper::share_query(&helper::share_token_param(req));
     let ref kind = req.extensions
         .get::<Router>()
         .unwrap()
@@ -81,7 +96,7 @@ pub fn unstock_handler(req: &mut Request) -> IronResult<Response> {
 
     match models::post::stock_remove(&conn, &login_id, &id) {
         Ok(_) => {
-            let url = Url::parse(&format!("{}/{}/show/{}", &CONFIG.team_domain, kind, id)
+            let url = Url::parse(&format!("{}/{}/show/{}{}", &CONFIG.team_domain, kind, id, share_query)
                     .to_string())
                     .unwrap();
             return Ok(Response::with((status::Found, Redirect(url))));
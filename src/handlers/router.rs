@@ -9,6 +9,15 @@ use handlers::account;
 use handlers::post;
 use handlers::gist;
 use handlers::tweet;
+use handlers::totp;
+use handlers::session;
+use handlers::admin;
+use handlers::password;
+use handlers::export;
+use handlers::member;
+use handlers::follow;
+use handlers::api_token;
+use handlers::api;
 
 pub fn create_router() -> Router {
     let mut router = Router::new();
@@ -18,19 +27,56 @@ pub fn create_router() -> Router {
 
     router.get("/signup", account::get_signup_handler, "account/get_signup");
     router.post("/signup", account::post_signup_handler, "account/post_signup");
+    router.get("/signup/confirm", account::get_signup_confirm_handler, "account/get_signup_confirm");
     router.get("/signin", account::get_signin_handler, "account/get_signin");
     router.post("/signin", account::post_signin_handler, "account/post_signin");
-    router.get("/signout", account::get_signout_handler, "account/get_signout");
+    router.post("/signout", account::post_signout_handler, "account/post_signout");
+    router.get("/signin/totp", totp::get_signin_totp_handler, "totp/get_signin");
+    router.post("/signin/totp", totp::post_signin_totp_handler, "totp/post_signin");
+    router.get("/password/forgot", password::get_forgot_handler, "password/get_forgot");
+    router.post("/password/forgot", password::post_forgot_handler, "password/post_forgot");
+    router.get("/password/reset", password::get_reset_handler, "password/get_reset");
+    router.post("/password/reset", password::post_reset_handler, "password/post_reset");
 
     router.get("/auth/google", account::get_auth_google_handler, "account/get_auth_google");
+    router.get("/auth/oidc", account::get_auth_oidc_handler, "account/get_auth_oidc");
 
     router.get("/account/settings", account::get_settings_handler, "account/get_settings");
     router.post("/account/settings", account::post_settings_handler, "account/post_settings");
     router.post("/account/password", account::post_password_update, "account/post_password");
     router.post("/account/username", account::post_username_update, "account/post_username");
+    router.post("/account/profile", account::post_profile_update, "account/post_profile");
     router.post("/account/preference/menu", account::post_preference_menu, "account/post_preference_menu");
     router.post("/account/preference/theme", account::post_preference_theme, "account/post_preference_theme");
     router.post("/account/icon/upload", account::icon_upload_handler, "account/icon_upload");
+    router.post("/account/totp/setup", totp::post_setup_handler, "totp/setup");
+    router.post("/account/totp/enable", totp::post_enable_handler, "totp/enable");
+    router.post("/account/totp/disable", totp::post_disable_handler, "totp/disable");
+    router.post("/account/totp/recovery", totp::post_recovery_codes_handler, "totp/recovery");
+    router.get("/account/sessions", session::list_handler, "session/list");
+    router.post("/account/sessions/revoke/:id", session::revoke_handler, "session/revoke");
+    router.post("/account/sessions/revoke_others", session::revoke_others_handler, "session/revoke_others");
+    router.get("/account/export", export::download_handler, "export/download");
+    router.post("/account/delete", account::post_delete_handler, "account/post_delete");
+    router.get("/account/tokens", api_token::list_handler, "api_token/list");
+    router.post("/account/tokens", api_token::create_handler, "api_token/create");
+    router.post("/account/tokens/:id/delete", api_token::delete_handler, "api_token/delete");
+
+    router.get("/admin", admin::users_handler, "admin/users");
+    router.post("/admin/users/:id/role", admin::role_handler, "admin/role");
+    router.post("/admin/users/:id/logout", admin::logout_handler, "admin/logout");
+    router.post("/admin/users/:id/deactivate", admin::deactivate_handler, "admin/deactivate");
+    router.post("/admin/users/:id/reactivate", admin::reactivate_handler, "admin/reactivate");
+    router.post("/admin/users/:id/transfer", admin::transfer_handler, "admin/transfer");
+    router.get("/admin/pins", admin::pins_handler, "admin/pins");
+    router.post("/admin/pins/:id/unpin", admin::unpin_handler, "admin/unpin");
+    router.get("/admin/settings", admin::get_settings_handler, "admin/get_settings");
+    router.post("/admin/settings", admin::post_settings_handler, "admin/post_settings");
+    router.get("/admin/invites", admin::invites_handler, "admin/invites");
+    router.post("/admin/invites", admin::create_invite_handler, "admin/create_invite");
+    router.post("/admin/invites/:id/delete", admin::delete_invite_handler, "admin/delete_invite");
+    router.get("/admin/audit", admin::audit_handler, "admin/audit");
+    router.get("/admin/audit/export", admin::audit_export_handler, "admin/audit_export");
 
     router.get("/pinned/list", post::pin::pinned_list_handler, "piined/list");
 
@@ -38,17 +84,32 @@ pub fn create_router() -> Router {
     router.post("/gist/create", gist::create_handler, "gist/create");
     router.get("/gist/list", gist::list_handler, "gist/list");
     router.get("/gist/show/:id", gist::show_handler, "gist/show");
+    router.get("/gist/revisions/:id", gist::revisions_handler, "gist/revisions");
     router.get("/gist/edit/:id", gist::edit_handler, "gist/edit");
     router.post("/gist/update", gist::update_handler, "gist/update");
-    router.get("/gist/delete/:id", gist::delete_handler, "gist/delete");
+    router.post("/gist/delete/:id", gist::delete_handler, "gist/delete");
+    router.post("/gist/fork/:id", gist::fork_handler, "gist/fork");
+    router.post("/gist/star/:id", gist::star_handler, "gist/star");
+    router.post("/gist/unstar/:id", gist::unstar_handler, "gist/unstar");
+    router.get("/gist/starred", gist::starred_handler, "gist/starred");
+    router.get("/gist/raw/:id/:filename", gist::raw_handler, "gist/raw");
+    router.get("/gist/download/:id", gist::download_handler, "gist/download");
+    router.get("/gist/embed/:id", gist::embed_handler, "gist/embed");
+    router.get("/gist/embed/:id/embed.js", gist::embed_script_handler, "gist/embed_script");
     router.post("/gist/comment", gist::comment_handler, "gist/comment");
     router.post("/gist/comment/:id", gist::comment_update_handler, "gist/comment/update");
 
+    router.get("/api/gists", api::list_handler, "api/gist_list");
+    router.post("/api/gists", api::create_handler, "api/gist_create");
+    router.get("/api/gists/:id", api::show_handler, "api/gist_show");
+    router.put("/api/gists/:id", api::update_handler, "api/gist_update");
+    router.get("/api/gists/:id/raw/:filename", api::raw_handler, "api/gist_raw");
+
     router.get("/:kind/new", post::post::new_handler, "post/new");
     router.post("/:kind/create", post::post::create_handler, "post/create");
     router.get("/:kind/list", post::post::list_handler, "post/list");
     router.get("/:kind/show/:id", post::post::show_handler, "post/show/:id");
-    router.get("/:kind/delete/:id", post::post::delete_handler, "post/delete");
+    router.post("/:kind/delete/:id", post::post::delete_handler, "post/delete");
     router.get("/:kind/edit/:id", post::post::edit_handler, "post/edit");
     router.post("/:kind/update", post::post::update_handler, "post/update");
     router.post("/:kind/comment", post::comment::comment_handler, "post/comment");
@@ -69,8 +130,16 @@ pub fn create_router() -> Router {
     router.post("/tweet/post", tweet::post_handler, "tweet/post");
     router.get("/tweet/show/:id", tweet::show_handler, "tweet/show");
     router.post("/tweet/comment", tweet::comment_handler, "tweet/comment");
+    router.post("/tweet/update/:id", tweet::update_handler, "tweet/update");
+    router.post("/tweet/comment/:id", tweet::comment_update_handler, "tweet/comment/update");
 
     router.get("/tag/list", post::tag::tag_list_handler, "tag/list");
+    router.post("/tag/follow", follow::follow_tag_handler, "follow/tag");
+    router.post("/tag/unfollow", follow::unfollow_tag_handler, "follow/untag");
+
+    router.get("/members", member::list_handler, "member/list");
+    router.post("/users/:username/follow", follow::follow_user_handler, "follow/user");
+    router.post("/users/:username/unfollow", follow::unfollow_user_handler, "follow/unuser");
 
     router.get("/notifications", post::post::notifications_handler, "post/notifications");
     router.get("/notification_count", post::post::notification_count_handler, "post/notification_count");
@@ -0,0 +1,110 @@
+use iron::{Request, status};
+use iron::modifiers::Redirect;
+use iron::prelude::IronResult;
+use iron::prelude::*;
+use router::Router;
+use hbs::Template;
+use persistent;
+use hbs::handlebars::to_json;
+
+use db;
+use models;
+use helper;
+use handlers;
+use middlewares;
+
+pub fn list_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let mut login_user: models::user::UserWithPreference = models::user::UserWithPreference{..Default::default()};
+    match handlers::account::current_user(req, &conn) {
+        Ok(user) => { login_user = user; }
+        Err(e) => { error!("Errored: {:?}", e); }
+    }
+    let login_id = login_user.id;
+    if login_id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+
+    #[derive(Serialize)]
+    struct Data {
+        logged_in: bool,
+        login_user: models::user::UserWithPreference,
+        sessions: Vec<models::session::Session>,
+    }
+
+    let current_hash = middlewares::session::current_hash(req);
+    match models::session::list_by_user_id(&conn, &login_id, &current_hash) {
+        Ok(sessions) => {
+            let data = Data {
+                logged_in: login_id != 0,
+                login_user: login_user,
+                sessions: sessions,
+            };
+            let mut resp = Response::new();
+            resp.set_mut(Template::new("account/sessions", to_json(&data)))
+                .set_mut(status::Ok);
+            return Ok(resp);
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+}
+
+pub fn revoke_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let mut login_user: models::user::UserWithPreference = models::user::UserWithPreference{..Default::default()};
+    match handlers::account::current_user(req, &conn) {
+        Ok(user) => { login_user = user; }
+        Err(e) => { error!("Errored: {:?}", e); }
+    }
+    let login_id = login_user.id;
+    if login_id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+
+    let id = match req.extensions
+        .get::<Router>()
+        .unwrap()
+        .find("id")
+        .unwrap_or("/")
+        .parse::<i32>() {
+        Ok(id) => id,
+        Err(_) => return Ok(Response::with(status::BadRequest)),
+    };
+
+    match models::session::delete(&conn, &id, &login_id) {
+        Ok(_) => {
+            return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/account/sessions")))));
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+}
+
+pub fn revoke_others_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let mut login_user: models::user::UserWithPreference = models::user::UserWithPreference{..Default::default()};
+    match handlers::account::current_user(req, &conn) {
+        Ok(user) => { login_user = user; }
+        Err(e) => { error!("Errored: {:?}", e); }
+    }
+    let login_id = login_user.id;
+    if login_id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+
+    let current_hash = middlewares::session::current_hash(req);
+    match models::session::delete_others(&conn, &login_id, &current_hash) {
+        Ok(_) => {
+            return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/account/sessions")))));
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+}
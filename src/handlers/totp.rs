@@ -0,0 +1,314 @@
+use iron::{Request, status};
+use iron::modifiers::Redirect;
+use iron::prelude::IronResult;
+use iron::prelude::*;
+use hbs::Template;
+use persistent;
+use hbs::handlebars::to_json;
+
+use iron_sessionstorage::traits::*;
+
+use db;
+use models;
+use helper;
+use handlers;
+use handlers::account::{Login, PendingLogin};
+use middlewares;
+use totp;
+
+fn render_signin_totp(req: &mut Request, message: &str) -> IronResult<Response> {
+    #[derive(Serialize, Debug)]
+    struct Data {
+        csrf_token: String,
+        message: String,
+    }
+    let data = Data {
+        csrf_token: middlewares::csrf::token(req),
+        message: message.to_string(),
+    };
+    let mut resp = Response::new();
+    resp.set_mut(Template::new("account/signin_totp", to_json(&data)))
+        .set_mut(status::Ok);
+    return Ok(resp);
+}
+
+fn render_recovery_codes(login_user: models::user::UserWithPreference, codes: Vec<String>) -> IronResult<Response> {
+    #[derive(Serialize)]
+    struct Data {
+        logged_in: bool,
+        login_user: models::user::UserWithPreference,
+        codes: Vec<String>,
+    }
+    let data = Data {
+        logged_in: true,
+        login_user: login_user,
+        codes: codes,
+    };
+    let mut resp = Response::new();
+    resp.set_mut(Template::new("account/recovery_codes", to_json(&data)))
+        .set_mut(status::Ok);
+    return Ok(resp);
+}
+
+fn get_code(req: &mut Request) -> Result<String, status::Status> {
+    use params::Params;
+    let map = &req.get_ref::<Params>().unwrap();
+    helper::get_param(map, "code")
+}
+
+// Accepts either a current TOTP code that hasn't been used yet or an unused recovery code.
+fn verify_second_factor(conn: &db::PostgresConnection, user_id: &i32, code: &str) -> Result<bool, String> {
+    let totp = try!(models::totp::get_by_user_id(conn, user_id).map_err(|e| format!("{}", e)));
+    if !totp.enabled {
+        return Ok(false);
+    }
+    if let Some(step) = totp::verify(&totp.secret.unwrap_or("".to_string()), code) {
+        return models::totp::use_step(conn, user_id, &step).map_err(|e| format!("{}", e));
+    }
+    models::totp::use_recovery_code(conn, user_id, code).map_err(|e| format!("{}", e))
+}
+
+pub fn get_signin_totp_handler(req: &mut Request) -> IronResult<Response> {
+    if try!(req.session().get::<PendingLogin>()).is_none() {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+    render_signin_totp(req, "")
+}
+
+pub fn post_signin_totp_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+
+    let user_id = match try!(req.session().get::<PendingLogin>()) {
+        Some(pending) => pending.id.parse::<i32>().unwrap_or(0),
+        None => return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin"))))),
+    };
+
+    let code = match get_code(req) {
+        Ok(value) => value,
+        Err(st) => return Ok(Response::with(st)),
+    };
+
+    // Failed codes count toward the same lockout as failed passwords.
+    let username = match models::user::get_by_id(&conn, &user_id) {
+        Ok(user) => user.username,
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    };
+    match models::login_failure::locked_for(&conn, &username) {
+        Ok(0) => {}
+        Ok(seconds) => {
+            return render_signin_totp(req, &handlers::account::lockout_message(seconds));
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+
+    match verify_second_factor(&conn, &user_id, &code) {
+        Ok(true) => {
+            if let Err(e) = models::login_failure::delete_by_user_id(&conn, &user_id) {
+                error!("Errored: {:?}", e);
+            }
+            helper::audit(req, &conn, &user_id, "signin", "", "totp");
+            try!(req.session().set(PendingLogin { id: "".to_string() }));
+            try!(req.session().set(Login { id: user_id.to_string() }));
+            let url = handlers::account::ref_url(req);
+            return Ok(Response::with((status::Found, Redirect(url))));
+        }
+        Ok(false) => {
+            handlers::account::record_login_failure(req, &conn, &username, "bad second factor");
+            return render_signin_totp(req, "Invalid authentication code.");
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+}
+
+pub fn post_setup_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let mut login_user: models::user::UserWithPreference = models::user::UserWithPreference{..Default::default()};
+    match handlers::account::current_user(req, &conn) {
+        Ok(user) => { login_user = user; }
+        Err(e) => { error!("Errored: {:?}", e); }
+    }
+    let login_id = login_user.id;
+    if login_id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+
+    match models::totp::get_by_user_id(&conn, &login_id) {
+        Ok(totp) => {
+            if totp.enabled {
+                return Ok(Response::with(status::BadRequest));
+            }
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+
+    match models::totp::set_secret(&conn, &login_id, &totp::generate_secret()) {
+        Ok(_) => {
+            return Ok(Response::with((status::Found,
+                                      Redirect(helper::redirect_url("/account/settings#two-factor")))));
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+}
+
+pub fn post_enable_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let mut login_user: models::user::UserWithPreference = models::user::UserWithPreference{..Default::default()};
+    match handlers::account::current_user(req, &conn) {
+        Ok(user) => { login_user = user; }
+        Err(e) => { error!("Errored: {:?}", e); }
+    }
+    let login_id = login_user.id;
+    if login_id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+
+    let code = match get_code(req) {
+        Ok(value) => value,
+        Err(st) => return Ok(Response::with(st)),
+    };
+
+    let secret: String;
+    match models::totp::get_by_user_id(&conn, &login_id) {
+        Ok(totp) => {
+            if totp.enabled {
+                return Ok(Response::with(status::BadRequest));
+            }
+            match totp.secret {
+                Some(s) => secret = s,
+                None => return Ok(Response::with(status::BadRequest)),
+            }
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+
+    let step = match totp::verify(&secret, &code) {
+        Some(step) => step,
+        None => {
+            return Ok(Response::with((status::Found,
+                                      Redirect(helper::redirect_url("/account/settings#two-factor")))));
+        }
+    };
+
+    if let Err(e) = models::totp::enable(&conn, &login_id, &step) {
+        error!("Errored: {:?}", e);
+        return Ok(Response::with(status::InternalServerError));
+    }
+    helper::audit(req, &conn, &login_id, "totp.enable", &format!("user:{}", login_id), "");
+    match models::totp::regenerate_recovery_codes(&conn, &login_id) {
+        Ok(codes) => render_recovery_codes(login_user, codes),
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+}
+
+pub fn post_disable_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let mut login_user: models::user::UserWithPreference = models::user::UserWithPreference{..Default::default()};
+    match handlers::account::current_user(req, &conn) {
+        Ok(user) => { login_user = user; }
+        Err(e) => { error!("Errored: {:?}", e); }
+    }
+    let login_id = login_user.id;
+    if login_id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+
+    let code = match get_code(req) {
+        Ok(value) => value,
+        Err(st) => return Ok(Response::with(st)),
+    };
+
+    match models::setting::get_bool(&conn, models::setting::REQUIRE_2FA) {
+        Ok(true) => return Ok(Response::with((status::Forbidden, "two-factor authentication is required by your team"))),
+        Ok(false) => {}
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+
+    match verify_second_factor(&conn, &login_id, &code) {
+        Ok(true) => {}
+        Ok(false) => {
+            return Ok(Response::with((status::Found,
+                                      Redirect(helper::redirect_url("/account/settings#two-factor")))));
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+
+    match models::totp::disable(&conn, &login_id) {
+        Ok(_) => {
+            helper::audit(req, &conn, &login_id, "totp.disable", &format!("user:{}", login_id), "");
+            return Ok(Response::with((status::Found,
+                                      Redirect(helper::redirect_url("/account/settings#two-factor")))));
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+}
+
+pub fn post_recovery_codes_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let mut login_user: models::user::UserWithPreference = models::user::UserWithPreference{..Default::default()};
+    match handlers::account::current_user(req, &conn) {
+        Ok(user) => { login_user = user; }
+        Err(e) => { error!("Errored: {:?}", e); }
+    }
+    let login_id = login_user.id;
+    if login_id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+
+    let code = match get_code(req) {
+        Ok(value) => value,
+        Err(st) => return Ok(Response::with(st)),
+    };
+
+    match verify_second_factor(&conn, &login_id, &code) {
+        Ok(true) => {}
+        Ok(false) => {
+            return Ok(Response::with((status::Found,
+                                      Redirect(helper::redirect_url("/account/settings#two-factor")))));
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+
+    match models::totp::regenerate_recovery_codes(&conn, &login_id) {
+        Ok(codes) => {
+            helper::audit(req, &conn, &login_id, "totp.recovery_codes", &format!("user:{}", login_id), "");
+            render_recovery_codes(login_user, codes)
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+}
@@ -1,3 +1,6 @@
+use std::fs;
+use std::path::Path;
+
 use iron::{Request, status};
 use iron::modifiers::Redirect;
 use iron::prelude::IronResult;
@@ -15,6 +18,8 @@ use handlers;
 use env::CONFIG;
 
 pub const PAGINATES_PER: i32 = 10;
+// Deeper replies are still threaded but stop indenting further.
+const MAX_REPLY_INDENT: usize = 4;
 
 
 pub fn list_handler(req: &mut Request) -> IronResult<Response> {
@@ -30,6 +35,7 @@ pub fn list_handler(req: &mut Request) -> IronResult<Response> {
     }
 
     let page_param: String;
+    let tag_param: String;
 
     {
         use params::{Params, Value};
@@ -40,6 +46,12 @@ pub fn list_handler(req: &mut Request) -> IronResult<Response> {
             }
             _ => page_param = "1".to_string(),
         }
+        match map.get("tag") {
+            Some(&Value::String(ref name)) => {
+                tag_param = name.trim().to_string();
+            }
+            _ => tag_param = "".to_string(),
+        }
     }
 
     let mut resp = Response::new();
@@ -49,6 +61,8 @@ pub fn list_handler(req: &mut Request) -> IronResult<Response> {
         logged_in: bool,
         login_user: models::user::UserWithPreference,
         tweets: Vec<models::tweet::Tweet>,
+        // Set on a hashtag timeline, `/tweet/list?tag=name`.
+        tag_name: String,
         current_page: i32,
         total_page: i32,
         next_page: i32,
@@ -65,7 +79,12 @@ pub fn list_handler(req: &mut Request) -> IronResult<Response> {
     let tweets: Vec<models::tweet::Tweet>;
     let count: i32;
 
-    match models::tweet::list(&conn, &offset, &limit) {
+    let tweets_db = if tag_param == "" {
+        models::tweet::list(&conn, &offset, &limit)
+    } else {
+        models::tweet::tagged(&conn, &tag_param, &offset, &limit)
+    };
+    match tweets_db {
         Ok(tweets_db) => {
             tweets = tweets_db;
         }
@@ -75,7 +94,12 @@ pub fn list_handler(req: &mut Request) -> IronResult<Response> {
         }
     }
 
-    match models::tweet::count(&conn) {
+    let count_db = if tag_param == "" {
+        models::tweet::count(&conn)
+    } else {
+        models::tweet::tagged_count(&conn, &tag_param)
+    };
+    match count_db {
         Ok(count_db) => {
             count = count_db;
         }
@@ -89,6 +113,7 @@ pub fn list_handler(req: &mut Request) -> IronResult<Response> {
         logged_in: login_id != 0,
         login_user: login_user,
         tweets: tweets,
+        tag_name: tag_param,
         current_page: page,
         total_page: count / PAGINATES_PER + 1,
         next_page: page + 1,
@@ -112,7 +137,12 @@ pub fn post_handler(req: &mut Request) -> IronResult<Response> {
         return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
     }
 
+    if login_user.is_guest {
+        return Ok(Response::with(status::Forbidden));
+    }
+
     let body: String;
+    let attachments: Vec<String>;
 
     {
         use params::{Params, Value};
@@ -124,9 +154,37 @@ pub fn post_handler(req: &mut Request) -> IronResult<Response> {
             }
             _ => return Ok(Response::with(status::BadRequest)),
         }
+
+        // `attachment[]` holds the paths the image upload returned for this tweet.
+        match map.get("attachment") {
+            Some(&Value::Array(ref values)) => {
+                attachments = values.iter().filter_map(|value| match *value {
+                    Value::String(ref value) if value != "" => Some(value.to_string()),
+                    _ => None,
+                }).collect();
+            }
+            _ => attachments = Vec::new(),
+        }
     }
 
-    match models::tweet::create(&conn, &login_id, &body) {
+    if body.trim() == "" && attachments.is_empty() {
+        return Ok(Response::with(status::BadRequest));
+    }
+    if attachments.len() > models::tweet::MAX_ATTACHMENTS {
+        return Ok(Response::with(status::BadRequest));
+    }
+    for path in &attachments {
+        match models::upload::is_attachable(&conn, &login_id, path) {
+            Ok(true) => {}
+            Ok(false) => return Ok(Response::with(status::BadRequest)),
+            Err(e) => {
+                error!("Errored: {:?}", e);
+                return Ok(Response::with(status::InternalServerError));
+            }
+        }
+    }
+
+    match models::tweet::create(&conn, &login_id, &body, &attachments) {
         Ok(id) => {
             let title = String::from("New Tweet");
             let path = String::from("tweet");
@@ -165,17 +223,37 @@ pub fn show_handler(req: &mut Request) -> IronResult<Response> {
         .unwrap_or("/");
     let id = id_str.parse::<i32>().unwrap();
 
+    #[derive(Serialize)]
+    struct CommentWithFlags {
+        comment: models::tweet::Comment,
+        editable: bool,
+        deletable: bool,
+        // Left margin in rem; replies indent by nesting depth, up to a limit.
+        indent: usize,
+    }
+
     #[derive(Serialize)]
     struct Data {
         logged_in: bool,
         login_user: models::user::UserWithPreference,
         tweet: models::tweet::Tweet,
-        comments: Vec<models::tweet::Comment>,
+        comments: Vec<CommentWithFlags>,
+        editable: bool,
+        deletable: bool,
     }
 
     let tweet: models::tweet::Tweet;
     let comments: Vec<models::tweet::Comment>;
 
+    match models::tweet::exists(&conn, &id) {
+        Ok(true) => {}
+        Ok(false) => return Ok(Response::with(status::NotFound)),
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+
     match models::tweet::get_by_id(&conn, &id) {
         Ok(tweet_obj) => {
             tweet = tweet_obj;
@@ -196,8 +274,18 @@ pub fn show_handler(req: &mut Request) -> IronResult<Response> {
         }
     }
 
+    // Only the author edits; the author or an admin deletes.
+    let comments = models::tweet::thread(comments).into_iter().map(|(comment, depth)| CommentWithFlags {
+        editable: comment.user_id == login_id,
+        deletable: comment.user_id == login_id || login_user.is_admin,
+        indent: depth.min(MAX_REPLY_INDENT) * 2,
+        comment: comment,
+    }).collect();
+
     let data = Data {
         logged_in: login_id != 0,
+        editable: tweet.user_id == login_id,
+        deletable: tweet.user_id == login_id || login_user.is_admin,
         login_user: login_user,
         tweet: tweet,
         comments: comments,
@@ -220,8 +308,13 @@ pub fn comment_handler(req: &mut Request) -> IronResult<Response> {
         return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
     }
 
+    if login_user.is_guest {
+        return Ok(Response::with(status::Forbidden));
+    }
+
     let id: i32;
     let body: String;
+    let parent_id: Option<i32>;
 
     use params::{Params, Value};
     {
@@ -240,6 +333,37 @@ pub fn comment_handler(req: &mut Request) -> IronResult<Response> {
             }
             _ => return Ok(Response::with(status::BadRequest)),
         }
+
+        match map.find(&["parent_id"]) {
+            Some(&Value::String(ref name)) if name != "" => {
+                match name.parse::<i32>() {
+                    Ok(value) => parent_id = Some(value),
+                    Err(_) => return Ok(Response::with(status::BadRequest)),
+                }
+            }
+            _ => parent_id = None,
+        }
+    }
+
+    // A reply must answer a comment on the same tweet.
+    if let Some(ref parent_id) = parent_id {
+        match models::tweet::get_comment_by_id(&conn, parent_id) {
+            Ok(Some(ref parent)) if parent.tweet_id == id => {}
+            Ok(_) => return Ok(Response::with(status::BadRequest)),
+            Err(e) => {
+                error!("Errored: {:?}", e);
+                return Ok(Response::with(status::InternalServerError));
+            }
+        }
+    }
+
+    match models::tweet::exists(&conn, &id) {
+        Ok(true) => {}
+        Ok(false) => return Ok(Response::with(status::NotFound)),
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
     }
 
     let mut mentions = Vec::new();
@@ -270,7 +394,7 @@ pub fn comment_handler(req: &mut Request) -> IronResult<Response> {
     }
 
 
-    match models::tweet::add_comment(&conn, &login_id, &id, &body) {
+    match models::tweet::add_comment(&conn, &login_id, &id, &parent_id, &body) {
         Ok(_) => {
             let title = String::from("New comment");
             let path = String::from("tweet");
@@ -285,3 +409,231 @@ pub fn comment_handler(req: &mut Request) -> IronResult<Response> {
         }
     }
 }
+
+pub fn update_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let mut login_user: models::user::UserWithPreference = models::user::UserWithPreference{..Default::default()};
+    match handlers::account::current_user(req, &conn) {
+        Ok(user) => { login_user = user; }
+        Err(e) => { error!("Errored: {:?}", e); }
+    }
+    let login_id = login_user.id;
+    if login_id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+
+    if login_user.is_guest {
+        return Ok(Response::with(status::Forbidden));
+    }
+
+    let id = match req.extensions
+        .get::<Router>()
+        .unwrap()
+        .find("id")
+        .unwrap_or("/")
+        .parse::<i32>() {
+        Ok(id) => id,
+        Err(_) => return Ok(Response::with(status::BadRequest)),
+    };
+
+    let action: String;
+    let body: String;
+
+    use params::{Params, Value};
+    {
+        let map = req.get_ref::<Params>().unwrap();
+
+        match map.find(&["action"]) {
+            Some(&Value::String(ref name)) => {
+                action = name.to_string();
+            }
+            _ => return Ok(Response::with(status::BadRequest)),
+        }
+
+        match map.find(&["body"]) {
+            Some(&Value::String(ref name)) => {
+                body = name.to_string();
+            }
+            _ => body = "".to_string(),
+        }
+    }
+
+    let tweet: models::tweet::Tweet;
+
+    match models::tweet::exists(&conn, &id) {
+        Ok(true) => {}
+        Ok(false) => return Ok(Response::with(status::NotFound)),
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+
+    match models::tweet::get_by_id(&conn, &id) {
+        Ok(tweet_obj) => {
+            tweet = tweet_obj;
+        }
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+
+    if action == "update" {
+        if tweet.user_id != login_id {
+            return Ok(Response::with(status::Forbidden));
+        }
+        if body.trim() == "" {
+            return Ok(Response::with(status::BadRequest));
+        }
+        match models::tweet::update(&conn, &id, &body) {
+            Ok(_) => {
+                let url = Url::parse(&format!("{}/tweet/show/{}", &CONFIG.team_domain, id)
+                                         .to_string()).unwrap();
+                return Ok(Response::with((status::Found, Redirect(url))));
+            }
+            Err(e) => {
+                error!("Errored: {:?}", e);
+                return Ok(Response::with(status::InternalServerError));
+            }
+        }
+    }
+    if action == "delete" {
+        if tweet.user_id != login_id && !login_user.is_admin {
+            return Ok(Response::with(status::Forbidden));
+        }
+        match models::tweet::delete_by_id(&conn, &id) {
+            Ok(paths) => {
+                remove_attachments(&conn, &paths);
+                helper::audit(req, &conn, &login_id, "tweet.delete", &format!("/tweet/show/{}", id), &tweet.body);
+                let url = Url::parse(&format!("{}/tweet/list", &CONFIG.team_domain)
+                                         .to_string()).unwrap();
+                return Ok(Response::with((status::Found, Redirect(url))));
+            }
+            Err(e) => {
+                error!("Errored: {:?}", e);
+                return Ok(Response::with(status::InternalServerError));
+            }
+        }
+    }
+    return Ok(Response::with(status::BadRequest));
+}
+
+// Removes the uploads a deleted tweet carried; each was the poster's own and attached only here.
+// The same upload can also be linked from Markdown, so those that still are keep their file.
+fn remove_attachments(conn: &db::PostgresConnection, paths: &Vec<String>) {
+    for path in paths {
+        match models::upload::is_referenced(conn, path) {
+            Ok(false) => {}
+            Ok(true) => continue,
+            Err(e) => {
+                error!("Errored: {:?}", e);
+                continue;
+            }
+        }
+        if let Err(e) = models::upload::delete_by_path(conn, path) {
+            error!("Errored: {:?}", e);
+        }
+        if let Err(e) = fs::remove_file(Path::new("public").join(path)) {
+            error!("Errored: {:?}", e);
+        }
+    }
+}
+
+pub fn comment_update_handler(req: &mut Request) -> IronResult<Response> {
+    let conn = get_pg_connection!(req);
+    let mut login_user: models::user::UserWithPreference = models::user::UserWithPreference{..Default::default()};
+    match handlers::account::current_user(req, &conn) {
+        Ok(user) => { login_user = user; }
+        Err(e) => { error!("Errored: {:?}", e); }
+    }
+    let login_id = login_user.id;
+    if login_id == 0 {
+        return Ok(Response::with((status::Found, Redirect(helper::redirect_url("/signin")))));
+    }
+
+    if login_user.is_guest {
+        return Ok(Response::with(status::Forbidden));
+    }
+
+    let id = match req.extensions
+        .get::<Router>()
+        .unwrap()
+        .find("id")
+        .unwrap_or("/")
+        .parse::<i32>() {
+        Ok(id) => id,
+        Err(_) => return Ok(Response::with(status::BadRequest)),
+    };
+
+    let action: String;
+    let body: String;
+    let comment: models::tweet::Comment;
+
+    use params::{Params, Value};
+    {
+        let map = req.get_ref::<Params>().unwrap();
+
+        match map.find(&["action"]) {
+            Some(&Value::String(ref name)) => {
+                action = name.to_string();
+            }
+            _ => return Ok(Response::with(status::BadRequest)),
+        }
+
+        match map.find(&["body"]) {
+            Some(&Value::String(ref name)) => {
+                body = name.to_string();
+            }
+            _ => body = "".to_string(),
+        }
+    }
+
+    match models::tweet::get_comment_by_id(&conn, &id) {
+        Ok(Some(db_comment)) => {
+            comment = db_comment;
+        }
+        Ok(None) => return Ok(Response::with(status::NotFound)),
+        Err(e) => {
+            error!("Errored: {:?}", e);
+            return Ok(Response::with(status::InternalServerError));
+        }
+    }
+
+    if action == "update" {
+        if comment.user_id != login_id {
+            return Ok(Response::with(status::Forbidden));
+        }
+        if body.trim() == "" {
+            return Ok(Response::with(status::BadRequest));
+        }
+        match models::tweet::update_comment_by_id(&conn, &id, &body) {
+            Ok(_) => {
+                let url = Url::parse(&format!("{}/tweet/show/{}", &CONFIG.team_domain, comment.tweet_id)
+                                         .to_string()).unwrap();
+                return Ok(Response::with((status::Found, Redirect(url))));
+            }
+            Err(e) => {
+                error!("Errored: {:?}", e);
+                return Ok(Response::with(status::InternalServerError));
+            }
+        }
+    }
+    if action == "delete" {
+        if comment.user_id != login_id && !login_user.is_admin {
+            return Ok(Response::with(status::Forbidden));
+        }
+        match models::tweet::delete_comment_by_id(&conn, &id) {
+            Ok(_) => {
+                let url = Url::parse(&format!("{}/tweet/show/{}", &CONFIG.team_domain, comment.tweet_id)
+                                         .to_string()).unwrap();
+                return Ok(Response::with((status::Found, Redirect(url))));
+            }
+            Err(e) => {
+                error!("Errored: {:?}", e);
+                return Ok(Response::with(status::InternalServerError));
+            }
+        }
+    }
+    return Ok(Response::with(status::BadRequest));
+}
@@ -2,6 +2,8 @@ use hbs::Template;
 use serde::ser::Serialize;
 use crypto::sha2::Sha256;
 use crypto::digest::Digest;
+use rand::{Rng, OsRng};
+use base64;
 use slack_hook::{Slack, PayloadBuilder};
 use chrono::{NaiveDateTime};
 use time::Duration;
@@ -30,6 +32,33 @@ pub fn encrypt_password(plain_password: String) -> String {
     return sha256.result_str();
 }
 
+/// Checks a new password against the team's password policy.
+pub fn check_password_policy(password: &str, username: &str) -> Result<(), String> {
+    if password.chars().count() < CONFIG.team_password_min_length {
+        return Err(format!("Password must be at least {} characters.", CONFIG.team_password_min_length));
+    }
+    if username != "" && password.to_lowercase().contains(&username.to_lowercase()) {
+        return Err("Password must not contain your username.".to_string());
+    }
+    if !password.chars().any(|c| c.is_alphabetic()) || password.chars().all(|c| c.is_alphabetic()) {
+        return Err("Password must contain both letters and numbers or symbols.".to_string());
+    }
+    Ok(())
+}
+
+pub fn random_token() -> String {
+    let mut rng = OsRng::new().unwrap();
+    let bytes: Vec<u8> = (0..32).map(|_| rng.gen::<u8>()).collect();
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Hash for single-use secrets (recovery codes, reset tokens) stored at rest.
+pub fn token_hash(token: &str) -> String {
+    let mut sha256 = Sha256::new();
+    sha256.input_str(&format!("{}{}", token, SALT));
+    return sha256.result_str();
+}
+
 pub fn username_hash(username: String) -> String {
     let mut sha256 = Sha256::new();
     sha256.input_str(&format!("{}", username));
@@ -123,6 +152,32 @@ pub fn get_param(map: &Map, name: &str) -> Result<String, status::Status> {
     }
 }
 
+use iron;
+// X-Forwarded-For is only honoured behind a trusted reverse proxy. The client can
+// send entries of its own, so only the last one, added by that proxy, is used.
+pub fn client_ip(req: &iron::Request) -> String {
+    if CONFIG.team_trust_proxy {
+        if let Some(values) = req.headers.get_raw("X-Forwarded-For") {
+            if let Some(value) = values.last() {
+                let forwarded = String::from_utf8_lossy(value);
+                if let Some(ip) = forwarded.split(",").last() {
+                    if ip.trim() != "" {
+                        return ip.trim().to_string();
+                    }
+                }
+            }
+        }
+    }
+    req.remote_addr.ip().to_string()
+}
+
+/// Appends to the audit log; a failed write is logged rather than failing the request.
+pub fn audit(req: &iron::Request, conn: &db::PostgresConnection, user_id: &i32, action: &str, target: &str, detail: &str) {
+    if let Err(e) = models::audit::create(conn, user_id, action, target, detail, &client_ip(req)) {
+        error!("Errored: {:?}", e);
+    }
+}
+
 use iron::Url;
 pub fn redirect_url(path: &str) -> Url {
     let url = Url::parse(&format!("{}{}", &CONFIG.team_domain, path)
@@ -131,23 +186,110 @@ pub fn redirect_url(path: &str) -> Url {
     return url
 }
 
+/// The share token an unlisted post or gist was opened with, from the `t` parameter.
+pub fn share_token_param(req: &mut iron::Request) -> String {
+    use iron::Plugin;
+    use params::Params;
+    match req.get_ref::<Params>() {
+        Ok(map) => get_param(map, "t").unwrap_or(String::new()),
+        Err(_) => String::new(),
+    }
+}
+
+/// The `?t=` query that carries a share token on to the next page; empty without one.
+pub fn share_query(token: &str) -> String {
+    use url::form_urlencoded::byte_serialize;
+    if token == "" {
+        return String::new();
+    }
+    format!("?t={}", byte_serialize(token.as_bytes()).collect::<String>())
+}
+
 // use std;
 use reqwest;
 
-pub fn get_google_email(access_token: String) -> String {
-    #[derive(Deserialize, Default, Debug)]
-    struct Info {
-        email: String,
+pub const GOOGLE_AUTH_URL: &str = "https://accounts.google.com/o/oauth2/v2/auth";
+pub const GOOGLE_TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+const GOOGLE_TOKENINFO_URL: &str = "https://oauth2.googleapis.com/tokeninfo";
+
+// RFC 7636 S256 code challenge
+pub fn pkce_challenge(code_verifier: &str) -> String {
+    let mut sha256 = Sha256::new();
+    sha256.input_str(code_verifier);
+    let mut digest = [0u8; 32];
+    sha256.result(&mut digest);
+    return base64::encode_config(&digest, base64::URL_SAFE_NO_PAD);
+}
+
+#[derive(Deserialize, Default, Debug)]
+pub struct GoogleToken {
+    pub access_token: String,
+    #[serde(default)]
+    pub id_token: String,
+}
+
+pub fn exchange_google_code(code: &str, code_verifier: &str) -> Result<GoogleToken, String> {
+    let params = [
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("code_verifier", code_verifier),
+        ("client_id", CONFIG.team_google_client_id.as_str()),
+        ("client_secret", CONFIG.team_google_client_secret.as_str()),
+        ("redirect_uri", CONFIG.team_google_redirect_url.as_str()),
+    ];
+    let client = try!(reqwest::Client::new().map_err(|e| e.to_string()));
+    let mut res = try!(client.post(GOOGLE_TOKEN_URL).form(&params).send().map_err(|e| e.to_string()));
+    if !res.status().is_success() {
+        return Err(format!("token endpoint returned {}", res.status()));
+    }
+    res.json::<GoogleToken>().map_err(|e| e.to_string())
+}
+
+#[derive(Deserialize, Default, Debug)]
+pub struct GoogleIdToken {
+    #[serde(default)]
+    pub email: String,
+    #[serde(default)]
+    pub email_verified: String,
+    #[serde(default)]
+    pub aud: String,
+    #[serde(default)]
+    pub nonce: String,
+}
+
+// The tokeninfo endpoint checks the id_token signature and expiry for us.
+pub fn get_google_id_token_info(id_token: &str) -> Result<GoogleIdToken, String> {
+    if id_token == "" {
+        return Err("no id_token in token response".to_string());
+    }
+    let url = format!("{}?id_token={}", GOOGLE_TOKENINFO_URL, id_token);
+    let mut res = try!(reqwest::get(&url).map_err(|e| e.to_string()));
+    if !res.status().is_success() {
+        return Err(format!("tokeninfo returned {}", res.status()));
     }
-    let url = &format!("{}{}","https://www.googleapis.com/oauth2/v1/tokeninfo?access_token=", access_token).to_string();
-    let mut res = reqwest::get(url).unwrap();
-    if let Ok(info) = res.json::<Info>() {
-        return info.email
-    } else {
-        return "".to_string()
+    res.json::<GoogleIdToken>().map_err(|e| e.to_string())
+}
+
+pub fn email_domain(email: &str) -> Option<String> {
+    match email.rfind("@") {
+        Some(i) if i > 0 && i + 1 < email.len() => Some(email[i + 1..].to_lowercase()),
+        _ => None,
     }
 }
 
+pub fn google_allow_domains() -> Vec<String> {
+    let domains = format!("{},{}", &CONFIG.team_google_allow_domains, &CONFIG.team_google_allow_domain);
+    return domains.split(",")
+        .map(|s| s.trim().to_lowercase())
+        .filter(|s| s != "")
+        .collect();
+}
+
+pub fn is_allowed_google_domain(domain: &str) -> bool {
+    let domains = google_allow_domains();
+    return domains.is_empty() || domains.contains(&domain.to_lowercase());
+}
+
 pub fn uppercase_first_letter(s: &str) -> String {
     let mut c = s.chars();
     match c.next() {
@@ -163,3 +305,13 @@ pub fn jst_time_formatter(time: NaiveDateTime) -> String {
 pub fn split_menu(menu: String) -> Vec<String> {
     return menu.split(",").map(|s| s.to_string()).collect();
 }
+
+/// Profile links are stored one per line; only http(s) URLs are kept.
+pub fn split_links(links: &str) -> Vec<String> {
+    links.lines()
+        .map(|l| l.trim())
+        .filter(|l| l.starts_with("http://") || l.starts_with("https://"))
+        .take(5)
+        .map(|l| l.to_string())
+        .collect()
+}
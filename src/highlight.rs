@@ -0,0 +1,23 @@
+use syntect::parsing::SyntaxSet;
+use syntect::highlighting::ThemeSet;
+use syntect::html::highlighted_snippet_for_string;
+
+const THEME: &str = "InspiredGitHub";
+
+// Loading the grammars is slow and they are not shareable across threads,
+// so each worker thread loads them once.
+thread_local! {
+    static SYNTAXES: SyntaxSet = SyntaxSet::load_defaults_newlines();
+    static THEMES: ThemeSet = ThemeSet::load_defaults();
+}
+
+/// Renders `code` as highlighted HTML, choosing the grammar by file extension
+/// (or a bare name such as `Makefile`). Unknown ones come out as escaped plain text.
+pub fn to_html(code: &str, extension: &str) -> String {
+    SYNTAXES.with(|syntaxes| THEMES.with(|themes| {
+        let syntax = syntaxes.find_syntax_by_extension(extension)
+            .or_else(|| syntaxes.find_syntax_by_extension(&extension.to_lowercase()))
+            .unwrap_or_else(|| syntaxes.find_syntax_plain_text());
+        highlighted_snippet_for_string(code, syntax, &themes.themes[THEME])
+    }))
+}
@@ -0,0 +1,38 @@
+use lettre::email::EmailBuilder;
+use lettre::transport::EmailTransport;
+use lettre::transport::smtp::{SecurityLevel, SmtpTransportBuilder};
+
+use env::CONFIG;
+
+pub fn enabled() -> bool {
+    CONFIG.team_smtp_host != "" && CONFIG.team_smtp_from != ""
+}
+
+pub fn send(to: &str, subject: &str, body: &str) -> Result<(), String> {
+    if !enabled() {
+        return Err("smtp is not configured".to_string());
+    }
+    let email = try!(EmailBuilder::new()
+        .to(to)
+        .from(CONFIG.team_smtp_from.as_str())
+        .subject(subject)
+        .text(body)
+        .build()
+        .map_err(|e| e.to_string()));
+
+    let security = match CONFIG.team_smtp_security.as_str() {
+        "tls" => SecurityLevel::EncryptedWrapper,
+        "none" => SecurityLevel::NeverEncrypt,
+        _ => SecurityLevel::AlwaysEncrypt,
+    };
+    let mut builder = try!(SmtpTransportBuilder::new((CONFIG.team_smtp_host.as_str(), CONFIG.team_smtp_port))
+        .map_err(|e| e.to_string()))
+        .security_level(security);
+    if CONFIG.team_smtp_username != "" {
+        builder = builder.credentials(&CONFIG.team_smtp_username, &CONFIG.team_smtp_password);
+    }
+    let mut mailer = builder.build();
+    let result = mailer.send(email).map(|_| ()).map_err(|e| e.to_string());
+    mailer.close();
+    result
+}
@@ -21,6 +21,9 @@ extern crate serde_json;
 extern crate serde_derive;
 
 extern crate crypto;
+extern crate rand;
+extern crate base64;
+extern crate qrcode;
 
 extern crate slack_hook;
 
@@ -44,6 +47,10 @@ extern crate log;
 extern crate oauth2;
 extern crate reqwest;
 extern crate url;
+extern crate ldap3;
+extern crate lettre;
+extern crate zip;
+extern crate syntect;
 
 use iron::prelude::*;
 use router::Router;
@@ -55,6 +62,12 @@ mod handlers;
 mod models;
 mod helper;
 mod env;
+mod oidc;
+mod auth;
+mod totp;
+mod mail;
+mod export;
+mod highlight;
 mod middlewares;
 
 fn setup_fern(level: log::LogLevelFilter, verbose: bool) {
@@ -79,17 +92,31 @@ fn setup_fern(level: log::LogLevelFilter, verbose: bool) {
 
 fn main() {
     setup_fern(log::LogLevelFilter::Debug, false);
-    let mount = handlers::router::mount_path();
-    let mut chain = middlewares::setup(mount);
-
-    match db::get_pool(&env::CONFIG.team_database_url.as_str()) {
-        Ok(pool) => chain.link(PRead::<db::PostgresDB>::both(pool)),
+    let pool = match db::get_pool(&env::CONFIG.team_database_url.as_str()) {
+        Ok(pool) => pool,
         Err(err) => {
             error!("postgres: {}", err);
             std::process::exit(-1);
         }
     };
 
+    // `team backfill-tweet-tags` tags the tweets written before hashtags were read, then exits.
+    if std::env::args().nth(1) == Some("backfill-tweet-tags".to_string()) {
+        let conn = pool.get().unwrap();
+        match models::tweet::backfill_tags(&conn) {
+            Ok(count) => info!("Tagged {} tweets", count),
+            Err(err) => {
+                error!("postgres: {}", err);
+                std::process::exit(-1);
+            }
+        }
+        return;
+    }
+
+    let mount = handlers::router::mount_path();
+    let mut chain = middlewares::setup(mount, pool.clone());
+    chain.link(PRead::<db::PostgresDB>::both(pool));
+
     let listen = format!("{}:{}", "0.0.0.0", &env::CONFIG.port);
     info!("Listen {:?}", listen);
     Iron::new(chain).http(listen).unwrap();
@@ -0,0 +1,99 @@
+use iron::prelude::*;
+use iron::{status, method};
+use iron::middleware::{AroundMiddleware, Handler};
+use crypto::util::fixed_time_eq;
+
+use iron_sessionstorage;
+use iron_sessionstorage::traits::*;
+
+use helper;
+
+const PARAM_NAME: &str = "csrf_token";
+const HEADER_NAME: &str = "X-CSRF-Token";
+// The JSON API authenticates with a bearer token instead of the session cookie,
+// which a browser never attaches on its own, so it has nothing to forge.
+const EXEMPT_PREFIX: &str = "/api/";
+
+#[derive(Serialize, Debug, Default)]
+pub struct CsrfToken {
+    token: String,
+}
+
+impl iron_sessionstorage::Value for CsrfToken {
+    fn get_key() -> &'static str {
+        "csrf_token"
+    }
+    fn into_raw(self) -> String {
+        self.token
+    }
+    fn from_raw(value: String) -> Option<Self> {
+        if value.is_empty() {
+            None
+        } else {
+            Some(CsrfToken { token: value })
+        }
+    }
+}
+
+/// Returns the CSRF token bound to the current session, creating one if needed.
+pub fn token(req: &mut Request) -> String {
+    if let Some(csrf) = req.session().get::<CsrfToken>().ok().and_then(|x| x) {
+        return csrf.token;
+    }
+    let token = helper::random_token();
+    let _ = req.session().set(CsrfToken { token: token.clone() });
+    token
+}
+
+fn submitted_token(req: &mut Request) -> String {
+    if let Some(values) = req.headers.get_raw(HEADER_NAME) {
+        if let Some(value) = values.get(0) {
+            return String::from_utf8_lossy(value).to_string();
+        }
+    }
+    use params::{Params, Value};
+    match req.get_ref::<Params>() {
+        Ok(map) => match map.find(&[PARAM_NAME]) {
+            Some(&Value::String(ref value)) => value.to_string(),
+            _ => "".to_string(),
+        },
+        Err(_) => "".to_string(),
+    }
+}
+
+fn verify(req: &mut Request) -> bool {
+    let expected = match req.session().get::<CsrfToken>().ok().and_then(|x| x) {
+        Some(csrf) => csrf.token,
+        None => return false,
+    };
+    let submitted = submitted_token(req);
+    submitted.len() == expected.len() && fixed_time_eq(submitted.as_bytes(), expected.as_bytes())
+}
+
+pub struct Csrf;
+impl AroundMiddleware for Csrf {
+    fn around(self, handler: Box<Handler>) -> Box<Handler> {
+        Box::new(CsrfHandler {
+            handler: handler,
+        }) as Box<Handler>
+    }
+}
+
+struct CsrfHandler<H: Handler> {
+    handler: H,
+}
+impl<H: Handler> Handler for CsrfHandler<H> {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        match req.method {
+            method::Get | method::Head | method::Options => {}
+            _ => {
+                let path = format!("/{}", req.url.path().join("/"));
+                if !path.starts_with(EXEMPT_PREFIX) && !verify(req) {
+                    warn!("CSRF token mismatch: {} {}", req.method, req.url);
+                    return Ok(Response::with((status::Forbidden, "invalid csrf token")));
+                }
+            }
+        }
+        self.handler.handle(req)
+    }
+}
@@ -4,11 +4,16 @@ use hbs::{DirectorySource, HandlebarsEngine};
 use iron::prelude::*;
 use iron::middleware::{AroundMiddleware, Handler};
 use iron_sessionstorage::SessionStorage;
-use iron_sessionstorage::backends::SignedCookieBackend;
 use time;
 
+use db;
 use env::CONFIG;
 
+pub mod csrf;
+pub mod rate_limit;
+pub mod session;
+pub mod two_factor;
+
 struct Logger;
 impl Logger {
     fn log(&self, req: &Request, res: Result<&Response, &IronError>, time: u64) {
@@ -41,7 +46,7 @@ impl<H: Handler> Handler for LoggerHandler<H> {
     }
 }
 
-pub fn setup<H: Handler>(handler: H) -> Chain {
+pub fn setup<H: Handler>(handler: H, pool: db::PostgresPool) -> Chain {
     let mut chain = Chain::new(handler);
 
     let mut hbse = HandlebarsEngine::new();
@@ -51,8 +56,12 @@ pub fn setup<H: Handler>(handler: H) -> Chain {
     }
     chain.link_after(hbse);
 
+    chain.link_around(two_factor::TwoFactor);
+    chain.link_around(csrf::Csrf);
+    chain.link_around(rate_limit::RateLimit);
+
     let secret = &CONFIG.team_secret_cookie.as_bytes();
-    chain.link_around(SessionStorage::new(SignedCookieBackend::new(secret.to_vec())));
+    chain.link_around(SessionStorage::new(session::PostgresBackend::new(pool, secret.to_vec())));
 
     chain.around(Logger);
 
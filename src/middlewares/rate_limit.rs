@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use iron::prelude::*;
+use iron::{status, method};
+use iron::middleware::{AroundMiddleware, Handler};
+use time;
+
+use iron_sessionstorage::traits::*;
+
+use env::CONFIG;
+use helper;
+use handlers::account::Login;
+
+// Entries are pruned once the table grows past this many keys.
+const PRUNE_THRESHOLD: usize = 10000;
+
+lazy_static! {
+    // key -> (window start, hits in window)
+    static ref COUNTERS: Mutex<HashMap<String, (u64, u32)>> = Mutex::new(HashMap::new());
+}
+
+/// Counts a hit against `key` and returns the seconds to wait if the limit
+/// for the current fixed window has been exceeded.
+fn hit(key: &str, limit: u32, window: u64) -> Option<u64> {
+    if limit == 0 || window == 0 {
+        return None;
+    }
+    let now = time::get_time().sec as u64;
+    let mut counters = match COUNTERS.lock() {
+        Ok(counters) => counters,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    if counters.len() > PRUNE_THRESHOLD {
+        counters.retain(|_, &mut (start, _)| now < start + window.max(CONFIG.team_rate_limit_login_window));
+    }
+    let entry = counters.entry(key.to_string()).or_insert((now, 0));
+    if now >= entry.0 + window {
+        *entry = (now, 0);
+    }
+    entry.1 += 1;
+    if entry.1 > limit {
+        Some(entry.0 + window - now)
+    } else {
+        None
+    }
+}
+
+fn is_sign_in(path: &[&str]) -> bool {
+    path == ["signin"] || path == ["signin", "totp"] || path == ["password", "forgot"]
+}
+
+fn is_upload(path: &[&str]) -> bool {
+    path == ["image", "upload"] || path == ["account", "icon", "upload"]
+}
+
+// Covers /:kind/comment, /gist/comment and /tweet/comment, but not edits.
+fn is_comment(path: &[&str]) -> bool {
+    path.len() == 2 && path[1] == "comment"
+}
+
+fn username_param(req: &mut Request) -> String {
+    use params::{Params, Value};
+    match req.get_ref::<Params>() {
+        Ok(map) => match map.find(&["username"]) {
+            Some(&Value::String(ref value)) => value.trim().to_lowercase(),
+            _ => "".to_string(),
+        },
+        Err(_) => "".to_string(),
+    }
+}
+
+// Signed-in writes are limited per user, falling back to the client address.
+fn actor(req: &mut Request) -> String {
+    match req.session().get::<Login>() {
+        Ok(Some(login)) => format!("user:{}", login.id),
+        _ => format!("ip:{}", helper::client_ip(req)),
+    }
+}
+
+fn check(req: &mut Request) -> Option<u64> {
+    if req.method != method::Post {
+        return None;
+    }
+    let path: Vec<String> = req.url.path().iter().map(|s| s.to_string()).collect();
+    let path: Vec<&str> = path.iter().map(|s| s.as_str()).collect();
+
+    if is_sign_in(&path) {
+        let ip = helper::client_ip(req);
+        let wait = hit(&format!("login:ip:{}", ip),
+                       CONFIG.team_rate_limit_login_per_ip,
+                       CONFIG.team_rate_limit_login_window);
+        if wait.is_some() {
+            return wait;
+        }
+        let username = username_param(req);
+        if username != "" {
+            return hit(&format!("login:account:{}", username),
+                       CONFIG.team_rate_limit_login_per_account,
+                       CONFIG.team_rate_limit_login_window);
+        }
+        return None;
+    }
+    if is_upload(&path) {
+        let key = format!("upload:{}", actor(req));
+        return hit(&key, CONFIG.team_rate_limit_uploads, CONFIG.team_rate_limit_window);
+    }
+    if is_comment(&path) {
+        let key = format!("comment:{}", actor(req));
+        return hit(&key, CONFIG.team_rate_limit_comments, CONFIG.team_rate_limit_window);
+    }
+    None
+}
+
+/// Throttles sign-in attempts, uploads and new comments with per-process
+/// fixed-window counters, answering 429 with a Retry-After header.
+pub struct RateLimit;
+impl AroundMiddleware for RateLimit {
+    fn around(self, handler: Box<Handler>) -> Box<Handler> {
+        Box::new(RateLimitHandler {
+            handler: handler,
+        }) as Box<Handler>
+    }
+}
+
+struct RateLimitHandler<H: Handler> {
+    handler: H,
+}
+impl<H: Handler> Handler for RateLimitHandler<H> {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        if let Some(wait) = check(req) {
+            warn!("rate limited {} {} from {}", req.method, req.url, helper::client_ip(req));
+            let mut resp = Response::with((status::TooManyRequests, "Too many requests. Try again later.\n"));
+            resp.headers.set_raw("Retry-After", vec![wait.to_string().into_bytes()]);
+            return Ok(resp);
+        }
+        self.handler.handle(req)
+    }
+}
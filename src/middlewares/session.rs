@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+
+use iron;
+use iron::prelude::*;
+use iron::status;
+use iron::typemap;
+use iron::headers::{SetCookie, UserAgent};
+use iron_sessionstorage::{RawSession, SessionBackend, Value};
+use iron_sessionstorage::cookie;
+use serde_json;
+
+use db;
+use helper;
+use models;
+use handlers::account::Login;
+
+const COOKIE_NAME: &str = "team_session";
+
+struct SessionHash;
+impl typemap::Key for SessionHash {
+    type Value = String;
+}
+
+/// Returns the storage key of the session the request arrived with.
+pub fn current_hash(req: &Request) -> String {
+    req.extensions.get::<SessionHash>().cloned().unwrap_or("".to_string())
+}
+
+fn internal_error<E: ::std::error::Error + Send + 'static>(e: E) -> IronError {
+    IronError::new(e, status::InternalServerError)
+}
+
+/// Session data lives in the `sessions` table; the cookie only carries a
+/// signed random id, and only its hash is stored.
+pub struct PostgresSession {
+    pool: db::PostgresPool,
+    jar: cookie::CookieJar<'static>,
+    session_id: String,
+    values: HashMap<String, String>,
+    // Whether `values` differ from what was loaded, so unchanged sessions aren't rewritten.
+    changed: bool,
+    // Whether the id was minted during this request; only then may a row be inserted.
+    is_new: bool,
+    user_agent: String,
+    ip: String,
+}
+
+impl RawSession for PostgresSession {
+    fn get_raw(&self, key: &str) -> IronResult<Option<String>> {
+        Ok(self.values.get(key).cloned())
+    }
+
+    fn set_raw(&mut self, key: &str, value: String) -> IronResult<()> {
+        if self.values.get(key) == Some(&value) {
+            return Ok(());
+        }
+        if self.session_id.is_empty() {
+            self.session_id = helper::random_token();
+            self.is_new = true;
+        }
+        self.values.insert(key.to_string(), value);
+        self.changed = true;
+        Ok(())
+    }
+
+    fn clear(&mut self) -> IronResult<()> {
+        if !self.session_id.is_empty() {
+            let conn = try!(self.pool.get().map_err(internal_error));
+            try!(models::session::delete_by_hash(&conn, &helper::token_hash(&self.session_id)).map_err(internal_error));
+        }
+        self.session_id = "".to_string();
+        self.values.clear();
+        self.changed = false;
+        Ok(())
+    }
+
+    fn write(&self, res: &mut Response) -> IronResult<()> {
+        if self.session_id.is_empty() {
+            self.jar.signed().remove(COOKIE_NAME);
+        } else if self.changed {
+            let user_id = self.values.get(Login::get_key()).and_then(|id| id.parse::<i32>().ok());
+            let data = try!(serde_json::to_string(&self.values).map_err(internal_error));
+            let conn = try!(self.pool.get().map_err(internal_error));
+            let saved = try!(models::session::save(&conn, &helper::token_hash(&self.session_id), &user_id, &data,
+                                                   &self.user_agent, &self.ip, &self.is_new).map_err(internal_error));
+            if !saved {
+                // Revoked while this request ran; it stays revoked.
+                self.jar.signed().remove(COOKIE_NAME);
+                return set_cookies(&self.jar, res);
+            }
+
+            let mut c = cookie::Cookie::new(COOKIE_NAME.to_string(), self.session_id.clone());
+            c.httponly = true;
+            c.path = Some("/".to_string());
+            // Keeps the session off cross-site subresource requests such as embedded gist scripts.
+            c.custom.insert("SameSite".to_string(), "Lax".to_string());
+            self.jar.signed().add(c);
+        }
+
+        set_cookies(&self.jar, res)
+    }
+}
+
+fn set_cookies(jar: &cookie::CookieJar<'static>, res: &mut Response) -> IronResult<()> {
+    let cookies: Vec<String> = jar.delta().into_iter().map(|c| format!("{}", c)).collect();
+    if let Some(set_cookie) = res.headers.get_mut::<SetCookie>() {
+        set_cookie.extend(cookies);
+        return Ok(());
+    }
+    res.headers.set(SetCookie(cookies));
+    Ok(())
+}
+
+pub struct PostgresBackend {
+    pool: db::PostgresPool,
+    signing_key: Vec<u8>,
+}
+
+impl PostgresBackend {
+    pub fn new(pool: db::PostgresPool, signing_key: Vec<u8>) -> Self {
+        PostgresBackend {
+            pool: pool,
+            signing_key: signing_key,
+        }
+    }
+
+    fn load(&self, session_hash: &String, ip: &String) -> Result<Option<HashMap<String, String>>, String> {
+        let conn = try!(self.pool.get().map_err(|e| format!("{}", e)));
+        match try!(models::session::get_data(&conn, session_hash).map_err(|e| format!("{}", e))) {
+            Some(data) => {
+                try!(models::session::touch(&conn, session_hash, ip).map_err(|e| format!("{}", e)));
+                Ok(Some(serde_json::from_str(&data).unwrap_or(HashMap::new())))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl SessionBackend for PostgresBackend {
+    type S = PostgresSession;
+
+    fn from_request(&self, req: &mut Request) -> Self::S {
+        let mut jar = cookie::CookieJar::new(&self.signing_key);
+        if let Some(cookies) = req.headers.get::<iron::headers::Cookie>() {
+            for c in cookies.iter() {
+                if let Ok(c) = cookie::Cookie::parse(&c) {
+                    jar.add_original(c);
+                }
+            }
+        }
+        let user_agent: String = req.headers.get::<UserAgent>()
+            .map(|ua| ua.to_string())
+            .unwrap_or("".to_string())
+            .chars()
+            .take(1024)
+            .collect();
+        let ip = helper::client_ip(req);
+
+        let mut session_id = jar.signed().find(COOKIE_NAME).map(|c| c.value).unwrap_or("".to_string());
+        let mut values = HashMap::new();
+        if !session_id.is_empty() {
+            let session_hash = helper::token_hash(&session_id);
+            match self.load(&session_hash, &ip) {
+                Ok(Some(v)) => {
+                    values = v;
+                    req.extensions.insert::<SessionHash>(session_hash);
+                }
+                Ok(None) => {
+                    // Expired or revoked; start over with a fresh id on the next write.
+                    session_id = "".to_string();
+                }
+                Err(e) => {
+                    error!("Errored: {:?}", e);
+                    session_id = "".to_string();
+                }
+            }
+        }
+
+        PostgresSession {
+            pool: self.pool.clone(),
+            jar: jar,
+            session_id: session_id,
+            values: values,
+            changed: false,
+            is_new: false,
+            user_agent: user_agent,
+            ip: ip,
+        }
+    }
+}
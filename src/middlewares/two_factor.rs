@@ -0,0 +1,72 @@
+use iron::prelude::*;
+use iron::status;
+use iron::modifiers::Redirect;
+use iron::middleware::{AroundMiddleware, Handler};
+use persistent;
+
+use iron_sessionstorage::traits::*;
+
+use db;
+use helper;
+use models;
+use handlers::account::Login;
+
+// Paths a signed-in user can still reach before enrolling a second factor.
+const EXEMPT_PATHS: &[&str] = &["/account/settings", "/signin", "/signout", "/notification_count"];
+const EXEMPT_PREFIXES: &[&str] = &["/account/totp/", "/signin/", "/css/", "/js/", "/img/", "/webfonts/",
+                                   "/codemirror/", "/favicons/"];
+
+fn is_exempt(path: &str) -> bool {
+    EXEMPT_PATHS.iter().any(|p| path == *p) || EXEMPT_PREFIXES.iter().any(|p| path.starts_with(p))
+}
+
+// Returns true when the team requires 2FA and the signed-in user has not enabled it.
+fn needs_enrollment(req: &mut Request) -> Result<bool, String> {
+    let user_id = match try!(req.session().get::<Login>().map_err(|e| format!("{:?}", e))) {
+        Some(login) => login.id.parse::<i32>().unwrap_or(0),
+        None => return Ok(false),
+    };
+    if user_id == 0 {
+        return Ok(false);
+    }
+    let pool = try!(req.get::<persistent::Read<db::PostgresDB>>().map_err(|e| format!("{:?}", e)));
+    let conn = try!(pool.get().map_err(|e| format!("{:?}", e)));
+    if !try!(models::setting::get_bool(&conn, models::setting::REQUIRE_2FA).map_err(|e| format!("{}", e))) {
+        return Ok(false);
+    }
+    let totp = try!(models::totp::get_by_user_id(&conn, &user_id).map_err(|e| format!("{}", e)));
+    Ok(!totp.enabled)
+}
+
+pub struct TwoFactor;
+impl AroundMiddleware for TwoFactor {
+    fn around(self, handler: Box<Handler>) -> Box<Handler> {
+        Box::new(TwoFactorHandler {
+            handler: handler,
+        }) as Box<Handler>
+    }
+}
+
+struct TwoFactorHandler<H: Handler> {
+    handler: H,
+}
+impl<H: Handler> Handler for TwoFactorHandler<H> {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let path = format!("/{}", req.url.path().join("/"));
+        if !is_exempt(&path) {
+            match needs_enrollment(req) {
+                Ok(true) => {
+                    return Ok(Response::with((status::Found,
+                                              Redirect(helper::redirect_url("/account/settings#two-factor")))));
+                }
+                Ok(false) => {}
+                // Fail closed: a user we can't check must not get past the requirement.
+                Err(e) => {
+                    error!("Errored: {:?}", e);
+                    return Ok(Response::with(status::InternalServerError));
+                }
+            }
+        }
+        self.handler.handle(req)
+    }
+}
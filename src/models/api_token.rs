@@ -0,0 +1,57 @@
+use postgres::error::Error;
+use chrono::NaiveDateTime;
+use db;
+use helper;
+
+#[derive(Serialize, Debug)]
+pub struct ApiToken {
+    pub id: i32,
+    pub name: String,
+    pub created: NaiveDateTime,
+    pub last_used: Option<NaiveDateTime>,
+    pub formated_created: String,
+    pub formated_last_used: String,
+}
+
+/// Issues a token and returns it in plain text; only its hash is stored,
+/// so this is the one time it can be shown.
+pub fn create(conn: &db::PostgresConnection, user_id: &i32, name: &String) -> Result<String, Error> {
+    let token = helper::random_token();
+    try!(conn.execute(
+        "INSERT INTO api_tokens (user_id, name, token_hash) VALUES ($1, $2, $3)",
+        &[&user_id, &name, &helper::token_hash(&token)]));
+    Ok(token)
+}
+
+pub fn list_by_user_id(conn: &db::PostgresConnection, user_id: &i32) -> Result<Vec<ApiToken>, Error> {
+    let mut tokens: Vec<ApiToken> = Vec::new();
+    for row in &try!(conn.query(
+        "SELECT id, name, created, last_used from api_tokens where user_id = $1 order by id desc", &[&user_id])) {
+        let mut token = ApiToken {
+            id: row.get("id"),
+            name: row.get("name"),
+            created: row.get("created"),
+            last_used: row.get("last_used"),
+            formated_created: "".to_string(),
+            formated_last_used: "".to_string(),
+        };
+        token.formated_created = helper::jst_time_formatter(token.created);
+        if let Some(last_used) = token.last_used {
+            token.formated_last_used = helper::jst_time_formatter(last_used);
+        }
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+pub fn delete(conn: &db::PostgresConnection, id: &i32, user_id: &i32) -> Result<(), Error> {
+    conn.execute("DELETE FROM api_tokens WHERE id = $1 and user_id = $2", &[&id, &user_id]).map(|_| ())
+}
+
+/// Returns the user a token belongs to and records that it was used.
+pub fn user_id_for(conn: &db::PostgresConnection, token: &str) -> Result<Option<i32>, Error> {
+    let rows = try!(conn.query(
+        "UPDATE api_tokens set last_used = now() WHERE token_hash = $1 returning user_id",
+        &[&helper::token_hash(token)]));
+    Ok(rows.iter().next().map(|row| row.get("user_id")))
+}
@@ -0,0 +1,88 @@
+use postgres::error::Error;
+use chrono::NaiveDateTime;
+use db;
+use helper;
+use models;
+
+// The audit log is append-only: events are inserted and read, never updated or deleted.
+
+pub const ACTIONS: [&str; 27] = [
+    "signin", "signin.failed", "signout",
+    "post.delete", "post.share", "post.pin", "post.unpin", "gist.delete", "tweet.delete",
+    "account.username", "account.delete", "token.create", "token.delete",
+    "password.change", "password.reset", "totp.enable", "totp.disable", "totp.recovery_codes",
+    "admin.role", "admin.logout", "admin.deactivate", "admin.reactivate", "admin.transfer", "admin.unpin",
+    "admin.settings", "admin.invite_create", "admin.invite_delete",
+];
+
+#[derive(Serialize, Debug)]
+pub struct Event {
+    pub id: i32,
+    pub user: models::user::User,
+    pub action: String,
+    pub target: String,
+    pub detail: String,
+    pub ip: String,
+    pub created: NaiveDateTime,
+    pub formated_created: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Filter {
+    pub action: String,
+    pub username: String,
+    // Inclusive `YYYY-MM-DD` bounds in JST, as dates are shown; empty means unbounded.
+    pub from: String,
+    pub to: String,
+}
+
+const WHERE: &str = "
+    where ($1 = '' or a.action = $1)
+    and ($2 = '' or u.username = $2)
+    and a.created + interval '9 hours' >= coalesce(nullif($3, '')::date, '-infinity'::date)
+    and a.created + interval '9 hours' < coalesce(nullif($4, '')::date + 1, 'infinity'::date)";
+
+/// Records an event. `user_id` 0 stands for an anonymous actor, e.g. a failed sign-in.
+pub fn create(conn: &db::PostgresConnection, user_id: &i32, action: &str, target: &str, detail: &str, ip: &str) -> Result<(), Error> {
+    conn.execute(
+        "INSERT INTO audit_events (user_id, action, target, detail, ip) VALUES (nullif($1, 0), $2, $3, $4, $5)",
+        &[&user_id, &action, &target, &detail, &ip]
+    ).map(|_| ())
+}
+
+pub fn list(conn: &db::PostgresConnection, filter: &Filter, offset: &i32, limit: &i32) -> Result<Vec<Event>, Error> {
+    let mut events: Vec<Event> = Vec::new();
+    for row in &try!(conn.query(&format!("
+        SELECT a.id, coalesce(a.user_id, 0) as user_id, a.action, a.target, a.detail, a.ip, a.created,
+        coalesce(u.username, '') as username, u.icon_url, coalesce(u.deactivated, false) as deactivated
+        from audit_events as a left join users as u on u.id = a.user_id
+        {} order by a.id desc offset $5::int limit $6::int", WHERE),
+        &[&filter.action, &filter.username, &filter.from, &filter.to, &offset, &limit])) {
+        let mut event = Event {
+            id: row.get("id"),
+            user: models::user::User {
+                id: row.get("user_id"),
+                username: row.get("username"),
+                icon_url: row.get("icon_url"),
+                username_hash: helper::username_hash(row.get("username")),
+                deactivated: row.get("deactivated"),
+            },
+            action: row.get("action"),
+            target: row.get("target"),
+            detail: row.get("detail"),
+            ip: row.get("ip"),
+            created: row.get("created"),
+            formated_created: "".to_string(),
+        };
+        event.formated_created = helper::jst_time_formatter(event.created);
+        events.push(event);
+    }
+    Ok(events)
+}
+
+pub fn count(conn: &db::PostgresConnection, filter: &Filter) -> Result<i32, Error> {
+    let rows = try!(conn.query(&format!("
+        SELECT count(*)::int as count from audit_events as a left join users as u on u.id = a.user_id {}", WHERE),
+        &[&filter.action, &filter.username, &filter.from, &filter.to]));
+    Ok(rows.get(0).get("count"))
+}
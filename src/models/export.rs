@@ -0,0 +1,173 @@
+use postgres::error::Error;
+use chrono::NaiveDateTime;
+use db;
+use models;
+
+// Everything a user has written or saved, as plain rows for the data export.
+
+#[derive(Serialize, Debug)]
+pub struct Post {
+    pub id: i32,
+    pub kind: String,
+    pub title: String,
+    pub body: String,
+    pub status: String,
+    pub visibility: String,
+    pub tags: Vec<String>,
+    pub created: NaiveDateTime,
+    pub updated: NaiveDateTime,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Gist {
+    pub id: i32,
+    pub description: String,
+    pub visibility: String,
+    pub tags: Vec<String>,
+    pub files: Vec<models::gist::File>,
+    pub created: NaiveDateTime,
+    pub updated: NaiveDateTime,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Tweet {
+    pub id: i32,
+    pub body: String,
+    // Paths of the attached images, `img/posts/<name>.png`.
+    pub attachments: Vec<String>,
+    pub created: NaiveDateTime,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Comment {
+    pub id: i32,
+    pub target: String,
+    pub target_id: i32,
+    pub body: String,
+    pub created: NaiveDateTime,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Star {
+    pub gist_id: i32,
+    pub description: String,
+    pub created: NaiveDateTime,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Stock {
+    pub post_id: i32,
+    pub kind: String,
+    pub title: String,
+    pub created: NaiveDateTime,
+}
+
+pub fn posts(conn: &db::PostgresConnection, user_id: &i32) -> Result<Vec<Post>, Error> {
+    let mut posts: Vec<Post> = Vec::new();
+    for row in &try!(conn.query("
+        SELECT p.id, p.kind, p.title, p.body, p.status, p.visibility, p.created, p.updated,
+        coalesce((SELECT string_agg(tg.name, ',' order by tg.name) from taggings as t
+                  join tags as tg on tg.id = t.tag_id where t.post_id = p.id), '') as tags
+        from posts as p where p.user_id = $1 order by p.id", &[&user_id])) {
+        let tags: String = row.get("tags");
+        posts.push(Post {
+            id: row.get("id"),
+            kind: row.get("kind"),
+            title: row.get("title"),
+            body: row.get("body"),
+            status: row.get("status"),
+            visibility: row.get("visibility"),
+            tags: tags.split(",").filter(|t| *t != "").map(|t| t.to_string()).collect(),
+            created: row.get("created"),
+            updated: row.get("updated"),
+        });
+    }
+    Ok(posts)
+}
+
+pub fn gists(conn: &db::PostgresConnection, user_id: &i32) -> Result<Vec<Gist>, Error> {
+    let mut gists: Vec<Gist> = Vec::new();
+    for row in &try!(conn.query("
+        SELECT g.id, coalesce(g.description, '') as description, g.visibility, g.created, g.updated,
+        coalesce((SELECT string_agg(tg.name, ',' order by tg.name) from gist_taggings as t
+                  join tags as tg on tg.id = t.tag_id where t.gist_id = g.id), '') as tags
+        from gists as g where g.user_id = $1 order by g.id", &[&user_id])) {
+        let id: i32 = row.get("id");
+        let tags: String = row.get("tags");
+        gists.push(Gist {
+            id: id,
+            description: row.get("description"),
+            visibility: row.get("visibility"),
+            tags: tags.split(",").filter(|t| *t != "").map(|t| t.to_string()).collect(),
+            files: try!(models::gist::get_files(conn, &id)),
+            created: row.get("created"),
+            updated: row.get("updated"),
+        });
+    }
+    Ok(gists)
+}
+
+pub fn tweets(conn: &db::PostgresConnection, user_id: &i32) -> Result<Vec<Tweet>, Error> {
+    let mut tweets: Vec<Tweet> = Vec::new();
+    for row in &try!(conn.query("SELECT id, body, created from tweets where user_id = $1 order by id", &[&user_id])) {
+        let id: i32 = row.get("id");
+        tweets.push(Tweet {
+            id: id,
+            body: row.get("body"),
+            attachments: try!(models::tweet::get_attachments_by_tweet_id(conn, &id)).into_iter().map(|a| a.path).collect(),
+            created: row.get("created"),
+        });
+    }
+    Ok(tweets)
+}
+
+pub fn comments(conn: &db::PostgresConnection, user_id: &i32) -> Result<Vec<Comment>, Error> {
+    let mut comments: Vec<Comment> = Vec::new();
+    for row in &try!(conn.query("
+        (SELECT id, 'post' as target, post_id as target_id, body, created from post_comments where user_id = $1)
+        union all
+        (SELECT id, 'gist' as target, gist_id as target_id, body, created from gist_comments where user_id = $1)
+        union all
+        (SELECT id, 'tweet' as target, tweet_id as target_id, body, created from tweet_comments where user_id = $1)
+        order by created", &[&user_id])) {
+        comments.push(Comment {
+            id: row.get("id"),
+            target: row.get("target"),
+            target_id: row.get("target_id"),
+            body: row.get("body"),
+            created: row.get("created"),
+        });
+    }
+    Ok(comments)
+}
+
+pub fn stocks(conn: &db::PostgresConnection, user_id: &i32) -> Result<Vec<Stock>, Error> {
+    let mut stocks: Vec<Stock> = Vec::new();
+    for row in &try!(conn.query("
+        SELECT s.post_id, p.kind, p.title, s.created
+        from stocks as s join posts as p on p.id = s.post_id
+        where s.user_id = $1 and (p.visibility = 'public' or p.user_id = $1) order by s.id", &[&user_id])) {
+        stocks.push(Stock {
+            post_id: row.get("post_id"),
+            kind: row.get("kind"),
+            title: row.get("title"),
+            created: row.get("created"),
+        });
+    }
+    Ok(stocks)
+}
+
+pub fn stars(conn: &db::PostgresConnection, user_id: &i32) -> Result<Vec<Star>, Error> {
+    let mut stars: Vec<Star> = Vec::new();
+    for row in &try!(conn.query("
+        SELECT s.gist_id, coalesce(g.description, '') as description, s.created
+        from gist_stars as s join gists as g on g.id = s.gist_id
+        where s.user_id = $1 and (g.visibility <> 'private' or g.user_id = $1) order by s.id", &[&user_id])) {
+        stars.push(Star {
+            gist_id: row.get("gist_id"),
+            description: row.get("description"),
+            created: row.get("created"),
+        });
+    }
+    Ok(stars)
+}
@@ -0,0 +1,55 @@
+use postgres::error::Error;
+use db;
+
+pub fn follow_user(conn: &db::PostgresConnection, user_id: &i32, followee_id: &i32) -> Result<(), Error> {
+    conn.execute(
+        "INSERT INTO follows (user_id, followee_id)
+        SELECT $1, $2
+        WHERE NOT EXISTS (SELECT 1 FROM follows WHERE user_id = $1 and followee_id = $2)",
+        &[&user_id, &followee_id]
+    ).map(|_| ())
+}
+
+pub fn unfollow_user(conn: &db::PostgresConnection, user_id: &i32, followee_id: &i32) -> Result<(), Error> {
+    conn.execute(
+        "DELETE FROM follows WHERE user_id = $1 and followee_id = $2", &[&user_id, &followee_id]
+    ).map(|_| ())
+}
+
+pub fn is_following_user(conn: &db::PostgresConnection, user_id: &i32, followee_id: &i32) -> Result<bool, Error> {
+    let rows = try!(conn.query(
+        "SELECT count(*)::int as count from follows where user_id = $1 and followee_id = $2",
+        &[&user_id, &followee_id]));
+    let count: i32 = rows.get(0).get("count");
+    Ok(count > 0)
+}
+
+pub fn follower_count(conn: &db::PostgresConnection, followee_id: &i32) -> Result<i32, Error> {
+    let rows = try!(conn.query(
+        "SELECT count(*)::int as count from follows where followee_id = $1", &[&followee_id]));
+    Ok(rows.get(0).get("count"))
+}
+
+pub fn follow_tag(conn: &db::PostgresConnection, user_id: &i32, tag_id: &i32) -> Result<(), Error> {
+    conn.execute(
+        "INSERT INTO tag_follows (user_id, tag_id)
+        SELECT $1, $2
+        WHERE NOT EXISTS (SELECT 1 FROM tag_follows WHERE user_id = $1 and tag_id = $2)",
+        &[&user_id, &tag_id]
+    ).map(|_| ())
+}
+
+pub fn unfollow_tag(conn: &db::PostgresConnection, user_id: &i32, tag_id: &i32) -> Result<(), Error> {
+    conn.execute(
+        "DELETE FROM tag_follows WHERE user_id = $1 and tag_id = $2", &[&user_id, &tag_id]
+    ).map(|_| ())
+}
+
+pub fn is_following_tag(conn: &db::PostgresConnection, user_id: &i32, tag_name: &String) -> Result<bool, Error> {
+    let rows = try!(conn.query(
+        "SELECT count(*)::int as count from tag_follows as f join tags as t on t.id = f.tag_id
+        where f.user_id = $1 and t.name = $2",
+        &[&user_id, &tag_name]));
+    let count: i32 = rows.get(0).get("count");
+    Ok(count > 0)
+}
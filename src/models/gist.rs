@@ -1,78 +1,161 @@
 use postgres::error::Error;
+use postgres::rows::{Row, Rows};
+use postgres::transaction::Transaction;
 use db;
 use models;
 use helper;
 use chrono::{NaiveDateTime};
+use diff;
+use url::percent_encoding::{utf8_percent_encode, PATH_SEGMENT_ENCODE_SET};
+use highlight;
 
 #[derive(Serialize, Debug)]
 pub struct Gist {
     pub id: i32,
     pub user_id: i32,
     pub description: String,
-    pub filename: String,
-    pub code: String,
+    pub filenames: String,
+    pub languages: String,
+    pub visibility: String,
+    // Opens an unlisted gist for non-authors.
+    #[serde(skip_serializing)]
+    pub share_token: String,
+    pub tags: Vec<models::tag::Tag>,
+    pub star_count: i32,
+    pub files: Vec<File>,
+    pub forked_from: Option<i32>,
+    pub forked_from_username: String,
+    pub fork_count: i32,
     pub created: NaiveDateTime,
     pub formated_created: String,
     pub user: models::user::User,
 }
 
-pub fn create(conn: &db::PostgresConnection, user_id: &i32, description: &String, filename: &String, code: &String) -> Result<(i32), Error> {
-    let mut gist_id = 0;
-    for row in &conn.query("
-        INSERT INTO gists (user_id, description, filename, code)
-        VALUES ($1, $2, $3, $4) returning id;",
-        &[&user_id, &description, &filename, &code]).unwrap() {
-        gist_id = row.get("id");
+impl Gist {
+    pub fn visible_to(&self, user_id: &i32, token: &str) -> bool {
+        models::post::is_visible(&self.visibility, &self.user_id, &self.share_token, user_id, token)
+    }
+
+    /// The token links to the gist must carry; empty unless it is unlisted.
+    pub fn link_token(&self) -> &str {
+        models::post::link_token(&self.visibility, &self.share_token)
+    }
+
+    /// The tags as the comma separated list the forms use.
+    pub fn tag_names(&self) -> String {
+        self.tags.iter().map(|t| t.name.as_str()).collect::<Vec<&str>>().join(",")
     }
-    Ok(gist_id)
 }
 
-pub fn list(conn: &db::PostgresConnection, offset: &i32, limit: &i32) -> Result<Vec<Gist>, Error> {
-    let mut gists: Vec<Gist> = Vec::new();
-    for row in &conn.query("
-        SELECT g.id, g.user_id, g.description, g.filename, g.code, g.created, u.username, u.icon_url
-        from gists as g
-        join users as u on u.id = g.user_id
-        order by g.id desc offset $1::int limit $2::int", &[&offset, &limit]).unwrap() {
-        let mut gist = Gist {
-            id: row.get("id"),
-            user_id: row.get("user_id"),
-            description: row.get("description"),
-            filename: row.get("filename"),
-            code: row.get("code"),
-            created: row.get("created"),
-            formated_created: "".to_string(),
-            user: models::user::User{
-                id: row.get("user_id"),
-                username: row.get("username"),
-                icon_url: row.get("icon_url"),
-                username_hash: helper::username_hash(row.get("username")),
-            },
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct File {
+    pub filename: String,
+    pub code: String,
+    // A name from `LANGUAGES` chosen by the author, or empty to go by the extension.
+    pub language_override: String,
+    pub language: String,
+    // The filename as it appears in `/gist/raw/:id/:filename` links.
+    pub url_filename: String,
+    // Highlighted markup; only filled in by `highlight`.
+    pub html: String,
+}
+
+impl File {
+    pub fn new(filename: &str, code: &str, language_override: &str) -> File {
+        File {
+            filename: filename.to_string(),
+            code: code.to_string(),
+            language_override: language_override.to_string(),
+            language: if language_override == "" { language(filename) } else { language_override }.to_string(),
+            url_filename: utf8_percent_encode(filename, PATH_SEGMENT_ENCODE_SET).to_string(),
+            html: "".to_string(),
+        }
+    }
+
+    pub fn highlight(&mut self) {
+        let hint = match LANGUAGES.iter().find(|&&(_, name)| name == self.language_override) {
+            Some(&(ext, _)) => ext.to_string(),
+            None => extension(&self.filename).to_string(),
         };
-        gist.formated_created = helper::jst_time_formatter(gist.created);
-        gists.push(gist);
+        self.html = highlight::to_html(&self.code, &hint);
     }
-    Ok(gists)
 }
 
-pub fn count(conn: &db::PostgresConnection) -> Result<i32, Error> {
-    let rows = &conn.query("SELECT count(*)::int as count from gists", &[]).unwrap();
-    let row = rows.get(0);
-    let count = row.get("count");
-    Ok(count)
+// Display names for the extensions the editor knows; anything else is plain text.
+const LANGUAGES: [(&str, &str); 40] = [
+    ("c", "C"), ("h", "C"), ("cc", "C++"), ("cpp", "C++"), ("hpp", "C++"), ("cs", "C#"),
+    ("clj", "Clojure"), ("coffee", "CoffeeScript"), ("css", "CSS"), ("d", "D"),
+    ("erl", "Erlang"), ("go", "Go"), ("hs", "Haskell"), ("html", "HTML"), ("java", "Java"),
+    ("js", "JavaScript"), ("json", "JSON"), ("jsx", "JSX"), ("kt", "Kotlin"), ("lisp", "Common Lisp"),
+    ("lua", "Lua"), ("md", "Markdown"), ("pl", "Perl"), ("php", "PHP"), ("proto", "Protocol Buffer"),
+    ("py", "Python"), ("rb", "Ruby"), ("rs", "Rust"), ("scala", "Scala"), ("sh", "Shell"),
+    ("bash", "Shell"), ("st", "Smalltalk"), ("sql", "SQL"), ("swift", "Swift"), ("toml", "TOML"),
+    ("ts", "TypeScript"), ("xml", "XML"), ("yml", "YAML"), ("yaml", "YAML"), ("dockerfile", "Dockerfile"),
+];
+
+// The part after the last dot, or the whole name for files like `Makefile`.
+fn extension(filename: &str) -> &str {
+    match filename.rfind('.') {
+        Some(i) => &filename[i + 1..],
+        None => filename,
+    }
 }
 
-pub fn get_by_id(conn: &db::PostgresConnection, id: &i32) -> Result<Gist, Error> {
-    let rows = &conn.query("SELECT g.id, g.user_id, g.description, g.filename, g.code, g.created, u.username, u.icon_url 
-                            from gists as g join users as u on u.id=g.user_id 
-                            where g.id = $1", &[&id]).unwrap();
-    let row = rows.get(0);
+pub fn language(filename: &str) -> &'static str {
+    let ext = extension(filename).to_lowercase();
+    LANGUAGES.iter().find(|&&(e, _)| e == ext).map(|&(_, name)| name).unwrap_or("Text")
+}
+
+/// The names an author can pick as a language override, in display order.
+pub fn language_names() -> Vec<&'static str> {
+    let mut names: Vec<&'static str> = LANGUAGES.iter().map(|&(_, name)| name).collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+const SELECT_GISTS: &str = "
+    SELECT g.id, g.user_id, coalesce(g.description, '') as description, g.visibility, coalesce(g.share_token, '') as share_token, g.created, u.username, u.icon_url, u.deactivated,
+    coalesce((select string_agg(f.filename, ', ' order by f.position) from gist_files as f where f.gist_id = g.id), '') as filenames,
+    coalesce((select string_agg(f.filename || '/' || f.language, E'\\n' order by f.position) from gist_files as f where f.gist_id = g.id), '') as file_languages,
+    g.forked_from,
+    coalesce((select ou.username from gists as o join users as ou on ou.id = o.user_id where o.id = g.forked_from), '') as forked_from_username,
+    (select count(*)::int from gists as fk where fk.forked_from = g.id) as fork_count,
+    (select count(*)::int from gist_stars as s where s.gist_id = g.id) as star_count
+    from gists as g
+    join users as u on u.id = g.user_id";
+
+// `file_languages` holds one `filename/override` line per file; filenames never contain a slash or a line break.
+fn languages(file_languages: &str) -> String {
+    let mut names: Vec<&str> = Vec::new();
+    for line in file_languages.lines() {
+        if let Some(i) = line.rfind('/') {
+            let (filename, language_override) = (&line[..i], &line[i + 1..]);
+            let name = if language_override == "" { language(filename) } else { language_override };
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+    names.join(", ")
+}
+
+fn from_row(row: &Row) -> Gist {
+    let file_languages: String = row.get("file_languages");
     let mut gist = Gist {
         id: row.get("id"),
         user_id: row.get("user_id"),
         description: row.get("description"),
-        filename: row.get("filename"),
-        code: row.get("code"),
+        filenames: row.get("filenames"),
+        languages: languages(&file_languages),
+        visibility: row.get("visibility"),
+        share_token: row.get("share_token"),
+        tags: Vec::new(),
+        star_count: row.get("star_count"),
+        files: Vec::new(),
+        forked_from: row.get("forked_from"),
+        forked_from_username: row.get("forked_from_username"),
+        fork_count: row.get("fork_count"),
         created: row.get("created"),
         formated_created: "".to_string(),
         user: models::user::User{
@@ -80,19 +163,310 @@ pub fn get_by_id(conn: &db::PostgresConnection, id: &i32) -> Result<Gist, Error>
             username: row.get("username"),
             icon_url: row.get("icon_url"),
             username_hash: helper::username_hash(row.get("username")),
+            deactivated: row.get("deactivated"),
         },
     };
     gist.formated_created = helper::jst_time_formatter(gist.created);
-    Ok(gist)
+    gist
 }
 
-pub fn update(conn: &db::PostgresConnection, id: &i32, description: &String, filename: &String, code: &String) -> Result<(), Error> {
-    conn.execute(
-        "UPDATE gists set description = $1, filename = $2, code = $3 WHERE id = $4", &[&description, &filename, &code, &id]
-    ).unwrap();
+fn from_rows_with_tags(conn: &db::PostgresConnection, rows: &Rows) -> Result<Vec<Gist>, Error> {
+    let mut gists: Vec<Gist> = Vec::new();
+    for row in rows.iter() {
+        let mut gist = from_row(&row);
+        gist.tags = try!(models::tag::get_tags_by_gist_id(conn, &gist.id));
+        gists.push(gist);
+    }
+    Ok(gists)
+}
+
+fn insert_files(trans: &Transaction, gist_id: &i32, files: &Vec<File>) -> Result<(), Error> {
+    for (position, file) in files.iter().enumerate() {
+        let position = position as i32;
+        try!(trans.execute(
+            "INSERT INTO gist_files (gist_id, filename, code, language, position) VALUES ($1, $2, $3, $4, $5)",
+            &[&gist_id, &file.filename, &file.code, &file.language_override, &position]));
+    }
     Ok(())
 }
 
+// Every save is kept as a revision: `user_id` is whoever saved, not the owner.
+fn insert_revision(trans: &Transaction, gist_id: &i32, user_id: &i32, description: &String, files: &Vec<File>) -> Result<(), Error> {
+    let rows = try!(trans.query("
+        INSERT INTO gist_revisions (gist_id, user_id, description)
+        VALUES ($1, $2, $3) returning id;",
+        &[&gist_id, &user_id, &description]));
+    let revision_id: i32 = rows.get(0).get("id");
+    for (position, file) in files.iter().enumerate() {
+        let position = position as i32;
+        try!(trans.execute(
+            "INSERT INTO gist_revision_files (revision_id, filename, code, position) VALUES ($1, $2, $3, $4)",
+            &[&revision_id, &file.filename, &file.code, &position]));
+    }
+    Ok(())
+}
+
+pub fn create(conn: &db::PostgresConnection, user_id: &i32, description: &String, files: &Vec<File>, visibility: &String, tags: &String) -> Result<(i32), Error> {
+    create_with_origin(conn, user_id, description, files, visibility, tags, &None)
+}
+
+fn create_with_origin(conn: &db::PostgresConnection, user_id: &i32, description: &String, files: &Vec<File>, visibility: &String, tags: &String, forked_from: &Option<i32>) -> Result<(i32), Error> {
+    let trans = try!(conn.transaction());
+    let rows = try!(trans.query("
+        INSERT INTO gists (user_id, description, visibility, forked_from, share_token)
+        VALUES ($1, $2, $3, $4, $5) returning id;",
+        &[&user_id, &description, &visibility, &forked_from, &helper::random_token()]));
+    let gist_id: i32 = rows.get(0).get("id");
+    try!(insert_files(&trans, &gist_id, files));
+    try!(insert_revision(&trans, &gist_id, user_id, description, files));
+    try!(models::tag::set_gist_tags(&trans, &gist_id, tags));
+    try!(trans.commit());
+    Ok(gist_id)
+}
+
+/// Copies a gist's current description, files and tags into a new gist owned by `user_id`.
+/// The fork keeps the original's visibility so an unlisted gist does not become listed.
+pub fn fork(conn: &db::PostgresConnection, user_id: &i32, gist: &Gist) -> Result<(i32), Error> {
+    create_with_origin(conn, user_id, &gist.description, &gist.files, &gist.visibility, &gist.tag_names(), &Some(gist.id))
+}
+
+/// Public gists, plus the viewer's own unlisted and private ones.
+pub fn list(conn: &db::PostgresConnection, viewer_id: &i32, offset: &i32, limit: &i32) -> Result<Vec<Gist>, Error> {
+    let rows = try!(conn.query(&format!("{} where g.visibility = 'public' or g.user_id = $3 order by g.id desc offset $1::int limit $2::int", SELECT_GISTS), &[&offset, &limit, &viewer_id]));
+    from_rows_with_tags(conn, &rows)
+}
+
+/// Gists carrying the tag, with the same visibility rule as `list`.
+pub fn tagged(conn: &db::PostgresConnection, tag_name: &String, viewer_id: &i32, offset: &i32, limit: &i32) -> Result<Vec<Gist>, Error> {
+    let rows = try!(conn.query(&format!("{}
+        join gist_taggings as t on t.gist_id = g.id
+        join tags as tg on tg.id = t.tag_id
+        where tg.name = $1 and (g.visibility = 'public' or g.user_id = $2)
+        order by g.id desc offset $3::int limit $4::int", SELECT_GISTS), &[&tag_name, &viewer_id, &offset, &limit]));
+    from_rows_with_tags(conn, &rows)
+}
+
+pub fn tagged_count(conn: &db::PostgresConnection, tag_name: &String, viewer_id: &i32) -> Result<i32, Error> {
+    let rows = try!(conn.query("
+        SELECT count(*)::int as count from gists as g
+        join gist_taggings as t on t.gist_id = g.id
+        join tags as tg on tg.id = t.tag_id
+        where tg.name = $1 and (g.visibility = 'public' or g.user_id = $2)", &[&tag_name, &viewer_id]));
+    Ok(rows.get(0).get("count"))
+}
+
+/// All of a user's own gists, whatever their visibility.
+pub fn owned_by(conn: &db::PostgresConnection, user_id: &i32, offset: &i32, limit: &i32) -> Result<Vec<Gist>, Error> {
+    let rows = try!(conn.query(&format!("{} where g.user_id = $1 order by g.id desc offset $2::int limit $3::int", SELECT_GISTS), &[&user_id, &offset, &limit]));
+    from_rows_with_tags(conn, &rows)
+}
+
+pub fn count(conn: &db::PostgresConnection, viewer_id: &i32) -> Result<i32, Error> {
+    let rows = &conn.query("SELECT count(*)::int as count from gists where visibility = 'public' or user_id = $1", &[&viewer_id]).unwrap();
+    let row = rows.get(0);
+    let count = row.get("count");
+    Ok(count)
+}
+
+pub fn get_by_id(conn: &db::PostgresConnection, id: &i32) -> Result<Gist, Error> {
+    let rows = try!(conn.query(&format!("{} where g.id = $1", SELECT_GISTS), &[&id]));
+    let mut gist = from_row(&rows.get(0));
+    gist.files = try!(get_files(conn, id));
+    gist.tags = try!(models::tag::get_tags_by_gist_id(conn, id));
+    Ok(gist)
+}
+
+pub fn exists(conn: &db::PostgresConnection, id: &i32) -> Result<bool, Error> {
+    let rows = try!(conn.query("SELECT count(*)::int as count from gists where id = $1", &[&id]));
+    let count: i32 = rows.get(0).get("count");
+    Ok(count > 0)
+}
+
+pub fn get_files(conn: &db::PostgresConnection, gist_id: &i32) -> Result<Vec<File>, Error> {
+    let rows = try!(conn.query(
+        "SELECT filename, code, language from gist_files where gist_id = $1 order by position, id", &[&gist_id]));
+    Ok(rows.iter().map(|row| {
+        let filename: String = row.get("filename");
+        let code: String = row.get("code");
+        let language_override: String = row.get("language");
+        File::new(&filename, &code, &language_override)
+    }).collect())
+}
+
+/// Replaces the description and the whole file list of a gist, recording a new revision.
+pub fn update(conn: &db::PostgresConnection, id: &i32, user_id: &i32, description: &String, files: &Vec<File>, visibility: &String, tags: &String) -> Result<(), Error> {
+    let trans = try!(conn.transaction());
+    try!(trans.execute("UPDATE gists set description = $1, visibility = $2, share_token = coalesce(share_token, $4), updated = now() WHERE id = $3",
+                       &[&description, &visibility, &id, &helper::random_token()]));
+    try!(trans.execute("DELETE FROM gist_files WHERE gist_id = $1", &[&id]));
+    try!(insert_files(&trans, id, files));
+    try!(insert_revision(&trans, id, user_id, description, files));
+    try!(models::tag::set_gist_tags(&trans, id, tags));
+    trans.commit()
+}
+
+#[derive(Serialize, Debug)]
+pub struct Revision {
+    pub id: i32,
+    pub user: models::user::User,
+    pub description: String,
+    pub description_changed: bool,
+    pub diffs: Vec<FileDiff>,
+    pub created: NaiveDateTime,
+    pub formated_created: String,
+    #[serde(skip_serializing)]
+    files: Vec<File>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct FileDiff {
+    pub filename: String,
+    pub added: bool,
+    pub removed: bool,
+    pub lines: Vec<DiffLine>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DiffLine {
+    pub added: bool,
+    pub removed: bool,
+    // Stands for unchanged lines left out between hunks.
+    pub skipped: bool,
+    pub text: String,
+}
+
+const DIFF_CONTEXT: usize = 3;
+
+fn diff_lines(old: &str, new: &str) -> Vec<DiffLine> {
+    let all: Vec<DiffLine> = diff::lines(old, new).into_iter().map(|d| match d {
+        diff::Result::Left(l) => DiffLine { added: false, removed: true, skipped: false, text: l.to_string() },
+        diff::Result::Both(l, _) => DiffLine { added: false, removed: false, skipped: false, text: l.to_string() },
+        diff::Result::Right(r) => DiffLine { added: true, removed: false, skipped: false, text: r.to_string() },
+    }).collect();
+
+    // A line is kept when a change lies within DIFF_CONTEXT lines on either side:
+    // one pass forward and one backward, each tracking the nearest change seen.
+    let mut keep = vec![false; all.len()];
+    let mut since_change = DIFF_CONTEXT + 1;
+    for (i, line) in all.iter().enumerate() {
+        since_change = if line.added || line.removed { 0 } else { since_change.saturating_add(1) };
+        keep[i] = since_change <= DIFF_CONTEXT;
+    }
+    let mut until_change = DIFF_CONTEXT + 1;
+    for (i, line) in all.iter().enumerate().rev() {
+        until_change = if line.added || line.removed { 0 } else { until_change.saturating_add(1) };
+        keep[i] = keep[i] || until_change <= DIFF_CONTEXT;
+    }
+
+    let mut lines: Vec<DiffLine> = Vec::new();
+    for (line, keep) in all.into_iter().zip(keep) {
+        if keep {
+            lines.push(line);
+        } else if lines.last().map(|l| !l.skipped).unwrap_or(true) {
+            lines.push(DiffLine { added: false, removed: false, skipped: true, text: "".to_string() });
+        }
+    }
+    lines
+}
+
+/// What changed from one revision's files to the next, by filename.
+fn diff_files(old: &Vec<File>, new: &Vec<File>) -> Vec<FileDiff> {
+    let mut diffs: Vec<FileDiff> = Vec::new();
+    for file in new {
+        match old.iter().find(|f| f.filename == file.filename) {
+            Some(before) => {
+                if before.code != file.code {
+                    diffs.push(FileDiff { filename: file.filename.to_string(), added: false, removed: false, lines: diff_lines(&before.code, &file.code) });
+                }
+            }
+            None => {
+                diffs.push(FileDiff { filename: file.filename.to_string(), added: true, removed: false, lines: diff_lines("", &file.code) });
+            }
+        }
+    }
+    for file in old {
+        if !new.iter().any(|f| f.filename == file.filename) {
+            diffs.push(FileDiff { filename: file.filename.to_string(), added: false, removed: true, lines: diff_lines(&file.code, "") });
+        }
+    }
+    diffs
+}
+
+pub fn revision_count(conn: &db::PostgresConnection, gist_id: &i32) -> Result<i32, Error> {
+    let rows = try!(conn.query("SELECT count(*)::int as count from gist_revisions where gist_id = $1", &[&gist_id]));
+    Ok(rows.get(0).get("count"))
+}
+
+/// A page of a gist's revisions, newest first, each with its diff against the one before.
+/// Only the files of the page, plus the revision just before it, are loaded.
+pub fn revisions(conn: &db::PostgresConnection, gist_id: &i32, offset: &i32, limit: &i32) -> Result<Vec<Revision>, Error> {
+    let mut revisions: Vec<Revision> = Vec::new();
+    for row in &try!(conn.query("
+        SELECT r.id, r.user_id, r.description, r.created, u.username, u.icon_url, u.deactivated
+        from gist_revisions as r join users as u on u.id = r.user_id
+        where r.gist_id = $1 order by r.id desc offset $2::int limit $3::int + 1", &[&gist_id, &offset, &limit])) {
+        let mut revision = Revision {
+            id: row.get("id"),
+            user: models::user::User{
+                id: row.get("user_id"),
+                username: row.get("username"),
+                icon_url: row.get("icon_url"),
+                username_hash: helper::username_hash(row.get("username")),
+                deactivated: row.get("deactivated"),
+            },
+            description: row.get("description"),
+            description_changed: false,
+            diffs: Vec::new(),
+            created: row.get("created"),
+            formated_created: "".to_string(),
+            files: Vec::new(),
+        };
+        revision.formated_created = helper::jst_time_formatter(revision.created);
+        revisions.push(revision);
+    }
+    let (newest, oldest) = match (revisions.first(), revisions.last()) {
+        (Some(newest), Some(oldest)) => (newest.id, oldest.id),
+        _ => return Ok(revisions),
+    };
+    for row in &try!(conn.query("
+        SELECT f.revision_id, f.filename, f.code from gist_revision_files as f
+        join gist_revisions as r on r.id = f.revision_id
+        where r.gist_id = $1 and r.id between $2 and $3 order by f.position, f.id", &[&gist_id, &oldest, &newest])) {
+        let revision_id: i32 = row.get("revision_id");
+        let filename: String = row.get("filename");
+        let code: String = row.get("code");
+        if let Some(revision) = revisions.iter_mut().find(|r| r.id == revision_id) {
+            revision.files.push(File::new(&filename, &code, ""));
+        }
+    }
+    Ok(diff_page(revisions, limit))
+}
+
+// Diffs each revision against the one after it in the list. The extra revision past
+// the page only serves as the base of the page's oldest diff.
+fn diff_page(mut revisions: Vec<Revision>, limit: &i32) -> Vec<Revision> {
+    let has_previous = revisions.len() as i32 > *limit;
+    for i in 0..revisions.len() {
+        let (current, rest) = revisions.split_at_mut(i + 1);
+        let revision = &mut current[i];
+        match rest.first() {
+            Some(previous) => {
+                revision.diffs = diff_files(&previous.files, &revision.files);
+                revision.description_changed = previous.description != revision.description;
+            }
+            None => {
+                if !has_previous {
+                    revision.diffs = diff_files(&Vec::new(), &revision.files);
+                }
+            }
+        }
+    }
+    if has_previous {
+        revisions.pop();
+    }
+    revisions
+}
+
 pub fn delete_by_id(conn: &db::PostgresConnection, id: &i32) -> Result<(), Error> {
     conn.execute(
         "DELETE FROM gists WHERE id = $1",
@@ -100,6 +474,50 @@ pub fn delete_by_id(conn: &db::PostgresConnection, id: &i32) -> Result<(), Error
     ).map(|_| ())
 }
 
+/// Hands the non-private gists of `from_user_id` over to `to_user_id`; returns how many moved.
+pub fn transfer_owner(trans: &Transaction, from_user_id: &i32, to_user_id: &i32) -> Result<u64, Error> {
+    trans.execute(
+        "UPDATE gists set user_id = $2 WHERE user_id = $1 and visibility <> 'private'",
+        &[&from_user_id, &to_user_id]
+    )
+}
+
+pub fn star(conn: &db::PostgresConnection, user_id: &i32, gist_id: &i32) -> Result<(), Error> {
+    conn.execute(
+        "INSERT INTO gist_stars (user_id, gist_id) SELECT $1::int, $2::int
+        WHERE NOT EXISTS (SELECT 1 FROM gist_stars WHERE user_id = $1 and gist_id = $2)",
+        &[&user_id, &gist_id]
+    ).map(|_| ())
+}
+
+pub fn unstar(conn: &db::PostgresConnection, user_id: &i32, gist_id: &i32) -> Result<(), Error> {
+    conn.execute(
+        "DELETE FROM gist_stars WHERE user_id = $1 and gist_id = $2",
+        &[&user_id, &gist_id]
+    ).map(|_| ())
+}
+
+pub fn is_starred(conn: &db::PostgresConnection, user_id: &i32, gist_id: &i32) -> Result<bool, Error> {
+    let rows = try!(conn.query("SELECT count(*)::int as count from gist_stars where user_id = $1 and gist_id = $2", &[&user_id, &gist_id]));
+    let count: i32 = rows.get(0).get("count");
+    Ok(count > 0)
+}
+
+/// Gists the user starred, most recent star first; ones made private since are left out.
+pub fn starred_list(conn: &db::PostgresConnection, user_id: &i32, offset: &i32, limit: &i32) -> Result<Vec<Gist>, Error> {
+    let rows = try!(conn.query(&format!("{}
+        join gist_stars as st on st.gist_id = g.id
+        where st.user_id = $1 and (g.visibility <> 'private' or g.user_id = $1)
+        order by st.id desc offset $2::int limit $3::int", SELECT_GISTS), &[&user_id, &offset, &limit]));
+    from_rows_with_tags(conn, &rows)
+}
+
+pub fn starred_count(conn: &db::PostgresConnection, user_id: &i32) -> Result<i32, Error> {
+    let rows = try!(conn.query("SELECT count(*)::int as count from gist_stars as st join gists as g on g.id = st.gist_id
+        where st.user_id = $1 and (g.visibility <> 'private' or g.user_id = $1)", &[&user_id]));
+    Ok(rows.get(0).get("count"))
+}
+
 #[derive(Serialize, Debug, Default)]
 pub struct Comment {
     pub id: i32,
@@ -118,7 +536,7 @@ pub fn add_comment(conn: &db::PostgresConnection, user_id: &i32, gist_id: &i32,
 
 pub fn get_comments_by_gist_id(conn: &db::PostgresConnection, id: &i32) -> Result<Vec<Comment>, Error> {
     let mut comments: Vec<Comment> = Vec::new();
-    for row in &conn.query("SELECT c.id, c.user_id, c.gist_id, c.body, u.username, u.icon_url from gist_comments as c join users as u on u.id = c.user_id where c.gist_id = $1 order by id asc", &[&id]).unwrap() {
+    for row in &conn.query("SELECT c.id, c.user_id, c.gist_id, c.body, u.username, u.icon_url, u.deactivated from gist_comments as c join users as u on u.id = c.user_id where c.gist_id = $1 order by id asc", &[&id]).unwrap() {
         comments.push(Comment {
             id: row.get("id"),
             user_id: row.get("user_id"),
@@ -129,6 +547,7 @@ pub fn get_comments_by_gist_id(conn: &db::PostgresConnection, id: &i32) -> Resul
                 username: row.get("username"),
                 icon_url: row.get("icon_url"),
                 username_hash: helper::username_hash(row.get("username")),
+                deactivated: row.get("deactivated"),
             }
         });
     }
@@ -136,7 +555,7 @@ pub fn get_comments_by_gist_id(conn: &db::PostgresConnection, id: &i32) -> Resul
 }
 
 pub fn get_comment_by_id(conn: &db::PostgresConnection, id: &i32) -> Result<Comment, Error> {
-    let rows = &conn.query("SELECT c.*, u.username, u.icon_url from gist_comments as c join users as u on u.id = c.user_id where c.id = $1", &[&id]).unwrap();
+    let rows = &conn.query("SELECT c.*, u.username, u.icon_url, u.deactivated from gist_comments as c join users as u on u.id = c.user_id where c.id = $1", &[&id]).unwrap();
     let row = rows.get(0);
     let comment = Comment {
         id: row.get("id"),
@@ -148,6 +567,7 @@ pub fn get_comment_by_id(conn: &db::PostgresConnection, id: &i32) -> Result<Comm
             username: row.get("username"),
             icon_url: row.get("icon_url"),
             username_hash: helper::username_hash(row.get("username")),
+            deactivated: row.get("deactivated"),
         },
     };
     Ok(comment)
@@ -166,3 +586,127 @@ pub fn delete_comment_by_id(conn: &db::PostgresConnection, id: &i32) -> Result<(
     ).unwrap();
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn numbered(lines: usize) -> String {
+        (1..lines + 1).map(|i| i.to_string()).collect::<Vec<String>>().join("\n")
+    }
+
+    fn changed(lines: usize, at: &[usize]) -> String {
+        (1..lines + 1).map(|i| if at.contains(&i) { format!("{}!", i) } else { i.to_string() })
+            .collect::<Vec<String>>().join("\n")
+    }
+
+    // Renders a diff as one token per line: `-old`, `+new`, ` same` or `...` for skipped lines.
+    fn render(lines: &Vec<DiffLine>) -> Vec<String> {
+        lines.iter().map(|l| {
+            if l.skipped { "...".to_string() }
+            else if l.added { format!("+{}", l.text) }
+            else if l.removed { format!("-{}", l.text) }
+            else { format!(" {}", l.text) }
+        }).collect()
+    }
+
+    fn revision(id: i32, description: &str, files: Vec<File>) -> Revision {
+        Revision {
+            id: id,
+            user: models::user::User{..Default::default()},
+            description: description.to_string(),
+            description_changed: false,
+            diffs: Vec::new(),
+            created: NaiveDate::from_ymd(2017, 6, 1).and_hms(0, 0, 0),
+            formated_created: "".to_string(),
+            files: files,
+        }
+    }
+
+    #[test]
+    fn context_stops_at_the_start_of_a_file() {
+        assert_eq!(render(&diff_lines(&numbered(10), &changed(10, &[1]))),
+                   vec!["-1", "+1!", " 2", " 3", " 4", "..."]);
+    }
+
+    #[test]
+    fn context_stops_at_the_end_of_a_file() {
+        assert_eq!(render(&diff_lines(&numbered(10), &changed(10, &[10]))),
+                   vec!["...", " 7", " 8", " 9", "-10", "+10!"]);
+    }
+
+    #[test]
+    fn hunks_with_shared_context_are_joined() {
+        assert_eq!(render(&diff_lines(&numbered(12), &changed(12, &[2, 8]))),
+                   vec![" 1", "-2", "+2!", " 3", " 4", " 5", " 6", " 7", "-8", "+8!", " 9", " 10", " 11", "..."]);
+    }
+
+    #[test]
+    fn hunks_apart_are_split_by_one_marker() {
+        assert_eq!(render(&diff_lines(&numbered(13), &changed(13, &[2, 10]))),
+                   vec![" 1", "-2", "+2!", " 3", " 4", " 5", "...", " 7", " 8", " 9", "-10", "+10!", " 11", " 12", " 13"]);
+    }
+
+    #[test]
+    fn unchanged_files_have_no_diff() {
+        let files = vec![File::new("a.rs", "fn main() {}", "")];
+        assert!(diff_files(&files, &files).is_empty());
+    }
+
+    #[test]
+    fn added_and_removed_files_are_marked() {
+        let old = vec![File::new("a.rs", "a", ""), File::new("b.rs", "b", "")];
+        let new = vec![File::new("a.rs", "a", ""), File::new("c.rs", "c\nd", "")];
+        let diffs = diff_files(&old, &new);
+        assert_eq!(diffs.len(), 2);
+        assert_eq!((diffs[0].filename.as_str(), diffs[0].added, diffs[0].removed), ("c.rs", true, false));
+        assert_eq!(render(&diffs[0].lines), vec!["+c", "+d"]);
+        assert_eq!((diffs[1].filename.as_str(), diffs[1].added, diffs[1].removed), ("b.rs", false, true));
+        assert_eq!(render(&diffs[1].lines), vec!["-b"]);
+    }
+
+    #[test]
+    fn renamed_files_show_as_removed_and_added() {
+        let old = vec![File::new("old.rs", "same", "")];
+        let new = vec![File::new("new.rs", "same", "")];
+        let diffs = diff_files(&old, &new);
+        let names: Vec<(&str, bool, bool)> = diffs.iter().map(|d| (d.filename.as_str(), d.added, d.removed)).collect();
+        assert_eq!(names, vec![("new.rs", true, false), ("old.rs", false, true)]);
+    }
+
+    #[test]
+    fn edited_files_are_diffed() {
+        let diffs = diff_files(&vec![File::new("a.rs", "1\n2", "")], &vec![File::new("a.rs", "1\n3", "")]);
+        assert_eq!(diffs.len(), 1);
+        assert!(!diffs[0].added && !diffs[0].removed);
+        assert_eq!(render(&diffs[0].lines), vec![" 1", "-2", "+3"]);
+    }
+
+    #[test]
+    fn page_diffs_its_oldest_revision_against_the_look_back_revision() {
+        let revisions = vec![
+            revision(3, "c", vec![File::new("a.rs", "3", "")]),
+            revision(2, "b", vec![File::new("a.rs", "2", "")]),
+            revision(1, "b", vec![File::new("a.rs", "1", "")]),
+        ];
+        let page = diff_page(revisions, &2);
+        assert_eq!(page.iter().map(|r| r.id).collect::<Vec<i32>>(), vec![3, 2]);
+        assert_eq!(render(&page[0].diffs[0].lines), vec!["-2", "+3"]);
+        assert!(page[0].description_changed);
+        assert_eq!(render(&page[1].diffs[0].lines), vec!["-1", "+2"]);
+        assert!(!page[1].description_changed);
+    }
+
+    #[test]
+    fn first_revision_shows_every_file_as_added() {
+        let revisions = vec![
+            revision(2, "", vec![File::new("a.rs", "2", "")]),
+            revision(1, "", vec![File::new("a.rs", "1", "")]),
+        ];
+        let page = diff_page(revisions, &2);
+        assert_eq!(page.len(), 2);
+        assert!(page[1].diffs[0].added);
+        assert_eq!(render(&page[1].diffs[0].lines), vec!["+1"]);
+    }
+}
@@ -0,0 +1,83 @@
+use postgres::error::Error;
+use postgres::transaction::Transaction;
+use chrono::NaiveDateTime;
+use db;
+use helper;
+use models;
+
+#[derive(Serialize, Debug)]
+pub struct Invite {
+    pub id: i32,
+    pub user: models::user::User,
+    pub max_uses: i32,
+    pub uses: i32,
+    pub expires: NaiveDateTime,
+    pub formated_expires: String,
+    pub expired: bool,
+}
+
+pub fn create(conn: &db::PostgresConnection, user_id: &i32, token: &String, days: &i32, max_uses: &i32) -> Result<i32, Error> {
+    let rows = try!(conn.query("
+        INSERT INTO invites (user_id, token_hash, max_uses, expires)
+        VALUES ($1, $2, $3, now() + $4::int * interval '1 day') returning id;",
+        &[&user_id, &helper::token_hash(token), &max_uses, &days]));
+    let row = rows.get(0);
+    Ok(row.get("id"))
+}
+
+pub fn list(conn: &db::PostgresConnection) -> Result<Vec<Invite>, Error> {
+    let mut invites: Vec<Invite> = Vec::new();
+    for row in &try!(conn.query("
+        SELECT i.id, i.user_id, i.max_uses, i.uses, i.expires, i.expires < now() as expired, u.username, u.icon_url, u.deactivated
+        from invites as i join users as u on u.id = i.user_id
+        order by i.id desc", &[])) {
+        let mut invite = Invite {
+            id: row.get("id"),
+            user: models::user::User {
+                id: row.get("user_id"),
+                username: row.get("username"),
+                icon_url: row.get("icon_url"),
+                username_hash: helper::username_hash(row.get("username")),
+                deactivated: row.get("deactivated"),
+            },
+            max_uses: row.get("max_uses"),
+            uses: row.get("uses"),
+            expires: row.get("expires"),
+            formated_expires: "".to_string(),
+            expired: row.get("expired"),
+        };
+        invite.formated_expires = helper::jst_time_formatter(invite.expires);
+        invites.push(invite);
+    }
+    Ok(invites)
+}
+
+pub fn delete(conn: &db::PostgresConnection, id: &i32) -> Result<(), Error> {
+    conn.execute("DELETE FROM invites WHERE id = $1", &[&id]).map(|_| ())
+}
+
+/// Returns whether the token belongs to an unexpired invite with uses left.
+pub fn is_valid(conn: &db::PostgresConnection, token: &String) -> Result<bool, Error> {
+    let rows = try!(conn.query(
+        "SELECT count(*)::int as count from invites where token_hash = $1 and expires > now() and uses < max_uses",
+        &[&helper::token_hash(token)]));
+    let count: i32 = rows.get(0).get("count");
+    Ok(count > 0)
+}
+
+/// The id of the invite the token belongs to, if it is unexpired with uses left.
+pub fn get_valid_id(conn: &db::PostgresConnection, token: &String) -> Result<Option<i32>, Error> {
+    let rows = try!(conn.query(
+        "SELECT id from invites where token_hash = $1 and expires > now() and uses < max_uses",
+        &[&helper::token_hash(token)]));
+    Ok(rows.iter().next().map(|row| row.get("id")))
+}
+
+/// Uses up one slot of the invite; returns false if it is no longer valid.
+/// Runs in the transaction that creates the account.
+pub fn redeem(trans: &Transaction, id: &i32) -> Result<bool, Error> {
+    let updated = try!(trans.execute(
+        "UPDATE invites set uses = uses + 1, updated = now() WHERE id = $1 and expires > now() and uses < max_uses",
+        &[&id]));
+    Ok(updated > 0)
+}
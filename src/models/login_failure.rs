@@ -0,0 +1,42 @@
+use postgres::error::Error;
+use db;
+use env::CONFIG;
+
+pub fn create(conn: &db::PostgresConnection, username: &str, ip: &String) -> Result<(), Error> {
+    conn.execute(
+        "INSERT INTO login_failures (username, ip) VALUES (lower($1), $2)",
+        &[&username, &ip]
+    ).map(|_| ())
+}
+
+/// Forgets the failures of a user once they have fully signed in.
+pub fn delete_by_user_id(conn: &db::PostgresConnection, user_id: &i32) -> Result<(), Error> {
+    conn.execute(
+        "DELETE FROM login_failures WHERE username = (SELECT lower(username) FROM users WHERE id = $1)",
+        &[&user_id]
+    ).map(|_| ())
+}
+
+/// Returns the seconds left on the account's lockout, or 0 when it may sign in.
+///
+/// Once the failures of the past day reach the threshold, each further one
+/// doubles the wait after the latest failure, up to the configured maximum.
+pub fn locked_for(conn: &db::PostgresConnection, username: &str) -> Result<i32, Error> {
+    if CONFIG.team_lockout_threshold <= 0 {
+        return Ok(0);
+    }
+    let rows = try!(conn.query("
+        SELECT count(*)::int as count,
+        coalesce(extract(epoch from now() - max(created)), 0)::int as elapsed
+        from login_failures where username = lower($1) and created > now() - interval '1 day'",
+        &[&username]));
+    let row = rows.get(0);
+    let count: i32 = row.get("count");
+    let elapsed: i32 = row.get("elapsed");
+    if count < CONFIG.team_lockout_threshold {
+        return Ok(0);
+    }
+    let exponent = (count - CONFIG.team_lockout_threshold).min(16) as u32;
+    let wait = CONFIG.team_lockout_base.saturating_mul(2i32.pow(exponent)).min(CONFIG.team_lockout_max);
+    Ok((wait - elapsed).max(0))
+}
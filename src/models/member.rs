@@ -0,0 +1,90 @@
+use postgres::error::Error;
+use postgres::rows::Row;
+use chrono::NaiveDateTime;
+use db;
+use models;
+use helper;
+
+#[derive(Serialize, Debug, Default)]
+pub struct Member {
+    pub user: models::user::User,
+    pub display_name: String,
+    pub bio: String,
+    pub title: String,
+    pub team: String,
+    pub links: Vec<String>,
+    pub post_count: i32,
+    pub nippo_count: i32,
+    pub gist_count: i32,
+    pub last_activity: Option<NaiveDateTime>,
+    pub formated_last_activity: String,
+}
+
+const SELECT_MEMBERS: &str = "
+    SELECT u.id, u.username, u.icon_url, u.deactivated, u.display_name, u.bio, u.title, u.team, u.links,
+    (select count(*)::int from posts as p where p.user_id = u.id and p.kind = 'post' and p.status = 'publish' and p.visibility = 'public') as post_count,
+    (select count(*)::int from posts as p where p.user_id = u.id and p.kind = 'nippo' and p.status = 'publish' and p.visibility = 'public') as nippo_count,
+    (select count(*)::int from gists as g where g.user_id = u.id and g.visibility = 'public') as gist_count,
+    greatest(
+        (select max(p.updated) from posts as p where p.user_id = u.id and p.status = 'publish' and p.visibility = 'public'),
+        (select max(c.created) from post_comments as c join posts as p on p.id = c.post_id
+         where c.user_id = u.id and p.status = 'publish' and p.visibility = 'public'),
+        (select max(g.updated) from gists as g where g.user_id = u.id and g.visibility = 'public'),
+        (select max(t.created) from tweets as t where t.user_id = u.id)
+    ) as last_activity
+    from users as u";
+
+// Matches username, display name, title or team.
+// A plain substring match, so `%` and `_` in the keyword are not wildcards.
+const SEARCH: &str = "
+    ($1 = '' or strpos(lower(u.username), lower($1)) > 0 or strpos(lower(u.display_name), lower($1)) > 0
+     or strpos(lower(u.title), lower($1)) > 0 or strpos(lower(u.team), lower($1)) > 0)";
+
+fn from_row(row: &Row) -> Member {
+    let links: String = row.get("links");
+    let last_activity: Option<NaiveDateTime> = row.get("last_activity");
+    Member {
+        user: models::user::User {
+            id: row.get("id"),
+            username: row.get("username"),
+            icon_url: row.get("icon_url"),
+            username_hash: helper::username_hash(row.get("username")),
+            deactivated: row.get("deactivated"),
+        },
+        display_name: row.get("display_name"),
+        bio: row.get("bio"),
+        title: row.get("title"),
+        team: row.get("team"),
+        links: helper::split_links(&links),
+        post_count: row.get("post_count"),
+        nippo_count: row.get("nippo_count"),
+        gist_count: row.get("gist_count"),
+        last_activity: last_activity,
+        formated_last_activity: last_activity.map(helper::jst_time_formatter).unwrap_or("".to_string()),
+    }
+}
+
+pub fn get(conn: &db::PostgresConnection, user_id: &i32) -> Result<Member, Error> {
+    let rows = try!(conn.query(&format!("{} where u.id = $1", SELECT_MEMBERS), &[&user_id]));
+    Ok(rows.iter().next().map(|row| from_row(&row)).unwrap_or(Member{..Default::default()}))
+}
+
+pub fn list(conn: &db::PostgresConnection, keyword: &String, offset: &i32, limit: &i32) -> Result<Vec<Member>, Error> {
+    let rows = try!(conn.query(&format!(
+        "{} where u.deactivated = false and {} order by last_activity desc nulls last, u.id offset $2::int limit $3::int",
+        SELECT_MEMBERS, SEARCH), &[&keyword, &offset, &limit]));
+    Ok(rows.iter().map(|row| from_row(&row)).collect())
+}
+
+pub fn count(conn: &db::PostgresConnection, keyword: &String) -> Result<i32, Error> {
+    let rows = try!(conn.query(&format!(
+        "SELECT count(*)::int as count from users as u where u.deactivated = false and {}", SEARCH), &[&keyword]));
+    Ok(rows.get(0).get("count"))
+}
+
+pub fn update_profile(conn: &db::PostgresConnection, user_id: &i32, display_name: &String, bio: &String, title: &String, team: &String, links: &String) -> Result<(), Error> {
+    conn.execute(
+        "UPDATE users set display_name = $2, bio = $3, title = $4, team = $5, links = $6, updated = now() WHERE id = $1",
+        &[&user_id, &display_name, &bio, &title, &team, &links]
+    ).map(|_| ())
+}
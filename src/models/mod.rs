@@ -4,3 +4,16 @@ pub mod tag;
 pub mod gist;
 pub mod notification;
 pub mod tweet;
+pub mod upload;
+pub mod totp;
+pub mod setting;
+pub mod session;
+pub mod invite;
+pub mod password_reset;
+pub mod signup_confirmation;
+pub mod login_failure;
+pub mod export;
+pub mod member;
+pub mod follow;
+pub mod audit;
+pub mod api_token;
@@ -12,6 +12,8 @@ pub struct Notification {
     pub to_user: i32,
     pub body: String,
     pub read: bool,
+    pub kind: String,
+    pub new_post: bool,
     pub created: NaiveDateTime,
     pub formated_created: String,
     pub user: models::user::User,
@@ -28,12 +30,21 @@ pub fn create(conn: &db::PostgresConnection, path: &String, from_user: &i32, to_
     Ok(notification_id)
 }
 
+/// Tells everyone following `from_user` about a newly published post.
+pub fn create_for_followers(conn: &db::PostgresConnection, path: &String, from_user: &i32, title: &String) -> Result<(), Error> {
+    conn.execute("
+        INSERT INTO notifications (path, from_user, to_user, body, kind)
+        SELECT $1, $2, f.user_id, $3, 'post' from follows as f where f.followee_id = $2",
+        &[&path, &from_user, &title]
+    ).map(|_| ())
+}
+
 pub fn list(conn: &db::PostgresConnection, to_user: &i32, offset: &i32, limit: &i32) -> Result<Vec<Notification>, Error> {
     conn.execute(
         "UPDATE notifications set read = true WHERE to_user = $1", &[&to_user]
     ).unwrap();
     let mut notifications: Vec<Notification> = Vec::new();
-    for row in &conn.query("select n.*, u.id, u.username, u.icon_url from notifications as n join users as u on n.from_user = u.id 
+    for row in &conn.query("select n.*, u.id, u.username, u.icon_url, u.deactivated from notifications as n join users as u on n.from_user = u.id 
         where n.to_user = $1::int 
         order by n.id desc 
         offset $2::int limit $3::int", &[&to_user, &offset, &limit]).unwrap() {
@@ -44,6 +55,8 @@ pub fn list(conn: &db::PostgresConnection, to_user: &i32, offset: &i32, limit: &
             to_user: row.get("to_user"),
             body: row.get("body"),
             read: row.get("read"),
+            kind: row.get("kind"),
+            new_post: false,
             created: row.get("created"),
             formated_created: "".to_string(),
             user: models::user::User{
@@ -51,9 +64,11 @@ pub fn list(conn: &db::PostgresConnection, to_user: &i32, offset: &i32, limit: &
                 username: row.get("username"),
                 icon_url: row.get("icon_url"),
                 username_hash: helper::username_hash(row.get("username")),
+                deactivated: row.get("deactivated"),
             }
         };
         notification.formated_created = helper::jst_time_formatter(notification.created);
+        notification.new_post = notification.kind == "post";
         notifications.push(notification);
     }
     Ok(notifications)
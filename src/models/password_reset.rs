@@ -0,0 +1,48 @@
+use postgres::error::Error;
+use db;
+use helper;
+
+pub fn create(conn: &db::PostgresConnection, user_id: &i32, token: &String, ip: &String) -> Result<(), Error> {
+    conn.execute("
+        INSERT INTO password_resets (user_id, token_hash, ip, expires)
+        VALUES ($1, $2, $3, now() + interval '1 hour')",
+        &[&user_id, &helper::token_hash(token), &ip]
+    ).map(|_| ())
+}
+
+/// Returns the user an unused, unexpired token was issued for.
+pub fn get_user_id(conn: &db::PostgresConnection, token: &String) -> Result<Option<i32>, Error> {
+    let rows = try!(conn.query(
+        "SELECT user_id from password_resets where token_hash = $1 and used = false and expires > now()",
+        &[&helper::token_hash(token)]));
+    Ok(rows.iter().next().map(|row| row.get("user_id")))
+}
+
+/// Marks the token used and returns its user, so a token works only once.
+pub fn consume(conn: &db::PostgresConnection, token: &String) -> Result<Option<i32>, Error> {
+    let rows = try!(conn.query(
+        "UPDATE password_resets set used = true, updated = now()
+        WHERE token_hash = $1 and used = false and expires > now() returning user_id",
+        &[&helper::token_hash(token)]));
+    let user_id: Option<i32> = rows.iter().next().map(|row| row.get("user_id"));
+    if let Some(id) = user_id {
+        try!(conn.execute(
+            "UPDATE password_resets set used = true, updated = now() WHERE user_id = $1 and used = false", &[&id]
+        ));
+    }
+    Ok(user_id)
+}
+
+pub fn recent_count_by_user_id(conn: &db::PostgresConnection, user_id: &i32) -> Result<i32, Error> {
+    let rows = try!(conn.query(
+        "SELECT count(*)::int as count from password_resets where user_id = $1 and created > now() - interval '1 hour'",
+        &[&user_id]));
+    Ok(rows.get(0).get("count"))
+}
+
+pub fn recent_count_by_ip(conn: &db::PostgresConnection, ip: &String) -> Result<i32, Error> {
+    let rows = try!(conn.query(
+        "SELECT count(*)::int as count from password_resets where ip = $1 and created > now() - interval '1 hour'",
+        &[&ip]));
+    Ok(rows.get(0).get("count"))
+}
@@ -1,8 +1,11 @@
 use postgres::error::Error;
+use postgres::types::ToSql;
+use postgres::transaction::Transaction;
 use db;
 use models;
 use helper;
 use chrono::{NaiveDateTime};
+use crypto::util::fixed_time_eq;
 
 #[derive(Serialize, Debug)]
 pub struct Post {
@@ -17,14 +20,72 @@ pub struct Post {
     pub tags: Vec<models::tag::Tag>,
     pub shared: bool,
     pub status: String,
+    pub visibility: String,
+    // Opens an unlisted post for non-authors; only loaded by `get_by_id`.
+    #[serde(skip_serializing)]
+    pub share_token: String,
 }
 
-pub fn create(conn: &db::PostgresConnection, kind: &str, user_id: &i32, action: &String, title: &String, body: &String, tags: &String) -> Result<(i32), Error> {
+/// Who can find a post or gist: `public` ones are listed for the whole team,
+/// `unlisted` ones only open from their URL, which carries the item's share token,
+/// and `private` ones only for the author.
+pub const VISIBILITIES: [&str; 3] = ["public", "unlisted", "private"];
+
+pub fn is_visibility(visibility: &str) -> bool {
+    VISIBILITIES.contains(&visibility)
+}
+
+#[derive(Serialize, Debug)]
+pub struct VisibilityOption {
+    pub name: &'static str,
+    pub label: &'static str,
+    pub selected: bool,
+}
+
+/// The choices for the visibility select on post and gist forms.
+pub fn visibility_options(current: &str) -> Vec<VisibilityOption> {
+    VISIBILITIES.iter().map(|&name| VisibilityOption {
+        name: name,
+        label: match name {
+            "public" => "Team",
+            "unlisted" => "Unlisted (link only)",
+            _ => "Private (only me)",
+        },
+        selected: name == current,
+    }).collect()
+}
+
+/// The visibility rule shared by posts and gists; `token` is the one the request came with.
+pub fn is_visible(visibility: &str, owner_id: &i32, share_token: &str, user_id: &i32, token: &str) -> bool {
+    match visibility {
+        "public" => true,
+        _ if owner_id == user_id => true,
+        "unlisted" => share_token != "" && token.len() == share_token.len() && fixed_time_eq(token.as_bytes(), share_token.as_bytes()),
+        _ => false,
+    }
+}
+
+pub fn link_token<'a>(visibility: &str, share_token: &'a str) -> &'a str {
+    if visibility == "unlisted" { share_token } else { "" }
+}
+
+impl Post {
+    pub fn visible_to(&self, user_id: &i32, token: &str) -> bool {
+        is_visible(&self.visibility, &self.user_id, &self.share_token, user_id, token)
+    }
+
+    /// The token links to the post must carry; empty unless it is unlisted.
+    pub fn link_token(&self) -> &str {
+        link_token(&self.visibility, &self.share_token)
+    }
+}
+
+pub fn create(conn: &db::PostgresConnection, kind: &str, user_id: &i32, action: &String, title: &String, body: &String, tags: &String, visibility: &String) -> Result<(i32), Error> {
     let mut post_id = 0;
     for row in &conn.query("
-        INSERT INTO posts (kind, user_id, title, body, status)
-        VALUES ($1, $2, $3, $4, $5) returning id;",
-        &[&kind, &user_id, &title, &body, &action]).unwrap() {
+        INSERT INTO posts (kind, user_id, title, body, status, visibility, share_token)
+        VALUES ($1, $2, $3, $4, $5, $6, $7) returning id;",
+        &[&kind, &user_id, &title, &body, &action, &visibility, &helper::random_token()]).unwrap() {
         post_id = row.get("id");
     }
     for mut tag in tags.split(",") {
@@ -43,14 +104,15 @@ pub fn create(conn: &db::PostgresConnection, kind: &str, user_id: &i32, action:
     Ok(post_id)
 }
 
-pub fn list(conn: &db::PostgresConnection, kind: &str, offset: &i32, limit: &i32) -> Result<Vec<Post>, Error> {
+/// Published posts of `kind` that are public, plus the viewer's own unlisted and private ones.
+pub fn list(conn: &db::PostgresConnection, kind: &str, viewer_id: &i32, offset: &i32, limit: &i32) -> Result<Vec<Post>, Error> {
     let mut posts: Vec<Post> = Vec::new();
     for row in &conn.query("
-        SELECT p.id, p.kind, p.user_id, p.title, p.body, p.created, p.shared, p.status, u.username, u.icon_url
+        SELECT p.id, p.kind, p.user_id, p.title, p.body, p.created, p.shared, p.status, p.visibility, u.username, u.icon_url, u.deactivated
         from posts as p
         join users as u on u.id = p.user_id
-        where p.status = 'publish' and p.kind = $1
-        order by p.id desc offset $2::int limit $3::int", &[&kind, &offset, &limit]).unwrap() {
+        where p.status = 'publish' and p.kind = $1 and (p.visibility = 'public' or p.user_id = $4)
+        order by p.id desc offset $2::int limit $3::int", &[&kind, &offset, &limit, &viewer_id]).unwrap() {
         match models::tag::get_tags_by_post_id(&conn, &row.get("id")) {
             Ok(tags) => {
                 let mut post = Post {
@@ -63,11 +125,14 @@ pub fn list(conn: &db::PostgresConnection, kind: &str, offset: &i32, limit: &i32
                     formated_created: "".to_string(),
                     shared: row.get("shared"),
                     status: row.get("status"),
+                    visibility: row.get("visibility"),
+                    share_token: "".to_string(),
                     user: models::user::User{
                         id: row.get("user_id"),
                         username: row.get("username"),
                         icon_url: row.get("icon_url"),
                         username_hash: helper::username_hash(row.get("username")),
+                        deactivated: row.get("deactivated"),
                     },
                     tags: tags,
                 };
@@ -82,17 +147,18 @@ pub fn list(conn: &db::PostgresConnection, kind: &str, offset: &i32, limit: &i32
     Ok(posts)
 }
 
-pub fn count(conn: &db::PostgresConnection, kind: &str) -> Result<i32, Error> {
-    let rows = &conn.query("SELECT count(*)::int as count from posts where status = 'publish' and kind = $1", &[&kind]).unwrap();
+pub fn count(conn: &db::PostgresConnection, kind: &str, viewer_id: &i32) -> Result<i32, Error> {
+    let rows = &conn.query("SELECT count(*)::int as count from posts where status = 'publish' and kind = $1 and (visibility = 'public' or user_id = $2)", &[&kind, &viewer_id]).unwrap();
     let row = rows.get(0);
     let count = row.get("count");
     Ok(count)
 }
 
 use super::tag;
-pub fn update(conn: &db::PostgresConnection, id: &i32, title: &String, body: &String, tags: &String, action: &String) -> Result<(), Error> {
+pub fn update(conn: &db::PostgresConnection, id: &i32, title: &String, body: &String, tags: &String, action: &String, visibility: &String) -> Result<(), Error> {
     conn.execute(
-        "UPDATE posts set title = $1, body = $2, status = $3 WHERE id = $4", &[&title, &body, &action, &id]
+        "UPDATE posts set title = $1, body = $2, status = $3, visibility = $4, share_token = coalesce(share_token, $6) WHERE id = $5",
+        &[&title, &body, &action, &visibility, &id, &helper::random_token()]
     ).unwrap();
     let mut old_tag_ids: Vec<i32> = models::tag::get_tags_by_post_id(&conn, &id)
         .or::<Vec<tag::Tag>>(Ok(Vec::<tag::Tag>::new()))
@@ -131,7 +197,7 @@ pub fn update(conn: &db::PostgresConnection, id: &i32, title: &String, body: &St
 }
 
 pub fn get_by_id(conn: &db::PostgresConnection, id: &i32) -> Result<Post, Error> {
-    let rows = &conn.query("SELECT p.id, p.kind, p.user_id, p.title, p.body, p.created, p.shared, p.status, u.username, u.icon_url from posts as p join users as u on u.id=p.user_id where p.id = $1", &[&id]).unwrap();
+    let rows = &conn.query("SELECT p.id, p.kind, p.user_id, p.title, p.body, p.created, p.shared, p.status, p.visibility, coalesce(p.share_token, '') as share_token, u.username, u.icon_url, u.deactivated from posts as p join users as u on u.id=p.user_id where p.id = $1", &[&id]).unwrap();
     let row = rows.get(0);
     match models::tag::get_tags_by_post_id(&conn, &row.get("id")) {
         Ok(tags) => {
@@ -145,11 +211,14 @@ pub fn get_by_id(conn: &db::PostgresConnection, id: &i32) -> Result<Post, Error>
                 formated_created: "".to_string(),
                 shared: row.get("shared"),
                 status: row.get("status"),
+                visibility: row.get("visibility"),
+                share_token: row.get("share_token"),
                 user: models::user::User{
                     id: row.get("user_id"),
                     username: row.get("username"),
                     icon_url: row.get("icon_url"),
                     username_hash: helper::username_hash(row.get("username")),
+                    deactivated: row.get("deactivated"),
                 },
                 tags: tags,
             };
@@ -170,6 +239,15 @@ pub fn delete_by_id(conn: &db::PostgresConnection, id: &i32) -> Result<(), Error
     ).map(|_| ())
 }
 
+/// Hands the published, non-private posts of `from_user_id` over to `to_user_id`;
+/// returns how many moved.
+pub fn transfer_owner(trans: &Transaction, from_user_id: &i32, to_user_id: &i32) -> Result<u64, Error> {
+    trans.execute(
+        "UPDATE posts set user_id = $2 WHERE user_id = $1 and status = 'publish' and visibility <> 'private'",
+        &[&from_user_id, &to_user_id]
+    )
+}
+
 #[derive(Serialize, Debug, Default)]
 pub struct Comment {
     pub id: i32,
@@ -192,7 +270,7 @@ pub fn add_comment(conn: &db::PostgresConnection, user_id: &i32, post_id: &i32,
 
 pub fn get_comments_by_post_id(conn: &db::PostgresConnection, id: &i32) -> Result<Vec<Comment>, Error> {
     let mut comments: Vec<Comment> = Vec::new();
-    for row in &conn.query("SELECT c.id, c.user_id, c.post_id, c.body, u.username, u.icon_url from post_comments as c join users as u on u.id = c.user_id where c.post_id = $1 order by id asc", &[&id]).unwrap() {
+    for row in &conn.query("SELECT c.id, c.user_id, c.post_id, c.body, u.username, u.icon_url, u.deactivated from post_comments as c join users as u on u.id = c.user_id where c.post_id = $1 order by id asc", &[&id]).unwrap() {
         comments.push(Comment {
             id: row.get("id"),
             user_id: row.get("user_id"),
@@ -203,6 +281,7 @@ pub fn get_comments_by_post_id(conn: &db::PostgresConnection, id: &i32) -> Resul
                 username: row.get("username"),
                 icon_url: row.get("icon_url"),
                 username_hash: helper::username_hash(row.get("username")),
+                deactivated: row.get("deactivated"),
             }
         });
     }
@@ -225,15 +304,22 @@ pub struct Feed {
     tags: Vec<models::tag::Tag>,
 }
 
-pub fn get_feeds(conn: &db::PostgresConnection, offset: &i32, limit: &i32) -> Result<Vec<Feed>, Error> {
+// Posts and gists by followed people or carrying a followed tag; $3 is the follower.
+const FOLLOWED_POST: &str = "(p.user_id in (select followee_id from follows where user_id = $3)
+    or exists (select 1 from taggings as t join tag_follows as tf on tf.tag_id = t.tag_id where t.post_id = p.id and tf.user_id = $3))";
+const FOLLOWED_COMMENT: &str = "(c.user_id in (select followee_id from follows where user_id = $3))";
+const FOLLOWED_GIST: &str = "(g.user_id in (select followee_id from follows where user_id = $3)
+    or exists (select 1 from gist_taggings as t join tag_follows as tf on tf.tag_id = t.tag_id where t.gist_id = g.id and tf.user_id = $3))";
+
+fn select_feeds(conn: &db::PostgresConnection, post_filter: &str, comment_filter: &str, gist_filter: &str, params: &[&ToSql]) -> Result<Vec<Feed>, Error> {
     let mut feeds: Vec<Feed> = Vec::new();
-    for row in &conn.query("
-        (select '' as comment, '' as gist, 'post' as post, p.id, p.kind, p.user_id, p.title, '' as body, u.username, u.icon_url, p.created from posts as p join users as u on u.id=p.user_id where p.status = 'publish')
+    for row in &try!(conn.query(&format!("
+        (select '' as comment, '' as gist, 'post' as post, p.id, p.kind, p.user_id, p.title, '' as body, u.username, u.icon_url, u.deactivated, p.created from posts as p join users as u on u.id=p.user_id where p.status = 'publish' and p.visibility = 'public' and {})
         union
-        (select 'comment' as comment, '' as gist, '' as post, c.post_id, p.kind, c.user_id, p.title as title, c.body, u.username, u.icon_url, c.created from post_comments as c join users as u on u.id=c.user_id join posts as p on c.post_id=p.id)
+        (select 'comment' as comment, '' as gist, '' as post, c.post_id, p.kind, c.user_id, p.title as title, c.body, u.username, u.icon_url, u.deactivated, c.created from post_comments as c join users as u on u.id=c.user_id join posts as p on c.post_id=p.id where p.visibility = 'public' and {})
         union
-        (select  '' as comment, 'gist' as gist, '' as post, g.id, '' as kind, g.user_id, g.description as title, '' as body, u.username, u.icon_url, g.created from gists as g join users as u on u.id=g.user_id)
-        order by created desc offset $1::int limit $2::int", &[&offset, &limit]).unwrap() {
+        (select  '' as comment, 'gist' as gist, '' as post, g.id, '' as kind, g.user_id, g.description as title, '' as body, u.username, u.icon_url, u.deactivated, g.created from gists as g join users as u on u.id=g.user_id where g.visibility = 'public' and {})
+        order by created desc offset $1::int limit $2::int", post_filter, comment_filter, gist_filter), params)) {
         match models::tag::get_tags_by_post_id(&conn, &row.get("id")) {
             Ok(tags) => {
                 let mut body: String = row.get("body");
@@ -254,6 +340,7 @@ pub fn get_feeds(conn: &db::PostgresConnection, offset: &i32, limit: &i32) -> Re
                         username: row.get("username"),
                         icon_url: row.get("icon_url"),
                         username_hash: helper::username_hash(row.get("username")),
+                        deactivated: row.get("deactivated"),
                     },
                     tags: tags,
                 };
@@ -268,29 +355,51 @@ pub fn get_feeds(conn: &db::PostgresConnection, offset: &i32, limit: &i32) -> Re
     Ok(feeds)
 }
 
+pub fn get_feeds(conn: &db::PostgresConnection, offset: &i32, limit: &i32) -> Result<Vec<Feed>, Error> {
+    select_feeds(conn, "true", "true", "true", &[&offset, &limit])
+}
+
+/// The home feed narrowed to followed people and tags.
+pub fn get_following_feeds(conn: &db::PostgresConnection, user_id: &i32, offset: &i32, limit: &i32) -> Result<Vec<Feed>, Error> {
+    select_feeds(conn, FOLLOWED_POST, FOLLOWED_COMMENT, FOLLOWED_GIST, &[&offset, &limit, &user_id])
+}
+
 pub fn get_feed_count(conn: &db::PostgresConnection) -> Result<i32, Error> {
     let rows = &conn.query("
     select sum(count)::int as count from
-    (select count(*) from posts where status = 'publish'
+    (select count(*) from posts where status = 'publish' and visibility = 'public'
     union all
-    select count(*) as b from post_comments) as t;
+    select count(*) as b from post_comments as c join posts as p on p.id = c.post_id where p.visibility = 'public') as t;
     ", &[]).unwrap();
     let row = rows.get(0);
     let count = row.get("count");
     Ok(count)
 }
 
-pub fn search(conn: &db::PostgresConnection, keyword: &String, kind: &String, offset: &i32, limit: &i32) -> Result<Vec<Post>, Error> {
+pub fn get_following_feed_count(conn: &db::PostgresConnection, user_id: &i32) -> Result<i32, Error> {
+    let rows = try!(conn.query(&format!("
+    select sum(count)::int as count from
+    (select count(*) from posts as p where p.status = 'publish' and p.visibility = 'public' and {}
+    union all
+    select count(*) from post_comments as c join posts as p on p.id = c.post_id where p.visibility = 'public' and {}
+    union all
+    select count(*) from gists as g where g.visibility = 'public' and {}) as t;
+    ", FOLLOWED_POST.replace("$3", "$1"), FOLLOWED_COMMENT.replace("$3", "$1"), FOLLOWED_GIST.replace("$3", "$1")), &[&user_id]));
+    Ok(rows.get(0).get("count"))
+}
+
+pub fn search(conn: &db::PostgresConnection, keyword: &String, kind: &String, viewer_id: &i32, offset: &i32, limit: &i32) -> Result<Vec<Post>, Error> {
     let mut kind_param = String::from("");
     if kind != "all" {
         kind_param = format!("{}", kind);
     }
     let mut posts: Vec<Post> = Vec::new();
     for row in &conn.query("
-        SELECT p.id, p.kind, p.user_id, p.title, p.body, p.created, p.shared, p.status, u.username, u.icon_url from posts as p
+        SELECT p.id, p.kind, p.user_id, p.title, p.body, p.created, p.shared, p.status, p.visibility, u.username, u.icon_url, u.deactivated from posts as p
         join users as u on u.id = p.user_id
         where p.status = 'publish' and (p.title ilike '%' || $1 || '%' or p.body ilike '%' || $1 || '%') and p.kind like '%' || $2 || '%'
-        order by p.id desc offset $3::int limit $4::int", &[&keyword, &kind_param, &offset, &limit]).unwrap() {
+        and (p.visibility = 'public' or p.user_id = $5)
+        order by p.id desc offset $3::int limit $4::int", &[&keyword, &kind_param, &offset, &limit, &viewer_id]).unwrap() {
         match models::tag::get_tags_by_post_id(&conn, &row.get("id")) {
             Ok(tags) => {
                 let mut post = Post {
@@ -303,11 +412,14 @@ pub fn search(conn: &db::PostgresConnection, keyword: &String, kind: &String, of
                     formated_created: "".to_string(),
                     shared: row.get("shared"),
                     status: row.get("status"),
+                    visibility: row.get("visibility"),
+                    share_token: "".to_string(),
                     user: models::user::User{
                         id: row.get("user_id"),
                         username: row.get("username"),
                         icon_url: row.get("icon_url"),
                         username_hash: helper::username_hash(row.get("username")),
+                        deactivated: row.get("deactivated"),
                     },
                     tags: tags,
                 };
@@ -322,13 +434,14 @@ pub fn search(conn: &db::PostgresConnection, keyword: &String, kind: &String, of
     Ok(posts)
 }
 
-pub fn search_count(conn: &db::PostgresConnection, keyword: &String, kind: &String) -> Result<i32, Error> {
+pub fn search_count(conn: &db::PostgresConnection, keyword: &String, kind: &String, viewer_id: &i32) -> Result<i32, Error> {
     let mut kind_param = String::from("");
     if kind != "all" {
         kind_param = format!("{}", kind);
     }
     let rows = &conn.query("
-        SELECT count(*)::int as count from posts where status = 'publish' and (title ilike '%' || $1 || '%' or body ilike '%' || $1 || '%') and kind like '%' || $2 || '%'", &[&keyword, &kind_param]).unwrap();
+        SELECT count(*)::int as count from posts where status = 'publish' and (title ilike '%' || $1 || '%' or body ilike '%' || $1 || '%') and kind like '%' || $2 || '%'
+        and (visibility = 'public' or user_id = $3)", &[&keyword, &kind_param, &viewer_id]).unwrap();
     let row = rows.get(0);
     let count = row.get("count");
     Ok(count)
@@ -345,11 +458,11 @@ pub fn stock_post(conn: &db::PostgresConnection, user_id: &i32, post_id: &i32) -
 pub fn stocked_list(conn: &db::PostgresConnection, user_id: &i32, offset: &i32, limit: &i32) -> Result<Vec<Post>, Error> {
     let mut posts: Vec<Post> = Vec::new();
     for row in &conn.query("
-        SELECT p.id, p.kind, p.user_id, p.title, p.body, p.created, p.shared, p.status, u.username, u.icon_url
+        SELECT p.id, p.kind, p.user_id, p.title, p.body, p.created, p.shared, p.status, p.visibility, u.username, u.icon_url, u.deactivated
         from posts as p
         join stocks as s on s.post_id = p.id
         join users as u on u.id = p.user_id
-        where s.user_id = $1
+        where s.user_id = $1 and (p.visibility = 'public' or p.user_id = $1)
         order by s.id desc offset $2::int limit $3::int", &[&user_id, &offset, &limit]).unwrap() {
         match models::tag::get_tags_by_post_id(&conn, &row.get("id")) {
             Ok(tags) => {
@@ -363,11 +476,14 @@ pub fn stocked_list(conn: &db::PostgresConnection, user_id: &i32, offset: &i32,
                     formated_created: "".to_string(),
                     shared: row.get("shared"),
                     status: row.get("status"),
+                    visibility: row.get("visibility"),
+                    share_token: "".to_string(),
                     user: models::user::User{
                         id: row.get("user_id"),
                         username: row.get("username"),
                         icon_url: row.get("icon_url"),
                         username_hash: helper::username_hash(row.get("username")),
+                        deactivated: row.get("deactivated"),
                     },
                     tags: tags,
                 };
@@ -383,7 +499,8 @@ pub fn stocked_list(conn: &db::PostgresConnection, user_id: &i32, offset: &i32,
 }
 
 pub fn stocked_count(conn: &db::PostgresConnection, user_id: &i32) -> Result<i32, Error> {
-    let rows = &conn.query("SELECT count(*)::int as count from stocks where user_id = $1", &[&user_id]).unwrap();
+    let rows = &conn.query("SELECT count(*)::int as count from stocks as s join posts as p on p.id = s.post_id
+        where s.user_id = $1 and (p.visibility = 'public' or p.user_id = $1)", &[&user_id]).unwrap();
     let row = rows.get(0);
     let count = row.get("count");
     Ok(count)
@@ -407,7 +524,7 @@ pub fn stock_remove(conn: &db::PostgresConnection, user_id: &i32, post_id: &i32)
 pub fn draft_list(conn: &db::PostgresConnection, user_id: &i32) -> Result<Vec<Post>, Error> {
     let mut posts: Vec<Post> = Vec::new();
     for row in &conn.query("
-        SELECT p.id, p.kind, p.user_id, p.title, p.body, p.created, p.shared, p.status, u.username, u.icon_url
+        SELECT p.id, p.kind, p.user_id, p.title, p.body, p.created, p.shared, p.status, p.visibility, u.username, u.icon_url, u.deactivated
         from posts as p
         join users as u on u.id = p.user_id
         where p.status = 'draft' and p.user_id = $1
@@ -424,11 +541,14 @@ pub fn draft_list(conn: &db::PostgresConnection, user_id: &i32) -> Result<Vec<Po
                     formated_created: "".to_string(),
                     shared: row.get("shared"),
                     status: row.get("status"),
+                    visibility: row.get("visibility"),
+                    share_token: "".to_string(),
                     user: models::user::User{
                         id: row.get("user_id"),
                         username: row.get("username"),
                         icon_url: row.get("icon_url"),
                         username_hash: helper::username_hash(row.get("username")),
+                        deactivated: row.get("deactivated"),
                     },
                     tags: tags,
                 });
@@ -442,7 +562,7 @@ pub fn draft_list(conn: &db::PostgresConnection, user_id: &i32) -> Result<Vec<Po
 }
 
 pub fn get_comment_by_id(conn: &db::PostgresConnection, id: &i32) -> Result<Comment, Error> {
-    let rows = &conn.query("SELECT p.*, u.username, u.icon_url from post_comments as p join users as u on u.id = p.user_id where p.id = $1", &[&id]).unwrap();
+    let rows = &conn.query("SELECT p.*, u.username, u.icon_url, u.deactivated from post_comments as p join users as u on u.id = p.user_id where p.id = $1", &[&id]).unwrap();
     let row = rows.get(0);
     let comment = Comment {
         id: row.get("id"),
@@ -454,6 +574,7 @@ pub fn get_comment_by_id(conn: &db::PostgresConnection, id: &i32) -> Result<Comm
             username: row.get("username"),
             icon_url: row.get("icon_url"),
             username_hash: helper::username_hash(row.get("username")),
+            deactivated: row.get("deactivated"),
         },
     };
     Ok(comment)
@@ -473,14 +594,14 @@ pub fn delete_comment_by_id(conn: &db::PostgresConnection, id: &i32) -> Result<(
     Ok(())
 }
 
-pub fn user_posts(conn: &db::PostgresConnection, username: &str, offset: &i32, limit: &i32, kind: &str) -> Result<Vec<Post>, Error> {
+pub fn user_posts(conn: &db::PostgresConnection, username: &str, viewer_id: &i32, offset: &i32, limit: &i32, kind: &str) -> Result<Vec<Post>, Error> {
     let mut posts: Vec<Post> = Vec::new();
     for row in &conn.query("
-        SELECT p.id, p.kind, p.user_id, p.title, p.body, p.created, p.shared, p.status, u.username, u.icon_url
+        SELECT p.id, p.kind, p.user_id, p.title, p.body, p.created, p.shared, p.status, p.visibility, u.username, u.icon_url, u.deactivated
         from posts as p
         join users as u on u.id = p.user_id
-        where p.status = 'publish' and u.username = $1 and p.kind = $2
-        order by p.id desc offset $3::int limit $4::int", &[&username, &kind, &offset, &limit]).unwrap() {
+        where p.status = 'publish' and u.username = $1 and p.kind = $2 and (p.visibility = 'public' or p.user_id = $5)
+        order by p.id desc offset $3::int limit $4::int", &[&username, &kind, &offset, &limit, &viewer_id]).unwrap() {
         match models::tag::get_tags_by_post_id(&conn, &row.get("id")) {
             Ok(tags) => {
                 let mut post = Post {
@@ -493,11 +614,14 @@ pub fn user_posts(conn: &db::PostgresConnection, username: &str, offset: &i32, l
                     formated_created: "".to_string(),
                     shared: row.get("shared"),
                     status: row.get("status"),
+                    visibility: row.get("visibility"),
+                    share_token: "".to_string(),
                     user: models::user::User{
                         id: row.get("user_id"),
                         username: row.get("username"),
                         icon_url: row.get("icon_url"),
                         username_hash: helper::username_hash(row.get("username")),
+                        deactivated: row.get("deactivated"),
                     },
                     tags: tags,
                 };
@@ -512,9 +636,9 @@ pub fn user_posts(conn: &db::PostgresConnection, username: &str, offset: &i32, l
     Ok(posts)
 }
 
-pub fn user_posts_count(conn: &db::PostgresConnection, username: &str, kind: &str) -> Result<i32, Error> {
+pub fn user_posts_count(conn: &db::PostgresConnection, username: &str, viewer_id: &i32, kind: &str) -> Result<i32, Error> {
     let rows = &conn.query("SELECT count(*)::int as count from posts as p join users as u on u.id=
-    p.user_id where p.status = 'publish' and u.username = $1 and p.kind = $2", &[&username, &kind]).unwrap();
+    p.user_id where p.status = 'publish' and u.username = $1 and p.kind = $2 and (p.visibility = 'public' or p.user_id = $3)", &[&username, &kind, &viewer_id]).unwrap();
     let row = rows.get(0);
     let count = row.get("count");
     Ok(count)
@@ -544,11 +668,11 @@ pub fn unpin_post(conn: &db::PostgresConnection, post_id: &i32) -> Result<(), Er
 pub fn pinned_list(conn: &db::PostgresConnection, offset: &i32, limit: &i32) -> Result<Vec<Post>, Error> {
     let mut posts: Vec<Post> = Vec::new();
     for row in &conn.query("
-        SELECT p.id, p.kind, p.user_id, p.title, p.body, p.created, p.shared, p.status, u.username, u.icon_url
+        SELECT p.id, p.kind, p.user_id, p.title, p.body, p.created, p.shared, p.status, p.visibility, u.username, u.icon_url, u.deactivated
         from posts as p
         join pinneds as s on s.post_id = p.id
         join users as u on u.id = p.user_id
-        where s.deleted = false
+        where s.deleted = false and p.visibility = 'public'
         order by s.id desc offset $1::int limit $2::int", &[&offset, &limit]).unwrap() {
         match models::tag::get_tags_by_post_id(&conn, &row.get("id")) {
             Ok(tags) => {
@@ -562,11 +686,14 @@ pub fn pinned_list(conn: &db::PostgresConnection, offset: &i32, limit: &i32) ->
                     formated_created: "".to_string(),
                     shared: row.get("shared"),
                     status: row.get("status"),
+                    visibility: row.get("visibility"),
+                    share_token: "".to_string(),
                     user: models::user::User{
                         id: row.get("user_id"),
                         username: row.get("username"),
                         icon_url: row.get("icon_url"),
                         username_hash: helper::username_hash(row.get("username")),
+                        deactivated: row.get("deactivated"),
                     },
                     tags: tags,
                 };
@@ -590,8 +717,38 @@ pub fn is_pinned(conn: &db::PostgresConnection, post_id: &i32) -> Result<bool, E
 }
 
 pub fn pinned_count(conn: &db::PostgresConnection) -> Result<i32, Error> {
-    let rows = &conn.query("SELECT count(*)::int as count from pinneds where deleted = false", &[]).unwrap();
+    let rows = &conn.query("SELECT count(*)::int as count from pinneds as s join posts as p on p.id = s.post_id where s.deleted = false and p.visibility = 'public'", &[]).unwrap();
     let row = rows.get(0);
     let count = row.get("count");
     Ok(count)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unlisted_items_open_only_with_their_token() {
+        assert!(is_visible("unlisted", &1, "abc", &2, "abc"));
+        assert!(!is_visible("unlisted", &1, "abc", &2, ""));
+        assert!(!is_visible("unlisted", &1, "abc", &2, "abd"));
+        assert!(!is_visible("unlisted", &1, "abc", &2, "abcd"));
+        // An item saved before share tokens existed has none to match.
+        assert!(!is_visible("unlisted", &1, "", &2, ""));
+        assert!(is_visible("unlisted", &1, "", &1, ""));
+    }
+
+    #[test]
+    fn private_items_open_only_for_their_author() {
+        assert!(is_visible("private", &1, "abc", &1, ""));
+        assert!(!is_visible("private", &1, "abc", &2, "abc"));
+        assert!(is_visible("public", &1, "abc", &2, ""));
+    }
+
+    #[test]
+    fn only_unlisted_links_carry_the_token() {
+        assert_eq!(link_token("unlisted", "abc"), "abc");
+        assert_eq!(link_token("public", "abc"), "");
+        assert_eq!(link_token("private", "abc"), "");
+    }
+}
@@ -0,0 +1,113 @@
+use postgres::error::Error;
+use chrono::NaiveDateTime;
+use db;
+use env;
+use helper;
+
+#[derive(Serialize, Debug)]
+pub struct Session {
+    pub id: i32,
+    pub user_id: Option<i32>,
+    pub user_agent: String,
+    pub ip: String,
+    pub created: NaiveDateTime,
+    pub last_seen: NaiveDateTime,
+    pub formated_created: String,
+    pub formated_last_seen: String,
+    pub current: bool,
+}
+
+/// Returns the stored data of a live session. Sessions past the idle or
+/// absolute timeout are treated as missing.
+pub fn get_data(conn: &db::PostgresConnection, session_hash: &String) -> Result<Option<String>, Error> {
+    let rows = try!(conn.query("
+        SELECT data from sessions
+        where session_hash = $1
+          and last_seen > now() - $2::int * interval '1 second'
+          and created > now() - $3::int * interval '1 second'",
+        &[&session_hash, &env::CONFIG.team_session_idle_timeout, &env::CONFIG.team_session_absolute_timeout]));
+    Ok(rows.iter().next().map(|row| row.get("data")))
+}
+
+/// Bumps last_seen, at most once a minute to keep writes down.
+pub fn touch(conn: &db::PostgresConnection, session_hash: &String, ip: &String) -> Result<(), Error> {
+    conn.execute(
+        "UPDATE sessions set last_seen = now(), ip = $2 WHERE session_hash = $1 and last_seen < now() - interval '1 minute'",
+        &[&session_hash, &ip]
+    ).map(|_| ())
+}
+
+/// Stores the session's values. A row is only inserted for a session minted in this
+/// request (`is_new`); an existing one whose row is gone was revoked and is not
+/// re-created. Returns whether the session was stored.
+pub fn save(conn: &db::PostgresConnection, session_hash: &String, user_id: &Option<i32>, data: &String, user_agent: &String, ip: &String, is_new: &bool) -> Result<bool, Error> {
+    let updated = try!(conn.execute(
+        "UPDATE sessions set user_id = $2, data = $3 WHERE session_hash = $1",
+        &[&session_hash, &user_id, &data]
+    ));
+    if updated > 0 {
+        return Ok(true);
+    }
+    if !*is_new {
+        return Ok(false);
+    }
+    try!(delete_expired(conn));
+    conn.execute(
+        "INSERT INTO sessions (session_hash, user_id, data, user_agent, ip) VALUES ($1, $2, $3, $4, $5)",
+        &[&session_hash, &user_id, &data, &user_agent, &ip]
+    ).map(|_| true)
+}
+
+pub fn delete_expired(conn: &db::PostgresConnection) -> Result<(), Error> {
+    conn.execute(
+        "DELETE FROM sessions WHERE last_seen < now() - $1::int * interval '1 second' or created < now() - $2::int * interval '1 second'",
+        &[&env::CONFIG.team_session_idle_timeout, &env::CONFIG.team_session_absolute_timeout]
+    ).map(|_| ())
+}
+
+pub fn delete_by_hash(conn: &db::PostgresConnection, session_hash: &String) -> Result<(), Error> {
+    conn.execute("DELETE FROM sessions WHERE session_hash = $1", &[&session_hash]).map(|_| ())
+}
+
+pub fn list_by_user_id(conn: &db::PostgresConnection, user_id: &i32, current_hash: &String) -> Result<Vec<Session>, Error> {
+    let mut sessions: Vec<Session> = Vec::new();
+    for row in &try!(conn.query("
+        SELECT id, session_hash, user_id, user_agent, ip, created, last_seen from sessions
+        where user_id = $1
+          and last_seen > now() - $2::int * interval '1 second'
+          and created > now() - $3::int * interval '1 second'
+        order by last_seen desc",
+        &[&user_id, &env::CONFIG.team_session_idle_timeout, &env::CONFIG.team_session_absolute_timeout])) {
+        let session_hash: String = row.get("session_hash");
+        let mut session = Session {
+            id: row.get("id"),
+            user_id: row.get("user_id"),
+            user_agent: row.get("user_agent"),
+            ip: row.get("ip"),
+            created: row.get("created"),
+            last_seen: row.get("last_seen"),
+            formated_created: "".to_string(),
+            formated_last_seen: "".to_string(),
+            current: &session_hash == current_hash,
+        };
+        session.formated_created = helper::jst_time_formatter(session.created);
+        session.formated_last_seen = helper::jst_time_formatter(session.last_seen);
+        sessions.push(session);
+    }
+    Ok(sessions)
+}
+
+pub fn delete(conn: &db::PostgresConnection, id: &i32, user_id: &i32) -> Result<(), Error> {
+    conn.execute("DELETE FROM sessions WHERE id = $1 and user_id = $2", &[&id, &user_id]).map(|_| ())
+}
+
+pub fn delete_others(conn: &db::PostgresConnection, user_id: &i32, current_hash: &String) -> Result<(), Error> {
+    conn.execute(
+        "DELETE FROM sessions WHERE user_id = $1 and session_hash <> $2", &[&user_id, &current_hash]
+    ).map(|_| ())
+}
+
+/// Signs the user out everywhere.
+pub fn delete_by_user_id(conn: &db::PostgresConnection, user_id: &i32) -> Result<(), Error> {
+    conn.execute("DELETE FROM sessions WHERE user_id = $1", &[&user_id]).map(|_| ())
+}
@@ -0,0 +1,48 @@
+use postgres::error::Error;
+use db;
+
+pub const REQUIRE_2FA: &str = "require_2fa";
+pub const SIGNUP_MODE: &str = "signup_mode";
+pub const SIGNUP_DOMAINS: &str = "signup_domains";
+
+pub const SIGNUP_OPEN: &str = "open";
+pub const SIGNUP_INVITE: &str = "invite";
+pub const SIGNUP_DOMAIN: &str = "domain";
+pub const SIGNUP_MODES: [&str; 3] = [SIGNUP_OPEN, SIGNUP_INVITE, SIGNUP_DOMAIN];
+
+pub fn get(conn: &db::PostgresConnection, name: &str) -> Result<Option<String>, Error> {
+    let rows = try!(conn.query("SELECT value from team_settings where name = $1", &[&name]));
+    if rows.len() == 0 {
+        return Ok(None);
+    }
+    Ok(rows.get(0).get("value"))
+}
+
+pub fn get_bool(conn: &db::PostgresConnection, name: &str) -> Result<bool, Error> {
+    get(conn, name).map(|v| v.map(|v| v == "true").unwrap_or(false))
+}
+
+pub fn signup_mode(conn: &db::PostgresConnection) -> Result<String, Error> {
+    get(conn, SIGNUP_MODE).map(|v| v.unwrap_or(SIGNUP_OPEN.to_string()))
+}
+
+pub fn signup_domains(conn: &db::PostgresConnection) -> Result<Vec<String>, Error> {
+    get(conn, SIGNUP_DOMAINS).map(|v| {
+        v.unwrap_or("".to_string())
+            .split(",")
+            .map(|d| d.trim().to_lowercase())
+            .filter(|d| d != "")
+            .collect()
+    })
+}
+
+pub fn set(conn: &db::PostgresConnection, name: &str, value: &str) -> Result<(), Error> {
+    try!(conn.execute(
+        "update team_settings set value=$2, updated=now() where name=$1", &[&name, &value]
+    ));
+    conn.execute(
+        "insert into team_settings (name, value)
+        select $1, $2
+        where not exists (select 1 from team_settings where name=$1)", &[&name, &value]
+    ).map(|_| ())
+}
@@ -0,0 +1,43 @@
+use postgres::error::Error;
+use db;
+use helper;
+
+/// A password signup waiting for its email address to be confirmed.
+#[derive(Debug)]
+pub struct SignupConfirmation {
+    pub username: String,
+    // Already encrypted.
+    pub password: String,
+    pub email: String,
+    pub invite_id: Option<i32>,
+}
+
+pub fn create(conn: &db::PostgresConnection, username: &String, password: &String, email: &String, invite_id: &Option<i32>, token: &String, ip: &String) -> Result<(), Error> {
+    conn.execute("
+        INSERT INTO signup_confirmations (username, password, email, invite_id, token_hash, ip, expires)
+        VALUES ($1, $2, $3, $4, $5, $6, now() + interval '1 day')",
+        &[&username, &password, &email, &invite_id, &helper::token_hash(token), &ip]
+    ).map(|_| ())
+}
+
+/// Marks the token used and returns the signup it confirms, so a link works only once.
+pub fn consume(conn: &db::PostgresConnection, token: &String) -> Result<Option<SignupConfirmation>, Error> {
+    let rows = try!(conn.query(
+        "UPDATE signup_confirmations set used = true, updated = now()
+        WHERE token_hash = $1 and used = false and expires > now()
+        returning username, password, email, invite_id",
+        &[&helper::token_hash(token)]));
+    Ok(rows.iter().next().map(|row| SignupConfirmation {
+        username: row.get("username"),
+        password: row.get("password"),
+        email: row.get("email"),
+        invite_id: row.get("invite_id"),
+    }))
+}
+
+pub fn recent_count_by_ip(conn: &db::PostgresConnection, ip: &String) -> Result<i32, Error> {
+    let rows = try!(conn.query(
+        "SELECT count(*)::int as count from signup_confirmations where ip = $1 and created > now() - interval '1 hour'",
+        &[&ip]));
+    Ok(rows.get(0).get("count"))
+}
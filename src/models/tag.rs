@@ -1,4 +1,5 @@
 use postgres::error::Error;
+use postgres::transaction::Transaction;
 use db;
 use models;
 use helper;
@@ -22,6 +23,21 @@ pub fn select_or_create_tag_id(conn: &db::PostgresConnection, tag_name: &str) ->
     Ok(tag_id)
 }
 
+// `select_or_create_tag_id` inside a transaction.
+fn select_or_create_tag_id_in(trans: &Transaction, tag_name: &str) -> Result<i32, Error> {
+    let rows = try!(trans.query("SELECT id from tags where name = $1", &[&tag_name]));
+    if let Some(row) = rows.iter().next() {
+        return Ok(row.get("id"));
+    }
+    let rows = try!(trans.query("INSERT INTO tags (name) VALUES ($1) returning id;", &[&tag_name]));
+    Ok(rows.get(0).get("id"))
+}
+
+pub fn get_tag_id(conn: &db::PostgresConnection, tag_name: &str) -> Result<Option<i32>, Error> {
+    let rows = try!(conn.query("SELECT id from tags where name = $1", &[&tag_name]));
+    Ok(rows.iter().next().map(|row| row.get("id")))
+}
+
 pub fn get_tags_by_post_id(conn: &db::PostgresConnection, post_id: &i32) -> Result<Vec<Tag>, Error> {
     let mut tags: Vec<Tag> = Vec::new();
     for row in &conn.query("select t2.id, t2.name from taggings as t1 join tags as t2 on t1.tag_id = t2.id where t1.post_id = $1 order by t2.id desc", &[&post_id]).unwrap() {
@@ -33,9 +49,104 @@ pub fn get_tags_by_post_id(conn: &db::PostgresConnection, post_id: &i32) -> Resu
     Ok(tags)
 }
 
-pub fn tag_search(conn: &db::PostgresConnection, tag_name: &String, offset: i32, limit: i32) -> Result<Vec<models::post::Post>, Error> {
+pub fn get_tags_by_gist_id(conn: &db::PostgresConnection, gist_id: &i32) -> Result<Vec<Tag>, Error> {
+    let rows = try!(conn.query("select t2.id, t2.name from gist_taggings as t1 join tags as t2 on t1.tag_id = t2.id where t1.gist_id = $1 order by t2.id desc", &[&gist_id]));
+    Ok(rows.iter().map(|row| Tag {
+        id: row.get("id"),
+        name: row.get("name"),
+    }).collect())
+}
+
+/// Replaces a gist's tags with the comma separated `tags`, as part of saving the gist.
+pub fn set_gist_tags(trans: &Transaction, gist_id: &i32, tags: &String) -> Result<(), Error> {
+    let mut tag_ids: Vec<i32> = Vec::new();
+    for tag in tags.split(",") {
+        let tag = tag.trim();
+        if tag == "" {
+            continue;
+        }
+        let tag_id = try!(select_or_create_tag_id_in(trans, tag));
+        if !tag_ids.contains(&tag_id) {
+            tag_ids.push(tag_id);
+        }
+    }
+    try!(trans.execute("DELETE FROM gist_taggings WHERE gist_id = $1", &[&gist_id]));
+    for tag_id in &tag_ids {
+        try!(trans.execute("INSERT INTO gist_taggings (tag_id, gist_id) VALUES ($1, $2)", &[&tag_id, &gist_id]));
+    }
+    Ok(())
+}
+
+// Characters that end a hashtag. `public/js/app.js` links hashtags with the same rules.
+// `+`, `=` and `%` are left out so a tag can go into a query string as it is.
+const HASHTAG_STOP: &str = "#.,!?;:()[]{}<>\"'`/\\*~|&+=%、。，．！？：；「」『』（）【】";
+// Characters a hashtag may follow, besides whitespace and the start of a line.
+const HASHTAG_OPEN: &str = "([「（";
+
+/// The `#hashtags` in a tweet body, in order and without duplicates. Code spans and
+/// fenced blocks are skipped, and so are all-digit tags like `#1`.
+pub fn hashtags(body: &str) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    let mut in_fence = false;
+    for line in body.lines() {
+        if line.trim_left().starts_with("```") {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence {
+            continue;
+        }
+        let mut in_code = false;
+        let mut prev: Option<char> = None;
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c == '`' {
+                in_code = !in_code;
+            }
+            let opens = match prev {
+                None => true,
+                Some(p) => p.is_whitespace() || HASHTAG_OPEN.contains(p),
+            };
+            prev = Some(c);
+            if in_code || c != '#' || !opens {
+                continue;
+            }
+            let mut tag = String::new();
+            while let Some(&next) = chars.peek() {
+                if next.is_whitespace() || HASHTAG_STOP.contains(next) {
+                    break;
+                }
+                tag.push(next);
+                prev = Some(next);
+                chars.next();
+            }
+            if tag != "" && tag.chars().count() <= 255 && !tag.chars().all(|c| c.is_digit(10)) && !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+    }
+    tags
+}
+
+/// Replaces a tweet's tags with the hashtags in its body, as part of saving the tweet.
+pub fn set_tweet_tags(trans: &Transaction, tweet_id: &i32, body: &str) -> Result<(), Error> {
+    let mut tag_ids: Vec<i32> = Vec::new();
+    for tag in hashtags(body) {
+        let tag_id = try!(select_or_create_tag_id_in(trans, &tag));
+        if !tag_ids.contains(&tag_id) {
+            tag_ids.push(tag_id);
+        }
+    }
+    try!(trans.execute("DELETE FROM tweet_taggings WHERE tweet_id = $1", &[&tweet_id]));
+    for tag_id in &tag_ids {
+        try!(trans.execute("INSERT INTO tweet_taggings (tag_id, tweet_id) VALUES ($1, $2)", &[&tag_id, &tweet_id]));
+    }
+    Ok(())
+}
+
+pub fn tag_search(conn: &db::PostgresConnection, tag_name: &String, viewer_id: &i32, offset: i32, limit: i32) -> Result<Vec<models::post::Post>, Error> {
     let mut posts: Vec<models::post::Post> = Vec::new();
-    for row in &conn.query("SELECT p.id, p.kind, p.user_id, p.title, p.body, p.created, p.shared, p.status, u.username, u.icon_url from posts as p join users as u on u.id = p.user_id join taggings as t on p.id = t.post_id join tags as tg on t.tag_id = tg.id where tg.name = $1 order by p.id desc offset $2::int limit $3::int", &[&tag_name, &offset, &limit]).unwrap() {
+    for row in &conn.query("SELECT p.id, p.kind, p.user_id, p.title, p.body, p.created, p.shared, p.status, p.visibility, u.username, u.icon_url, u.deactivated from posts as p join users as u on u.id = p.user_id join taggings as t on p.id = t.post_id join tags as tg on t.tag_id = tg.id where tg.name = $1 and (p.visibility = 'public' or p.user_id = $4) order by p.id desc offset $2::int limit $3::int", &[&tag_name, &offset, &limit, &viewer_id]).unwrap() {
         match models::tag::get_tags_by_post_id(&conn, &row.get("id")) {
             Ok(tags) => {
                 let mut post = models::post::Post {
@@ -48,11 +159,14 @@ pub fn tag_search(conn: &db::PostgresConnection, tag_name: &String, offset: i32,
                     formated_created: "".to_string(),
                     shared: row.get("shared"),
                     status: row.get("status"),
+                    visibility: row.get("visibility"),
+                    share_token: "".to_string(),
                     user: models::user::User{
                         id: row.get("user_id"),
                         username: row.get("username"),
                         icon_url: row.get("icon_url"),
                         username_hash: helper::username_hash(row.get("username")),
+                        deactivated: row.get("deactivated"),
                     },
                     tags: tags,
                 };
@@ -67,16 +181,25 @@ pub fn tag_search(conn: &db::PostgresConnection, tag_name: &String, offset: i32,
     Ok(posts)
 }
 
-pub fn tag_count(conn: &db::PostgresConnection, tag_name: &String) -> Result<i32, Error> {
-    let rows = &conn.query("SELECT count(p.*)::int as count from posts as p join taggings as t on p.id = t.post_id join tags tg on t.tag_id = tg.id where tg.name = $1", &[&tag_name]).unwrap();
+pub fn tag_count(conn: &db::PostgresConnection, tag_name: &String, viewer_id: &i32) -> Result<i32, Error> {
+    let rows = &conn.query("SELECT count(p.*)::int as count from posts as p join taggings as t on p.id = t.post_id join tags tg on t.tag_id = tg.id where tg.name = $1 and (p.visibility = 'public' or p.user_id = $2)", &[&tag_name, &viewer_id]).unwrap();
     let row = rows.get(0);
     let count = row.get("count");
     Ok(count)
 }
 
+/// Tags on something everyone can see, so a tag used only on private or unlisted
+/// items does not give its name away.
 pub fn tag_list(conn: &db::PostgresConnection) -> Result<Vec<Tag>, Error> {
     let mut tags: Vec<Tag> = Vec::new();
-    for row in &conn.query("select * from tags order by id desc", &[]).unwrap() {
+    for row in &try!(conn.query("
+        select tg.id, tg.name from tags as tg
+        where exists (select 1 from taggings as t join posts as p on p.id = t.post_id
+                      where t.tag_id = tg.id and p.status = 'publish' and p.visibility = 'public')
+        or exists (select 1 from gist_taggings as t join gists as g on g.id = t.gist_id
+                   where t.tag_id = tg.id and g.visibility = 'public')
+        or exists (select 1 from tweet_taggings as t where t.tag_id = tg.id)
+        order by tg.id desc", &[])) {
         tags.push(Tag {
             id: row.get("id"),
             name: row.get("name"),
@@ -84,3 +207,49 @@ pub fn tag_list(conn: &db::PostgresConnection) -> Result<Vec<Tag>, Error> {
     }
     Ok(tags)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::hashtags;
+
+    #[test]
+    fn hashtags_are_read_in_order() {
+        assert_eq!(hashtags("Shipping #rust and #postgres today"), vec!["rust", "postgres"]);
+    }
+
+    #[test]
+    fn hashtags_end_at_punctuation() {
+        assert_eq!(hashtags("#rust, #iron. (#serde) #c++ 「#日本語」"), vec!["rust", "iron", "serde", "c", "日本語"]);
+    }
+
+    #[test]
+    fn hashtags_need_a_break_before_them() {
+        assert_eq!(hashtags("issue#12 a#b http://example.com/#anchor #ok"), vec!["ok"]);
+    }
+
+    #[test]
+    fn all_digit_hashtags_are_ignored() {
+        assert_eq!(hashtags("Fixed #1 and #42, see #v2"), vec!["v2"]);
+    }
+
+    #[test]
+    fn duplicate_hashtags_are_read_once() {
+        assert_eq!(hashtags("#rust is #fun, #rust"), vec!["rust", "fun"]);
+    }
+
+    #[test]
+    fn hashtags_in_code_spans_are_ignored() {
+        assert_eq!(hashtags("use `#[derive(Debug)]` and `#cfg` in #rust"), vec!["rust"]);
+    }
+
+    #[test]
+    fn hashtags_in_fenced_blocks_are_ignored() {
+        let body = "Before #one\n```\n#include <stdio.h>\n#two\n```\nAfter #three";
+        assert_eq!(hashtags(body), vec!["one", "three"]);
+    }
+
+    #[test]
+    fn a_lone_hash_is_not_a_hashtag() {
+        assert!(hashtags("# heading and ## more #").is_empty());
+    }
+}
@@ -0,0 +1,81 @@
+use postgres::error::Error;
+use db;
+use helper;
+
+#[derive(Serialize, Debug, Default)]
+pub struct Totp {
+    pub secret: Option<String>,
+    pub enabled: bool,
+}
+
+pub fn get_by_user_id(conn: &db::PostgresConnection, user_id: &i32) -> Result<Totp, Error> {
+    let mut totp = Totp{..Default::default()};
+    for row in &try!(conn.query("SELECT totp_secret, totp_enabled from users where id = $1", &[&user_id])) {
+        totp = Totp {
+            secret: row.get("totp_secret"),
+            enabled: row.get("totp_enabled"),
+        };
+    }
+    Ok(totp)
+}
+
+pub fn set_secret(conn: &db::PostgresConnection, user_id: &i32, secret: &String) -> Result<(), Error> {
+    conn.execute(
+        "UPDATE users set totp_secret = $2, totp_enabled = false, totp_last_step = null WHERE id = $1", &[&user_id, &secret]
+    ).map(|_| ())
+}
+
+/// Turns 2FA on, remembering the step of the code that confirmed it so it can't sign in too.
+pub fn enable(conn: &db::PostgresConnection, user_id: &i32, step: &i64) -> Result<(), Error> {
+    conn.execute(
+        "UPDATE users set totp_enabled = true, totp_last_step = $2 WHERE id = $1 and totp_secret is not null",
+        &[&user_id, &step]
+    ).map(|_| ())
+}
+
+/// Records a code's time step as used; returns false if it or a later one was used already.
+pub fn use_step(conn: &db::PostgresConnection, user_id: &i32, step: &i64) -> Result<bool, Error> {
+    let updated = try!(conn.execute(
+        "UPDATE users set totp_last_step = $2 WHERE id = $1 and (totp_last_step is null or totp_last_step < $2)",
+        &[&user_id, &step]
+    ));
+    Ok(updated > 0)
+}
+
+pub fn disable(conn: &db::PostgresConnection, user_id: &i32) -> Result<(), Error> {
+    try!(conn.execute("DELETE FROM recovery_codes WHERE user_id = $1", &[&user_id]));
+    conn.execute(
+        "UPDATE users set totp_secret = null, totp_enabled = false, totp_last_step = null WHERE id = $1", &[&user_id]
+    ).map(|_| ())
+}
+
+/// Replaces the user's recovery codes and returns the new plain-text codes.
+pub fn regenerate_recovery_codes(conn: &db::PostgresConnection, user_id: &i32) -> Result<Vec<String>, Error> {
+    try!(conn.execute("DELETE FROM recovery_codes WHERE user_id = $1", &[&user_id]));
+    let mut codes: Vec<String> = Vec::new();
+    for _ in 0..10 {
+        let code = helper::random_token()[0..10].to_string();
+        try!(conn.execute(
+            "INSERT INTO recovery_codes (user_id, code_hash) VALUES ($1, $2)",
+            &[&user_id, &helper::token_hash(&code)]
+        ));
+        codes.push(code);
+    }
+    Ok(codes)
+}
+
+/// Marks a matching unused recovery code as used; returns whether one matched.
+pub fn use_recovery_code(conn: &db::PostgresConnection, user_id: &i32, code: &str) -> Result<bool, Error> {
+    let code = code.trim().to_lowercase();
+    let updated = try!(conn.execute(
+        "UPDATE recovery_codes set used = true, updated = now() WHERE user_id = $1 and code_hash = $2 and used = false",
+        &[&user_id, &helper::token_hash(&code)]
+    ));
+    Ok(updated > 0)
+}
+
+pub fn unused_recovery_code_count(conn: &db::PostgresConnection, user_id: &i32) -> Result<i32, Error> {
+    let rows = try!(conn.query("SELECT count(*)::int as count from recovery_codes where user_id = $1 and used = false", &[&user_id]));
+    let row = rows.get(0);
+    Ok(row.get("count"))
+}
@@ -1,4 +1,7 @@
+use std::collections::HashMap;
+
 use postgres::error::Error;
+use postgres::rows::Row;
 use db;
 use models;
 use helper;
@@ -9,71 +12,154 @@ pub struct Tweet {
     pub id: i32,
     pub user_id: i32,
     pub body: String,
+    pub edited: bool,
     pub created: NaiveDateTime,
     pub formated_created: String,
     pub comment_count: i32,
     pub user: models::user::User,
+    pub attachments: Vec<Attachment>,
+}
+
+/// An image attached to a tweet, stored by the image upload as `img/posts/<name>.png`.
+#[derive(Serialize, Debug)]
+pub struct Attachment {
+    pub id: i32,
+    pub path: String,
+}
+
+// Attachments a tweet can carry.
+pub const MAX_ATTACHMENTS: usize = 4;
+
+const SELECT_TWEETS: &str = "
+    select t.id, t.user_id, t.body, t.edited, t.created, u.username, u.icon_url, u.deactivated,
+    (select count(*)::int from tweet_comments as c where c.tweet_id = t.id) as comment_count
+    from tweets as t
+    join users as u on u.id = t.user_id";
+
+fn from_row(row: &Row) -> Tweet {
+    let mut tweet = Tweet {
+        id: row.get("id"),
+        user_id: row.get("user_id"),
+        body: row.get("body"),
+        edited: row.get("edited"),
+        created: row.get("created"),
+        formated_created: "".to_string(),
+        comment_count: row.get("comment_count"),
+        user: models::user::User{
+            id: row.get("user_id"),
+            username: row.get("username"),
+            icon_url: row.get("icon_url"),
+            username_hash: helper::username_hash(row.get("username")),
+            deactivated: row.get("deactivated"),
+        },
+        attachments: Vec::new(),
+    };
+    tweet.formated_created = helper::jst_time_formatter(tweet.created);
+    tweet
+}
+
+fn with_attachments(conn: &db::PostgresConnection, mut tweets: Vec<Tweet>) -> Result<Vec<Tweet>, Error> {
+    for tweet in &mut tweets {
+        tweet.attachments = try!(get_attachments_by_tweet_id(conn, &tweet.id));
+    }
+    Ok(tweets)
 }
 
-pub fn create(conn: &db::PostgresConnection, user_id: &i32, body: &String) -> Result<(i32), Error> {
-    let mut post_id = 0;
-    for row in &conn.query("
+/// Saves a tweet with its hashtags and attachments, all or nothing.
+pub fn create(conn: &db::PostgresConnection, user_id: &i32, body: &String, attachments: &Vec<String>) -> Result<(i32), Error> {
+    let trans = try!(conn.transaction());
+    let rows = try!(trans.query("
         INSERT INTO tweets (user_id, body)
         VALUES ($1, $2) returning id;",
-        &[&user_id, &body]).unwrap() {
-        post_id = row.get("id");
+        &[&user_id, &body]));
+    let post_id: i32 = rows.get(0).get("id");
+    try!(models::tag::set_tweet_tags(&trans, &post_id, body));
+    // Only the poster's own uploads that no tweet carries yet are attached.
+    for path in attachments {
+        try!(trans.execute("
+            INSERT INTO tweet_attachments (tweet_id, path)
+            SELECT $1, u.path from uploads as u
+            where u.user_id = $2 and u.path = $3
+            and not exists (select 1 from tweet_attachments as a where a.path = u.path)",
+            &[&post_id, &user_id, &path]));
     }
+    try!(trans.commit());
     Ok(post_id)
 }
 
+pub fn get_attachments_by_tweet_id(conn: &db::PostgresConnection, id: &i32) -> Result<Vec<Attachment>, Error> {
+    let rows = try!(conn.query("SELECT id, path from tweet_attachments where tweet_id = $1 order by id", &[&id]));
+    Ok(rows.iter().map(|row| Attachment {
+        id: row.get("id"),
+        path: row.get("path"),
+    }).collect())
+}
+
 #[derive(Serialize, Debug)]
 pub struct Comment {
     pub id: i32,
     pub user_id: i32,
     pub tweet_id: i32,
+    // The comment this one replies to, if any.
+    pub parent_id: Option<i32>,
     pub body: String,
+    pub edited: bool,
     pub created: NaiveDateTime,
     pub formated_created: String,
     pub user: models::user::User,
 }
 
-pub fn add_comment(conn: &db::PostgresConnection, user_id: &i32, tweet_id: &i32, body: &String) -> Result<(i32), Error> {
+const SELECT_COMMENTS: &str = "
+    SELECT c.id, c.user_id, c.tweet_id, c.parent_id, c.body, c.edited, c.created, u.username, u.icon_url, u.deactivated
+    from tweet_comments as c
+    join users as u on u.id = c.user_id";
+
+fn comment_from_row(row: &Row) -> Comment {
+    let mut comment = Comment {
+        id: row.get("id"),
+        user_id: row.get("user_id"),
+        tweet_id: row.get("tweet_id"),
+        parent_id: row.get("parent_id"),
+        body: row.get("body"),
+        edited: row.get("edited"),
+        created: row.get("created"),
+        formated_created: "".to_string(),
+        user: models::user::User{
+            id: row.get("user_id"),
+            username: row.get("username"),
+            icon_url: row.get("icon_url"),
+            username_hash: helper::username_hash(row.get("username")),
+            deactivated: row.get("deactivated"),
+        }
+    };
+    comment.formated_created = helper::jst_time_formatter(comment.created);
+    comment
+}
+
+/// Adds a comment, or a reply when `parent_id` is set. The tweet's author is notified,
+/// and so is the author of the comment replied to.
+pub fn add_comment(conn: &db::PostgresConnection, user_id: &i32, tweet_id: &i32, parent_id: &Option<i32>, body: &String) -> Result<(i32), Error> {
     &conn.query("
-        INSERT INTO tweet_comments (user_id, tweet_id, body) 
-        VALUES ($1, $2, $3) returning id;",
-        &[&user_id, &tweet_id, &body]).unwrap();
+        INSERT INTO tweet_comments (user_id, tweet_id, parent_id, body)
+        VALUES ($1, $2, $3, $4) returning id;",
+        &[&user_id, &tweet_id, &parent_id, &body]).unwrap();
     let tweets = &conn.query("SELECT * from tweets where id = $1", &[&tweet_id]).unwrap();
     let tweet = tweets.get(0);
     let tweet_user_id: i32 = tweet.get("user_id");
-    models::notification::create(conn, &format!("/tweet/show/{}", tweet_id).to_string(), user_id, &tweet_user_id, body)
+    let path = format!("/tweet/show/{}", tweet_id).to_string();
+    if let Some(ref parent_id) = *parent_id {
+        if let Some(parent) = try!(get_comment_by_id(conn, parent_id)) {
+            if parent.user_id != tweet_user_id && parent.user_id != *user_id {
+                try!(models::notification::create(conn, &path, user_id, &parent.user_id, body));
+            }
+        }
+    }
+    models::notification::create(conn, &path, user_id, &tweet_user_id, body)
 }
 
 pub fn list(conn: &db::PostgresConnection, offset: &i32, limit: &i32) -> Result<Vec<Tweet>, Error> {
-    let mut tweets: Vec<Tweet> = Vec::new();
-    for row in &conn.query("
-        select t.id, t.user_id, t.body, t.created, u.username, u.icon_url,
-        (select count(*)::int from tweet_comments as c where c.tweet_id = t.id) as comment_count
-        from tweets as t
-        join users as u on u.id = t.user_id
-        order by t.id desc offset $1::int limit $2::int", &[&offset, &limit]).unwrap() {
-        let mut tweet = Tweet {
-            id: row.get("id"),
-            user_id: row.get("user_id"),
-            body: row.get("body"),
-            created: row.get("created"),
-            formated_created: "".to_string(),
-            comment_count: row.get("comment_count"),
-            user: models::user::User{
-                id: row.get("user_id"),
-                username: row.get("username"),
-                icon_url: row.get("icon_url"),
-                username_hash: helper::username_hash(row.get("username")),
-            },
-        };
-        tweet.formated_created = helper::jst_time_formatter(tweet.created);
-        tweets.push(tweet);
-    }
-    Ok(tweets)
+    let rows = try!(conn.query(&format!("{} order by t.id desc offset $1::int limit $2::int", SELECT_TWEETS), &[&offset, &limit]));
+    with_attachments(conn, rows.iter().map(|row| from_row(&row)).collect())
 }
 
 pub fn count(conn: &db::PostgresConnection) -> Result<i32, Error> {
@@ -83,48 +169,167 @@ pub fn count(conn: &db::PostgresConnection) -> Result<i32, Error> {
     Ok(count)
 }
 
+/// Tweets carrying the hashtag, newest first.
+pub fn tagged(conn: &db::PostgresConnection, tag_name: &String, offset: &i32, limit: &i32) -> Result<Vec<Tweet>, Error> {
+    let rows = try!(conn.query(&format!("{}
+        join tweet_taggings as tt on tt.tweet_id = t.id
+        join tags as tg on tg.id = tt.tag_id
+        where tg.name = $1
+        order by t.id desc offset $2::int limit $3::int", SELECT_TWEETS), &[&tag_name, &offset, &limit]));
+    with_attachments(conn, rows.iter().map(|row| from_row(&row)).collect())
+}
+
+pub fn tagged_count(conn: &db::PostgresConnection, tag_name: &String) -> Result<i32, Error> {
+    let rows = try!(conn.query("
+        SELECT count(*)::int as count from tweet_taggings as tt join tags as tg on tg.id = tt.tag_id
+        where tg.name = $1", &[&tag_name]));
+    Ok(rows.get(0).get("count"))
+}
+
+pub fn exists(conn: &db::PostgresConnection, id: &i32) -> Result<bool, Error> {
+    let rows = try!(conn.query("SELECT count(*)::int as count from tweets where id = $1", &[&id]));
+    let count: i32 = rows.get(0).get("count");
+    Ok(count > 0)
+}
+
 pub fn get_by_id(conn: &db::PostgresConnection, id: &i32) -> Result<Tweet, Error> {
-    let rows = &conn.query("
-        select t.id, t.user_id, t.body, t.created, u.username, u.icon_url,
-        (select count(*)::int from tweet_comments as c where c.tweet_id = t.id) as comment_count
-        from tweets as t join users as u on u.id=t.user_id 
-        where t.id = $1", &[&id]).unwrap();
-    let row = rows.get(0);
-    let mut tweet = Tweet {
-        id: row.get("id"),
-        user_id: row.get("user_id"),
-        body: row.get("body"),
-        created: row.get("created"),
-        formated_created: "".to_string(),
-        comment_count: row.get("comment_count"),
-        user: models::user::User{
-            id: row.get("user_id"),
-            username: row.get("username"),
-            icon_url: row.get("icon_url"),
-            username_hash: helper::username_hash(row.get("username")),
-        },
-    };
-    tweet.formated_created = helper::jst_time_formatter(tweet.created);
+    let rows = try!(conn.query(&format!("{} where t.id = $1", SELECT_TWEETS), &[&id]));
+    let mut tweet = from_row(&rows.get(0));
+    tweet.attachments = try!(get_attachments_by_tweet_id(conn, id));
     Ok(tweet)
 }
 
 pub fn get_comments_by_tweet_id(conn: &db::PostgresConnection, id: &i32) -> Result<Vec<Comment>, Error> {
-    let mut comments: Vec<Comment> = Vec::new();
-    for row in &conn.query("SELECT c.id, c.user_id, c.tweet_id, c.body, c.created,u.username, u.icon_url from tweet_comments as c join users as u on u.id = c.user_id where c.tweet_id = $1 order by c.id asc", &[&id]).unwrap() {
-        comments.push(Comment {
-            id: row.get("id"),
-            user_id: row.get("user_id"),
-            tweet_id: row.get("tweet_id"),
-            body: row.get("body"),
-            created: row.get("created"),
-            formated_created: "".to_string(),
-            user: models::user::User{
-                id: row.get("user_id"),
-                username: row.get("username"),
-                icon_url: row.get("icon_url"),
-                username_hash: helper::username_hash(row.get("username")),
+    let rows = try!(conn.query(&format!("{} where c.tweet_id = $1 order by c.id asc", SELECT_COMMENTS), &[&id]));
+    Ok(rows.iter().map(|row| comment_from_row(&row)).collect())
+}
+
+/// Orders comments as a thread: each reply follows the comment it answers, oldest first,
+/// paired with how deep it is nested. Replies to deleted comments move to the top level.
+pub fn thread(comments: Vec<Comment>) -> Vec<(Comment, usize)> {
+    let ids: Vec<i32> = comments.iter().map(|c| c.id).collect();
+    let mut children: HashMap<Option<i32>, Vec<Comment>> = HashMap::new();
+    for comment in comments {
+        let parent = match comment.parent_id {
+            Some(id) if ids.contains(&id) => Some(id),
+            _ => None,
+        };
+        children.entry(parent).or_insert_with(Vec::new).push(comment);
+    }
+
+    let mut threaded: Vec<(Comment, usize)> = Vec::new();
+    // Walked with an explicit stack, last sibling pushed first so the oldest comes out first.
+    let mut stack: Vec<(Comment, usize)> = children.remove(&None).unwrap_or_else(Vec::new)
+        .into_iter().rev().map(|c| (c, 0)).collect();
+    while let Some((comment, depth)) = stack.pop() {
+        if let Some(replies) = children.remove(&Some(comment.id)) {
+            for reply in replies.into_iter().rev() {
+                stack.push((reply, depth + 1));
             }
-        });
+        }
+        threaded.push((comment, depth));
+    }
+    threaded
+}
+
+/// Re-reads the hashtags of every tweet that has any, for tweets written before
+/// hashtags were tagged. Returns how many tweets were read.
+pub fn backfill_tags(conn: &db::PostgresConnection) -> Result<usize, Error> {
+    let rows = try!(conn.query("SELECT id, body from tweets where strpos(body, '#') > 0 order by id", &[]));
+    let trans = try!(conn.transaction());
+    for row in rows.iter() {
+        let id: i32 = row.get("id");
+        let body: String = row.get("body");
+        try!(models::tag::set_tweet_tags(&trans, &id, &body));
+    }
+    try!(trans.commit());
+    Ok(rows.len())
+}
+
+/// Replaces the body, marks the tweet as edited and re-reads its hashtags, all or nothing.
+pub fn update(conn: &db::PostgresConnection, id: &i32, body: &String) -> Result<(), Error> {
+    let trans = try!(conn.transaction());
+    try!(trans.execute(
+        "UPDATE tweets set body = $1, edited = true, updated = now() WHERE id = $2", &[&body, &id]
+    ));
+    try!(models::tag::set_tweet_tags(&trans, id, body));
+    trans.commit()
+}
+
+/// Deletes the tweet with its attachment rows, returning the attached paths so the
+/// caller can remove the files; an upload is only ever attached to one tweet.
+pub fn delete_by_id(conn: &db::PostgresConnection, id: &i32) -> Result<Vec<String>, Error> {
+    let paths = try!(get_attachments_by_tweet_id(conn, id)).into_iter().map(|a| a.path).collect();
+    try!(conn.execute("DELETE FROM tweets WHERE id = $1", &[&id]));
+    Ok(paths)
+}
+
+pub fn get_comment_by_id(conn: &db::PostgresConnection, id: &i32) -> Result<Option<Comment>, Error> {
+    let rows = try!(conn.query(&format!("{} where c.id = $1", SELECT_COMMENTS), &[&id]));
+    Ok(rows.iter().next().map(|row| comment_from_row(&row)))
+}
+
+/// Replaces the body and marks the comment as edited.
+pub fn update_comment_by_id(conn: &db::PostgresConnection, id: &i32, body: &String) -> Result<(), Error> {
+    conn.execute(
+        "UPDATE tweet_comments set body = $1, edited = true, updated = now() WHERE id = $2", &[&body, &id]
+    ).map(|_| ())
+}
+
+pub fn delete_comment_by_id(conn: &db::PostgresConnection, id: &i32) -> Result<(), Error> {
+    conn.execute("DELETE FROM tweet_comments WHERE id = $1", &[&id]).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn comment(id: i32, parent_id: Option<i32>) -> Comment {
+        Comment {
+            id: id,
+            user_id: 1,
+            tweet_id: 1,
+            parent_id: parent_id,
+            body: "".to_string(),
+            edited: false,
+            created: NaiveDate::from_ymd(2017, 6, 1).and_hms(0, 0, 0),
+            formated_created: "".to_string(),
+            user: models::user::User{..Default::default()},
+        }
+    }
+
+    fn ids(threaded: Vec<(Comment, usize)>) -> Vec<(i32, usize)> {
+        threaded.into_iter().map(|(c, depth)| (c.id, depth)).collect()
+    }
+
+    #[test]
+    fn thread_keeps_top_level_comments_in_order() {
+        let comments = vec![comment(1, None), comment(2, None), comment(3, None)];
+        assert_eq!(ids(thread(comments)), vec![(1, 0), (2, 0), (3, 0)]);
+    }
+
+    #[test]
+    fn thread_puts_replies_after_their_parent() {
+        let comments = vec![comment(1, None), comment(2, None), comment(3, Some(1)), comment(4, Some(1))];
+        assert_eq!(ids(thread(comments)), vec![(1, 0), (3, 1), (4, 1), (2, 0)]);
+    }
+
+    #[test]
+    fn thread_nests_replies_to_replies() {
+        let comments = vec![comment(1, None), comment(2, Some(1)), comment(3, Some(2)), comment(4, Some(3)), comment(5, Some(1))];
+        assert_eq!(ids(thread(comments)), vec![(1, 0), (2, 1), (3, 2), (4, 3), (5, 1)]);
+    }
+
+    #[test]
+    fn thread_moves_orphan_replies_to_the_top_level() {
+        // Comment 9 was deleted, so its replies have nowhere to hang.
+        let comments = vec![comment(1, None), comment(2, Some(9)), comment(3, Some(2))];
+        assert_eq!(ids(thread(comments)), vec![(1, 0), (2, 0), (3, 1)]);
+    }
+
+    #[test]
+    fn thread_of_nothing_is_empty() {
+        assert!(thread(Vec::new()).is_empty());
     }
-    Ok(comments)
 }
@@ -0,0 +1,34 @@
+use postgres::error::Error;
+use db;
+
+/// Records who uploaded the image at `path`, so only they can attach it.
+pub fn create(conn: &db::PostgresConnection, user_id: &i32, path: &String) -> Result<(), Error> {
+    conn.execute("INSERT INTO uploads (user_id, path) VALUES ($1, $2)", &[&user_id, &path]).map(|_| ())
+}
+
+/// Whether `path` is an image `user_id` uploaded that no tweet carries yet.
+pub fn is_attachable(conn: &db::PostgresConnection, user_id: &i32, path: &String) -> Result<bool, Error> {
+    let rows = try!(conn.query("
+        SELECT count(*)::int as count from uploads as u
+        where u.user_id = $1 and u.path = $2
+        and not exists (select 1 from tweet_attachments as a where a.path = u.path)",
+        &[&user_id, &path]));
+    let count: i32 = rows.get(0).get("count");
+    Ok(count > 0)
+}
+
+/// Whether a post, tweet or comment body still links the image at `path`.
+pub fn is_referenced(conn: &db::PostgresConnection, path: &String) -> Result<bool, Error> {
+    let rows = try!(conn.query("
+        SELECT exists(select 1 from posts where strpos(body, $1) > 0)
+        or exists(select 1 from post_comments where strpos(body, $1) > 0)
+        or exists(select 1 from gist_comments where strpos(body, $1) > 0)
+        or exists(select 1 from tweets where strpos(body, $1) > 0)
+        or exists(select 1 from tweet_comments where strpos(body, $1) > 0) as referenced",
+        &[&path]));
+    Ok(rows.get(0).get("referenced"))
+}
+
+pub fn delete_by_path(conn: &db::PostgresConnection, path: &String) -> Result<(), Error> {
+    conn.execute("DELETE FROM uploads WHERE path = $1", &[&path]).map(|_| ())
+}
@@ -1,14 +1,24 @@
 use postgres::error::Error;
 use db;
 use helper;
+use models;
 use env;
 
+pub const ROLE_ADMIN: &str = "admin";
+pub const ROLE_MEMBER: &str = "member";
+pub const ROLE_GUEST: &str = "guest";
+pub const ROLES: [&str; 3] = [ROLE_ADMIN, ROLE_MEMBER, ROLE_GUEST];
+
+// The first account on a fresh install becomes the admin.
+const INITIAL_ROLE: &str = "CASE WHEN EXISTS (SELECT 1 FROM users) THEN 'member' ELSE 'admin' END";
+
 #[derive(Serialize, Debug, Default)]
 pub struct User {
     pub id: i32,
     pub username: String,
     pub icon_url: Option<String>,
     pub username_hash: String,
+    pub deactivated: bool,
 }
 
 #[derive(Serialize, Debug, Default)]
@@ -37,30 +47,87 @@ pub struct UserWithPreference {
     pub username_hash: String,
     pub menu: Vec<String>,
     pub theme: String,
+    pub csrf_token: String,
+    pub role: String,
+    pub is_admin: bool,
+    pub is_guest: bool,
 }
 
-pub fn create(conn: &db::PostgresConnection, username: &String, password: &String) -> Result<(i32), Error> {
-    let rows = &conn.query("INSERT INTO users (username, password) VALUES ($1, $2) returning id;", &[&username, &password]).unwrap();
-    let row = rows.get(0);
-    let user_id: i32 = row.get("id");
-    Ok(user_id)
+#[derive(Serialize, Debug, Default)]
+pub struct UserWithRole {
+    pub id: i32,
+    pub username: String,
+    pub icon_url: Option<String>,
+    pub email: Option<String>,
+    pub username_hash: String,
+    pub role: String,
+    pub auth_source: String,
+    pub totp_enabled: bool,
+    pub deactivated: bool,
+}
+
+/// Creates a password account, using up one slot of the invite the signup relies on.
+/// Nothing is saved unless both succeed; `Ok(None)` means the invite ran out.
+pub fn create(conn: &db::PostgresConnection, username: &String, password: &String, email: &String, invite_id: &Option<i32>) -> Result<Option<i32>, Error> {
+    let trans = try!(conn.transaction());
+    if let Some(ref invite_id) = *invite_id {
+        if !try!(models::invite::redeem(&trans, invite_id)) {
+            return Ok(None);
+        }
+    }
+    let rows = try!(trans.query(&format!("
+        INSERT INTO users (username, password, email, role)
+        VALUES ($1, $2, NULLIF($3, ''), {}) returning id;", INITIAL_ROLE),
+        &[&username, &password, &email]));
+    let user_id: i32 = rows.get(0).get("id");
+    try!(trans.commit());
+    Ok(Some(user_id))
+}
+
+/// Creates an account signed in through Google or OpenID Connect, using up the invite in the
+/// same transaction; returns None when the invite is no longer valid.
+pub fn create_with_email(conn: &db::PostgresConnection, username: &String, email: &String, invite_id: &Option<i32>) -> Result<Option<i32>, Error> {
+    let trans = try!(conn.transaction());
+    if let Some(ref invite_id) = *invite_id {
+        if !try!(models::invite::redeem(&trans, invite_id)) {
+            return Ok(None);
+        }
+    }
+    let rows = try!(trans.query(&format!("INSERT INTO users (username, email, role) VALUES ($1, $2, {}) returning id;", INITIAL_ROLE), &[&username, &email]));
+    let user_id: i32 = rows.get(0).get("id");
+    try!(trans.commit());
+    Ok(Some(user_id))
 }
 
-pub fn create_with_email(conn: &db::PostgresConnection, username: &String, email: &String) -> Result<(i32), Error> {
-    let rows = &conn.query("INSERT INTO users (username, email) VALUES ($1, $2) returning id;", &[&username, &email]).unwrap();
+pub fn create_with_auth_source(conn: &db::PostgresConnection, username: &String, email: &String, auth_source: &str) -> Result<(i32), Error> {
+    let rows = try!(conn.query(&format!("INSERT INTO users (username, email, auth_source, role) VALUES ($1, $2, $3, {}) returning id;", INITIAL_ROLE), &[&username, &email, &auth_source]));
     let row = rows.get(0);
     let user_id: i32 = row.get("id");
     Ok(user_id)
 }
 
+/// The account's auth source, or an empty string when there is no such user.
+pub fn get_auth_source(conn: &db::PostgresConnection, id: &i32) -> Result<String, Error> {
+    let rows = try!(conn.query("SELECT auth_source from users where id = $1", &[&id]));
+    Ok(rows.iter().next().map(|row| row.get("auth_source")).unwrap_or("".to_string()))
+}
+
+/// Whether the account has a local password; Google and OpenID Connect accounts don't.
+pub fn has_password(conn: &db::PostgresConnection, id: &i32) -> Result<bool, Error> {
+    let rows = try!(conn.query("SELECT count(*)::int as count from users where id = $1 and password is not null and password <> ''", &[&id]));
+    let count: i32 = rows.get(0).get("count");
+    Ok(count > 0)
+}
+
 pub fn get_by_username_password(conn: &db::PostgresConnection, username: &String, password: &String) -> Result<User, Error> {
     let mut user: User = User{..Default::default()};
-    for row in &conn.query("SELECT id, username, icon_url from users where username = $1 and password = $2", &[&username, &password]).unwrap() {
+    for row in &conn.query("SELECT id, username, icon_url, deactivated from users where username = $1 and password = $2", &[&username, &password]).unwrap() {
         user = User {
             id: row.get("id"),
             username: row.get("username"),
             icon_url: row.get("icon_url"),
             username_hash: helper::username_hash(row.get("username")),
+            deactivated: row.get("deactivated"),
         };
     }
     Ok(user)
@@ -68,12 +135,13 @@ pub fn get_by_username_password(conn: &db::PostgresConnection, username: &String
 
 pub fn get_by_id(conn: &db::PostgresConnection, id: &i32) -> Result<User, Error> {
     let mut user: User = User{..Default::default()};
-    for row in &conn.query("SELECT id, username, icon_url from users where id = $1", &[&id]).unwrap() {
+    for row in &conn.query("SELECT id, username, icon_url, deactivated from users where id = $1", &[&id]).unwrap() {
         user = User {
             id: row.get("id"),
             username: row.get("username"),
             icon_url: row.get("icon_url"),
             username_hash: helper::username_hash(row.get("username")),
+            deactivated: row.get("deactivated"),
         };
     }
     Ok(user)
@@ -83,7 +151,8 @@ pub fn get_current_user(conn: &db::PostgresConnection, id: &i32) -> Result<UserW
     let mut user: UserWithPreference = UserWithPreference{..Default::default()};
     let default_menu = &env::CONFIG.team_menu;
     let default_theme = &env::CONFIG.team_theme;
-    for row in &conn.query("SELECT u.id, u.username, u.icon_url, COALESCE(p.menu, $2) as menu, COALESCE(p.theme, $3) as theme from users as u left join preferences as p on u.id=p.user_id where u.id = $1", &[&id, &default_menu, &default_theme]).unwrap() {
+    for row in &conn.query("SELECT u.id, u.username, u.icon_url, u.role, COALESCE(p.menu, $2) as menu, COALESCE(p.theme, $3) as theme from users as u left join preferences as p on u.id=p.user_id where u.id = $1 and u.deactivated = false", &[&id, &default_menu, &default_theme]).unwrap() {
+        let role: String = row.get("role");
         user = UserWithPreference {
             id: row.get("id"),
             username: row.get("username"),
@@ -91,6 +160,10 @@ pub fn get_current_user(conn: &db::PostgresConnection, id: &i32) -> Result<UserW
             username_hash: helper::username_hash(row.get("username")),
             menu: helper::split_menu(row.get("menu")),
             theme: row.get("theme"),
+            csrf_token: "".to_string(),
+            is_admin: role == ROLE_ADMIN,
+            is_guest: role == ROLE_GUEST,
+            role: role,
         };
     }
     Ok(user)
@@ -130,17 +203,32 @@ pub fn update_username(conn: &db::PostgresConnection, id: &i32, username: &Strin
 
 pub fn get_by_username(conn: &db::PostgresConnection, username: &str) -> Result<User, Error> {
     let mut user: User = User{..Default::default()};
-    for row in &conn.query("SELECT id, username, icon_url from users where username = $1", &[&username]).unwrap() {
+    for row in &conn.query("SELECT id, username, icon_url, deactivated from users where username = $1", &[&username]).unwrap() {
         user = User {
             id: row.get("id"),
             username: row.get("username"),
             icon_url: row.get("icon_url"),
             username_hash: helper::username_hash(row.get("username")),
+            deactivated: row.get("deactivated"),
         };
     }
     Ok(user)
 }
 
+// Returns `username`, or `username` with a numeric suffix if it is already taken.
+pub fn available_username(conn: &db::PostgresConnection, username: &str) -> Result<String, Error> {
+    let mut candidate = username.to_string();
+    let mut n = 1;
+    loop {
+        let rows = try!(conn.query("SELECT 1 from users where username = $1", &[&candidate]));
+        if rows.len() == 0 {
+            return Ok(candidate);
+        }
+        n += 1;
+        candidate = format!("{}{}", username, n);
+    }
+}
+
 pub fn get_by_email(conn: &db::PostgresConnection, email: &str) -> Result<UserWithEmail, Error> {
     let mut user: UserWithEmail = UserWithEmail{..Default::default()};
     for row in &conn.query("SELECT id, username, icon_url, email from users where email = $1", &[&email]).unwrap() {
@@ -155,6 +243,20 @@ pub fn get_by_email(conn: &db::PostgresConnection, email: &str) -> Result<UserWi
     Ok(user)
 }
 
+pub fn get_with_email_by_id(conn: &db::PostgresConnection, id: &i32) -> Result<UserWithEmail, Error> {
+    let mut user: UserWithEmail = UserWithEmail{..Default::default()};
+    for row in &try!(conn.query("SELECT id, username, icon_url, email from users where id = $1", &[&id])) {
+        user = UserWithEmail {
+            id: row.get("id"),
+            username: row.get("username"),
+            icon_url: row.get("icon_url"),
+            email: row.get("email"),
+            username_hash: helper::username_hash(row.get("username")),
+        };
+    }
+    Ok(user)
+}
+
 pub fn set_preference_menu(conn: &db::PostgresConnection, user_id: &i32, menu: &String) -> Result<(), Error>{
     conn.execute(
         "update preferences set menu=$2 where user_id=$1", &[&user_id, &menu]
@@ -176,3 +278,87 @@ pub fn set_preference_theme(conn: &db::PostgresConnection, user_id: &i32, theme:
         where not exists (select 1 from preferences where user_id=$1)", &[&user_id, &theme]
     ).map(|_| ())
 }
+
+pub fn list_with_role(conn: &db::PostgresConnection) -> Result<Vec<UserWithRole>, Error> {
+    let mut users: Vec<UserWithRole> = Vec::new();
+    for row in &try!(conn.query("SELECT id, username, icon_url, email, role, auth_source, totp_enabled, deactivated from users order by id", &[])) {
+        users.push(UserWithRole {
+            id: row.get("id"),
+            username: row.get("username"),
+            icon_url: row.get("icon_url"),
+            email: row.get("email"),
+            username_hash: helper::username_hash(row.get("username")),
+            role: row.get("role"),
+            auth_source: row.get("auth_source"),
+            totp_enabled: row.get("totp_enabled"),
+            deactivated: row.get("deactivated"),
+        });
+    }
+    Ok(users)
+}
+
+/// The account's role, or an empty string when there is no such user.
+pub fn get_role(conn: &db::PostgresConnection, id: &i32) -> Result<String, Error> {
+    let rows = try!(conn.query("SELECT role from users where id = $1", &[&id]));
+    Ok(rows.iter().next().map(|row| row.get("role")).unwrap_or("".to_string()))
+}
+
+pub fn update_role(conn: &db::PostgresConnection, id: &i32, role: &str) -> Result<(), Error> {
+    conn.execute(
+        "UPDATE users set role = $2, updated = now() WHERE id = $1", &[&id, &role]
+    ).map(|_| ())
+}
+
+pub fn admin_count(conn: &db::PostgresConnection) -> Result<i32, Error> {
+    let rows = try!(conn.query("SELECT count(*)::int as count from users where role = $1 and deactivated = false", &[&ROLE_ADMIN]));
+    let row = rows.get(0);
+    Ok(row.get("count"))
+}
+
+pub fn is_deactivated(conn: &db::PostgresConnection, id: &i32) -> Result<bool, Error> {
+    let rows = try!(conn.query("SELECT deactivated from users where id = $1", &[&id]));
+    Ok(rows.iter().next().map(|row| row.get("deactivated")).unwrap_or(false))
+}
+
+pub fn set_deactivated(conn: &db::PostgresConnection, id: &i32, deactivated: &bool) -> Result<(), Error> {
+    conn.execute(
+        "UPDATE users set deactivated = $2, updated = now() WHERE id = $1", &[&id, &deactivated]
+    ).map(|_| ())
+}
+
+pub fn get_password(conn: &db::PostgresConnection, id: &i32) -> Result<Option<String>, Error> {
+    let rows = try!(conn.query("SELECT password from users where id = $1", &[&id]));
+    Ok(rows.iter().next().and_then(|row| row.get("password")))
+}
+
+/// Moves a deactivated account's published, non-private posts and gists to another member,
+/// all or nothing. Drafts and private items, which nobody else may read, stay with the
+/// original owner. Returns how many posts and gists moved.
+pub fn transfer_content(conn: &db::PostgresConnection, from_user_id: &i32, to_user_id: &i32) -> Result<(u64, u64), Error> {
+    let trans = try!(conn.transaction());
+    let posts = try!(models::post::transfer_owner(&trans, from_user_id, to_user_id));
+    let gists = try!(models::gist::transfer_owner(&trans, from_user_id, to_user_id));
+    try!(trans.commit());
+    Ok((posts, gists))
+}
+
+/// Deletes an account without deleting what it wrote: published posts, gists,
+/// tweets and comments stay under a placeholder name, while credentials,
+/// private data, drafts and private posts and gists are removed.
+pub fn anonymize(conn: &db::PostgresConnection, id: &i32) -> Result<(), Error> {
+    let trans = try!(conn.transaction());
+    try!(trans.execute("DELETE FROM login_failures WHERE username = (SELECT lower(username) FROM users WHERE id = $1)", &[&id]));
+    for table in &["preferences", "stocks", "gist_stars", "recovery_codes", "sessions", "invites", "password_resets", "follows", "tag_follows", "api_tokens"] {
+        try!(trans.execute(&format!("DELETE FROM {} WHERE user_id = $1", table), &[&id]));
+    }
+    try!(trans.execute("DELETE FROM notifications WHERE to_user = $1", &[&id]));
+    try!(trans.execute("DELETE FROM follows WHERE followee_id = $1", &[&id]));
+    try!(trans.execute("DELETE FROM posts WHERE user_id = $1 and (status = 'draft' or visibility = 'private')", &[&id]));
+    try!(trans.execute("DELETE FROM gists WHERE user_id = $1 and visibility = 'private'", &[&id]));
+    try!(trans.execute("
+        UPDATE users set username = 'former-member-' || id, password = NULL, email = NULL, icon_url = NULL,
+        totp_secret = NULL, totp_enabled = false, role = $2, deactivated = true,
+        display_name = '', bio = '', title = '', team = '', links = '', updated = now()
+        WHERE id = $1", &[&id, &ROLE_GUEST]));
+    trans.commit()
+}
@@ -0,0 +1,236 @@
+use std::sync::RwLock;
+
+use base64;
+use reqwest;
+use serde_json;
+use serde_json::Value;
+use time;
+use url::Url;
+
+use env::CONFIG;
+use helper;
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Discovery {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    #[serde(default)]
+    pub userinfo_endpoint: String,
+}
+
+#[derive(Deserialize, Default, Debug)]
+pub struct Token {
+    pub access_token: String,
+    #[serde(default)]
+    pub id_token: String,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct Identity {
+    pub username: String,
+    pub email: String,
+    pub avatar_url: String,
+}
+
+lazy_static!{
+static ref DISCOVERY: RwLock<Option<Discovery>> = RwLock::new(None);
+}
+
+pub fn enabled() -> bool {
+    CONFIG.team_oidc_discovery_url != "" && CONFIG.team_oidc_client_id != ""
+}
+
+pub fn discovery() -> Result<Discovery, String> {
+    if let Some(ref d) = *DISCOVERY.read().unwrap() {
+        return Ok(d.clone());
+    }
+    let mut res = try!(reqwest::get(CONFIG.team_oidc_discovery_url.as_str()).map_err(|e| e.to_string()));
+    if !res.status().is_success() {
+        return Err(format!("discovery returned {}", res.status()));
+    }
+    let d = try!(res.json::<Discovery>().map_err(|e| e.to_string()));
+    *DISCOVERY.write().unwrap() = Some(d.clone());
+    Ok(d)
+}
+
+pub fn authorize_url(state: &str, code_verifier: &str, nonce: &str) -> Result<Url, String> {
+    let d = try!(discovery());
+    let mut url = try!(Url::parse(&d.authorization_endpoint).map_err(|e| e.to_string()));
+    url.query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &CONFIG.team_oidc_client_id)
+        .append_pair("redirect_uri", &CONFIG.team_oidc_redirect_url)
+        .append_pair("scope", &CONFIG.team_oidc_scopes)
+        .append_pair("state", state)
+        .append_pair("nonce", nonce)
+        .append_pair("code_challenge", &helper::pkce_challenge(code_verifier))
+        .append_pair("code_challenge_method", "S256");
+    Ok(url)
+}
+
+pub fn exchange_code(code: &str, code_verifier: &str) -> Result<Token, String> {
+    let d = try!(discovery());
+    let params = [
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("code_verifier", code_verifier),
+        ("client_id", CONFIG.team_oidc_client_id.as_str()),
+        ("client_secret", CONFIG.team_oidc_client_secret.as_str()),
+        ("redirect_uri", CONFIG.team_oidc_redirect_url.as_str()),
+    ];
+    let client = try!(reqwest::Client::new().map_err(|e| e.to_string()));
+    let mut res = try!(client.post(d.token_endpoint.as_str()).form(&params).send().map_err(|e| e.to_string()));
+    if !res.status().is_success() {
+        return Err(format!("token endpoint returned {}", res.status()));
+    }
+    res.json::<Token>().map_err(|e| e.to_string())
+}
+
+// The id_token comes straight from the token endpoint over TLS, so its claims
+// are checked here but its signature is not (OIDC Core 3.1.3.7).
+fn id_token_claims(id_token: &str, nonce: &str) -> Result<Value, String> {
+    let d = try!(discovery());
+    let parts: Vec<&str> = id_token.split(".").collect();
+    if parts.len() != 3 {
+        return Err("malformed id_token".to_string());
+    }
+    let payload = try!(base64::decode_config(parts[1], base64::URL_SAFE_NO_PAD).map_err(|e| e.to_string()));
+    let claims: Value = try!(serde_json::from_slice(&payload).map_err(|e| e.to_string()));
+
+    if claims["iss"].as_str() != Some(d.issuer.as_str()) {
+        return Err("issuer mismatch".to_string());
+    }
+    let aud_ok = match claims["aud"] {
+        Value::String(ref aud) => aud == &CONFIG.team_oidc_client_id,
+        Value::Array(ref auds) => auds.iter().any(|a| a.as_str() == Some(CONFIG.team_oidc_client_id.as_str())),
+        _ => false,
+    };
+    if !aud_ok {
+        return Err("audience mismatch".to_string());
+    }
+    if claims["nonce"].as_str() != Some(nonce) {
+        return Err("nonce mismatch".to_string());
+    }
+    let exp = claims["exp"].as_i64().unwrap_or(0);
+    if exp < time::get_time().sec {
+        return Err("id_token expired".to_string());
+    }
+    Ok(claims)
+}
+
+fn userinfo(access_token: &str) -> Result<Value, String> {
+    let d = try!(discovery());
+    if d.userinfo_endpoint == "" {
+        return Ok(json!({}));
+    }
+    use reqwest::header::{Authorization, Bearer};
+    let client = try!(reqwest::Client::new().map_err(|e| e.to_string()));
+    let mut res = try!(client.get(d.userinfo_endpoint.as_str())
+        .header(Authorization(Bearer { token: access_token.to_string() }))
+        .send()
+        .map_err(|e| e.to_string()));
+    if !res.status().is_success() {
+        return Err(format!("userinfo returned {}", res.status()));
+    }
+    res.json::<Value>().map_err(|e| e.to_string())
+}
+
+fn claim(id_claims: &Value, info: &Value, name: &str) -> String {
+    if name == "" {
+        return "".to_string();
+    }
+    info[name].as_str()
+        .or(id_claims[name].as_str())
+        .unwrap_or("")
+        .to_string()
+}
+
+// The claim that says the provider verified the address in the email claim.
+// `email_verified` only vouches for `email`, so a remapped email claim needs its
+// own verified claim configured; without one no address counts as verified.
+fn verified_claim<'a>(email_claim: &'a str, email_verified_claim: &'a str) -> &'a str {
+    if email_verified_claim != "" {
+        email_verified_claim
+    } else if email_claim == "email" {
+        "email_verified"
+    } else {
+        ""
+    }
+}
+
+// Providers send the verified claim as a boolean, though some send the string "true".
+fn email_verified(id_claims: &Value, info: &Value, name: &str) -> bool {
+    if name == "" {
+        return false;
+    }
+    let verified = if info[name].is_null() { &id_claims[name] } else { &info[name] };
+    match *verified {
+        Value::Bool(verified) => verified,
+        Value::String(ref verified) => verified == "true",
+        _ => false,
+    }
+}
+
+/// The signed-in user. Accounts are matched by email, so an address the provider
+/// has not verified is refused, as it is for Google.
+pub fn identity(token: &Token, nonce: &str) -> Result<Identity, String> {
+    let id_claims = try!(id_token_claims(&token.id_token, nonce));
+    let info = try!(userinfo(&token.access_token));
+
+    let verified = verified_claim(&CONFIG.team_oidc_email_claim, &CONFIG.team_oidc_email_verified_claim);
+    if !email_verified(&id_claims, &info, verified) {
+        return Err("provider did not verify the email".to_string());
+    }
+    let email = claim(&id_claims, &info, &CONFIG.team_oidc_email_claim);
+    let mut username = claim(&id_claims, &info, &CONFIG.team_oidc_username_claim);
+    if username == "" {
+        username = email.split("@").next().unwrap_or("").to_string();
+    }
+    if email == "" || username == "" {
+        return Err("provider did not return an email and username".to_string());
+    }
+    Ok(Identity {
+        username: username,
+        email: email,
+        avatar_url: claim(&id_claims, &info, &CONFIG.team_oidc_avatar_claim),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{email_verified, verified_claim};
+
+    #[test]
+    fn email_verified_vouches_only_for_email() {
+        assert_eq!(verified_claim("email", ""), "email_verified");
+        assert_eq!(verified_claim("upn", ""), "");
+        assert_eq!(verified_claim("upn", "upn_verified"), "upn_verified");
+        assert_eq!(verified_claim("email", "mail_checked"), "mail_checked");
+    }
+
+    #[test]
+    fn verified_accepts_true_and_the_string_true() {
+        assert!(email_verified(&json!({"email_verified": true}), &json!({}), "email_verified"));
+        assert!(email_verified(&json!({}), &json!({"email_verified": "true"}), "email_verified"));
+    }
+
+    #[test]
+    fn verified_refuses_false_missing_and_other_values() {
+        assert!(!email_verified(&json!({"email_verified": false}), &json!({}), "email_verified"));
+        assert!(!email_verified(&json!({}), &json!({}), "email_verified"));
+        assert!(!email_verified(&json!({"email_verified": 1}), &json!({}), "email_verified"));
+        assert!(!email_verified(&json!({"email_verified": "yes"}), &json!({}), "email_verified"));
+    }
+
+    #[test]
+    fn userinfo_overrides_the_id_token() {
+        assert!(!email_verified(&json!({"email_verified": true}), &json!({"email_verified": false}), "email_verified"));
+        assert!(email_verified(&json!({"email_verified": false}), &json!({"email_verified": true}), "email_verified"));
+    }
+
+    #[test]
+    fn no_verified_claim_means_unverified() {
+        assert!(!email_verified(&json!({"email_verified": true}), &json!({"email_verified": true}), ""));
+    }
+}
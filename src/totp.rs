@@ -0,0 +1,200 @@
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha1::Sha1;
+use qrcode::QrCode;
+use rand::{Rng, OsRng};
+use time;
+use url::percent_encoding::{utf8_percent_encode, DEFAULT_ENCODE_SET};
+
+const ISSUER: &str = "Team";
+const STEP: i64 = 30;
+const DIGITS: u32 = 6;
+const BASE32_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+// RFC 4648 base32 without padding
+fn base32_encode(data: &[u8]) -> String {
+    let mut out = String::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for &byte in data {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            out.push(BASE32_ALPHABET[((buffer >> (bits - 5)) & 0x1f) as usize] as char);
+            bits -= 5;
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    out
+}
+
+fn base32_decode(data: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in data.chars().filter(|c| *c != '=' && *c != ' ') {
+        let c = c.to_ascii_uppercase() as u8;
+        let value = match BASE32_ALPHABET.iter().position(|&a| a == c) {
+            Some(v) => v as u32,
+            None => return None,
+        };
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            out.push(((buffer >> (bits - 8)) & 0xff) as u8);
+            bits -= 8;
+        }
+    }
+    Some(out)
+}
+
+pub fn generate_secret() -> String {
+    let mut rng = OsRng::new().unwrap();
+    let bytes: Vec<u8> = (0..20).map(|_| rng.gen::<u8>()).collect();
+    base32_encode(&bytes)
+}
+
+fn code_at(key: &[u8], counter: u64) -> u32 {
+    let mut message = [0u8; 8];
+    for i in 0..8 {
+        message[i] = (counter >> (56 - i * 8)) as u8;
+    }
+    let mut hmac = Hmac::new(Sha1::new(), key);
+    hmac.input(&message);
+    let result = hmac.result();
+    let digest = result.code();
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = ((digest[offset] as u32 & 0x7f) << 24)
+        | ((digest[offset + 1] as u32) << 16)
+        | ((digest[offset + 2] as u32) << 8)
+        | (digest[offset + 3] as u32);
+    binary % 10u32.pow(DIGITS)
+}
+
+/// Checks a 6 digit code, allowing one step of clock drift either way, and returns
+/// the time step it belongs to so the caller can refuse it a second time.
+pub fn verify(secret: &str, code: &str) -> Option<i64> {
+    verify_at(secret, code, time::get_time().sec)
+}
+
+fn verify_at(secret: &str, code: &str, now: i64) -> Option<i64> {
+    let code = code.trim().replace(" ", "");
+    if code.len() != DIGITS as usize {
+        return None;
+    }
+    let code = match code.parse::<u32>() {
+        Ok(c) => c,
+        Err(_) => return None,
+    };
+    let key = match base32_decode(secret) {
+        Some(k) => k,
+        None => return None,
+    };
+    let step = now / STEP;
+    for drift in -1..2 {
+        if code_at(&key, (step + drift) as u64) == code {
+            return Some(step + drift);
+        }
+    }
+    None
+}
+
+pub fn provisioning_uri(username: &str, secret: &str) -> String {
+    let label = utf8_percent_encode(&format!("{}:{}", ISSUER, username), DEFAULT_ENCODE_SET).to_string();
+    format!("otpauth://totp/{}?secret={}&issuer={}", label, secret, ISSUER)
+}
+
+pub fn qr_svg(data: &str) -> String {
+    let code = match QrCode::new(data.as_bytes()) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("qrcode: {:?}", e);
+            return "".to_string();
+        }
+    };
+    let width = code.width();
+    let quiet = 4;
+    let size = width + quiet * 2;
+    let mut svg = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {} {}\" width=\"200\" height=\"200\" shape-rendering=\"crispEdges\"><rect width=\"{}\" height=\"{}\" fill=\"#fff\"/><path fill=\"#000\" d=\"", size, size, size, size);
+    for (i, dark) in code.to_vec().iter().enumerate() {
+        if *dark {
+            svg.push_str(&format!("M{},{}h1v1h-1z", i % width + quiet, i / width + quiet));
+        }
+    }
+    svg.push_str("\"/></svg>");
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{base32_decode, base32_encode, code_at, verify_at};
+
+    // RFC 4226 and RFC 6238 use the ASCII key "12345678901234567890".
+    const KEY: &[u8] = b"12345678901234567890";
+    const SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    #[test]
+    fn base32_decodes_rfc4648_vectors() {
+        assert_eq!(base32_decode("").unwrap(), b"");
+        assert_eq!(base32_decode("MY======").unwrap(), b"f");
+        assert_eq!(base32_decode("MZXQ====").unwrap(), b"fo");
+        assert_eq!(base32_decode("MZXW6===").unwrap(), b"foo");
+        assert_eq!(base32_decode("MZXW6YQ=").unwrap(), b"foob");
+        assert_eq!(base32_decode("MZXW6YTB").unwrap(), b"fooba");
+        assert_eq!(base32_decode("MZXW6YTBOI======").unwrap(), b"foobar");
+    }
+
+    #[test]
+    fn base32_decode_ignores_case_and_spaces() {
+        assert_eq!(base32_decode("mzxw 6ytb oi").unwrap(), b"foobar");
+    }
+
+    #[test]
+    fn base32_decode_refuses_other_characters() {
+        assert!(base32_decode("MZXW1").is_none());
+        assert!(base32_decode("MZXW8").is_none());
+    }
+
+    #[test]
+    fn base32_round_trips() {
+        assert_eq!(base32_encode(KEY), SECRET);
+        assert_eq!(base32_decode(&base32_encode(KEY)).unwrap(), KEY);
+    }
+
+    #[test]
+    fn hotp_matches_rfc4226() {
+        let expected = [755224, 287082, 359152, 969429, 338314, 254676, 287922, 162583, 399871, 520489];
+        for (counter, code) in expected.iter().enumerate() {
+            assert_eq!(code_at(KEY, counter as u64), *code);
+        }
+    }
+
+    // RFC 6238 lists 8 digit SHA-1 codes; the 6 digit codes are their last six digits.
+    #[test]
+    fn totp_matches_rfc6238() {
+        assert_eq!(verify_at(SECRET, "287082", 59), Some(1));
+        assert_eq!(verify_at(SECRET, "081804", 1111111109), Some(37037036));
+        assert_eq!(verify_at(SECRET, "050471", 1111111111), Some(37037037));
+        assert_eq!(verify_at(SECRET, "005924", 1234567890), Some(41152263));
+        assert_eq!(verify_at(SECRET, "279037", 2000000000), Some(66666666));
+        assert_eq!(verify_at(SECRET, "353130", 20000000000), Some(666666666));
+    }
+
+    #[test]
+    fn totp_allows_one_step_of_drift() {
+        assert_eq!(verify_at(SECRET, "287082", 59 + 30), Some(1));
+        assert_eq!(verify_at(SECRET, "287082", 59 - 30), Some(1));
+        assert_eq!(verify_at(SECRET, "287082", 59 + 60), None);
+    }
+
+    #[test]
+    fn totp_refuses_malformed_codes() {
+        assert_eq!(verify_at(SECRET, "28708", 59), None);
+        assert_eq!(verify_at(SECRET, "2870822", 59), None);
+        assert_eq!(verify_at(SECRET, "28a082", 59), None);
+        assert_eq!(verify_at(SECRET, "287 082", 59), Some(1));
+        assert_eq!(verify_at("not base32!", "287082", 59), None);
+    }
+}